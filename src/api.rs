@@ -0,0 +1,485 @@
+// JSON API of the bookmarks served by `bkm serve`.
+
+use serde_json::{self, Value};
+use std::sync::Mutex;
+
+use backup;
+use bookmark::Bookmark;
+use database::DB;
+use http::{Request, Response};
+use normalize::{normalize_url, placeholder_title};
+use oplog::{merge_ops, Op};
+use query::{ListOptions, Query, SortKey};
+use utils::{bookmarks_to_html, bookmarks_to_json, parse_bookmarks_html, parse_bookmarks_json,
+            parse_icons_html};
+use web;
+
+// Fields of a bookmark sent to create or change it, missing ones are left as they are.
+#[derive(Deserialize, Default)]
+pub struct BookmarkInput {
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub description: Option<String>,
+    pub notes: Option<String>
+}
+
+pub struct Api {
+    // SQLite connections can be shared between threads, but not used at once.
+    db: Mutex<DB>,
+    token: String
+}
+
+impl Api {
+    pub fn new(db: DB, token: &str) -> Api {
+        Api { db: Mutex::new(db), token: token.to_string() }
+    }
+
+    pub fn handle(&self, req: &Request) -> Response {
+        // Pages authenticate with a cookie set by their login form.
+        let page = web::is_page(req);
+        let authorized = req.header("Authorization")
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .is_some_and(|t| t.trim() == self.token);
+        if !page && !authorized {
+            return Response::error(401, "Missing or invalid token")
+                .header("WWW-Authenticate", "Bearer");
+        }
+
+        // A handler panicking must not keep others from the database.
+        let db = self.db.lock().unwrap_or_else(|e| e.into_inner());
+        // Every request is undone on its own.
+        db.begin_step(&format!("serve {} {}", req.method, req.path));
+
+        let res = db.request(|| if page {
+            web::handle(&db, req, &self.token)
+        } else {
+            route(&db, req)
+        });
+        res.unwrap_or_else(|e| Response::error(500, &e))
+    }
+}
+
+fn route(db: &DB, req: &Request) -> Response {
+    match (req.method.as_str(), req.segments().as_slice()) {
+        ("GET", ["bookmarks"]) => list(db, req),
+        ("POST", ["bookmarks"]) => create(db, req),
+        ("GET", ["bookmarks", id]) => with_id(id, |id| get(db, id)),
+        ("PUT", ["bookmarks", id]) | ("PATCH", ["bookmarks", id]) => {
+            with_id(id, |id| update(db, id, req))
+        },
+        ("DELETE", ["bookmarks", id]) => with_id(id, |id| delete(db, id)),
+        ("GET", ["tags"]) => tags(db),
+        ("DELETE", ["tags", name]) => delete_tag(db, name),
+        ("GET", ["export"]) => export(db, req),
+        ("POST", ["import"]) => import(db, req),
+        ("GET", ["sync", "ops"]) => Response::json(200, &json!(db.get_ops())),
+        ("POST", ["sync", "ops"]) => merge(db, req),
+        (_, ["bookmarks"]) | (_, ["bookmarks", _]) | (_, ["tags"]) | (_, ["tags", _])
+        | (_, ["export"]) | (_, ["import"]) | (_, ["sync", "ops"]) => {
+            Response::error(405, "Method not allowed")
+        },
+        _ => Response::error(404, "Not found"),
+    }
+}
+
+// Status code of an error returned by DB.
+pub fn error_status(error: &str) -> u16 {
+    match error {
+        "Error: Did not match index" => 404,
+        "Error: URL already exists" => 409,
+        _ => 400,
+    }
+}
+
+fn db_error(error: &str) -> Response {
+    Response::error(error_status(error), error)
+}
+
+fn with_id<F>(id: &str, f: F) -> Response
+    where F: FnOnce(i64) -> Response
+{
+    match id.parse() {
+        Ok(id) => f(id),
+        Err(_) => Response::error(404, "Not found"),
+    }
+}
+
+fn to_json(bookmark: &Bookmark) -> Value {
+    serde_json::to_value(bookmark).unwrap()
+}
+
+// GET /bookmarks?q=&content=&sort=&reverse=&limit=&offset=
+fn list(db: &DB, req: &Request) -> Response {
+    let mut query = Query::parse(req.param("q").unwrap_or(""));
+    query.content = req.param("content").is_some_and(|c| c == "true" || c == "1");
+
+    let sort: SortKey = match req.param("sort").unwrap_or("id").parse() {
+        Ok(sort) => sort,
+        Err(e) => return Response::error(400, &e),
+    };
+    let number = |name: &str| -> Result<Option<i64>, Response> {
+        match req.param(name) {
+            Some(n) => n.parse().map(Some)
+                .map_err(|_| Response::error(400, &format!("Invalid {} \"{}\"", name, n))),
+            None => Ok(None),
+        }
+    };
+    let (limit, offset) = match (number("limit"), number("offset")) {
+        (Ok(limit), Ok(offset)) => (limit, offset),
+        (Err(res), _) | (_, Err(res)) => return res,
+    };
+    let reverse = req.param("reverse").is_some_and(|r| r == "true" || r == "1");
+
+    let options = ListOptions::new(sort, reverse, limit, offset);
+    let bookmarks = db.search_query(&query, &options);
+
+    Response::json(200, &Value::Array(bookmarks.iter().map(to_json).collect()))
+}
+
+fn parse_input(req: &Request) -> Result<BookmarkInput, Response> {
+    serde_json::from_slice(&req.body)
+        .map_err(|e| Response::error(400, &format!("Invalid bookmark: {}", e)))
+}
+
+// Add the bookmark of input, titled after its URL when it has no title
+// until `bkm refresh --pending` fetches its page.
+pub fn create_bookmark(db: &DB, input: BookmarkInput) -> Result<i64, &str> {
+    let url = match input.url {
+        Some(ref url) if !url.trim().is_empty() => normalize_url(url),
+        _ => return Err("Error: Missing url"),
+    };
+
+    let title = input.title.filter(|t| !t.trim().is_empty());
+    let mut bookmark = Bookmark::new(
+        0,
+        title.clone().unwrap_or_else(|| placeholder_title(&url)),
+        url,
+        input.tags.unwrap_or_default(),
+    );
+    bookmark.description = input.description.unwrap_or_default();
+    bookmark.notes = input.notes.unwrap_or_default();
+
+    let id = db.insert_bookmark(&bookmark)?;
+    if title.is_none() {
+        db.set_needs_metadata(id, true);
+    }
+
+    Ok(id)
+}
+
+// Change the fields of bookmark id given in input.
+pub fn change_bookmark(db: &DB, id: i64, input: BookmarkInput) -> Result<(), &str> {
+    let bookmark = db.get_bookmark_by_id(id)?;
+    if input.url.as_ref().is_some_and(|u| u.trim().is_empty()) {
+        return Err("Error: Missing url");
+    }
+
+    if input.title.is_some() || input.url.is_some() {
+        let title = input.title.unwrap_or(bookmark.title);
+        let url = input.url.map(|u| normalize_url(&u)).unwrap_or(bookmark.url);
+        db.update_bookmark(id, &title, &url)?;
+    }
+    if let Some(tags) = input.tags {
+        db.set_tags(id, &tags);
+    }
+    if let Some(description) = input.description {
+        db.update_description(id, &description);
+    }
+    if let Some(notes) = input.notes {
+        db.update_notes(id, &notes);
+    }
+
+    Ok(())
+}
+
+// POST /bookmarks
+fn create(db: &DB, req: &Request) -> Response {
+    let input = match parse_input(req) {
+        Ok(input) => input,
+        Err(res) => return res,
+    };
+
+    match create_bookmark(db, input) {
+        Ok(id) => {
+            let bookmark = db.get_bookmark_by_id(id).unwrap();
+            Response::json(201, &to_json(&bookmark))
+                .header("Location", &format!("/bookmarks/{}", id))
+        },
+        Err(e) => db_error(e),
+    }
+}
+
+// GET /bookmarks/<id>
+fn get(db: &DB, id: i64) -> Response {
+    match db.get_bookmark_by_id(id) {
+        Ok(bookmark) => Response::json(200, &to_json(&bookmark)),
+        Err(e) => db_error(e),
+    }
+}
+
+// PUT or PATCH /bookmarks/<id>, changing the fields sent only.
+fn update(db: &DB, id: i64, req: &Request) -> Response {
+    let input = match parse_input(req) {
+        Ok(input) => input,
+        Err(res) => return res,
+    };
+
+    match change_bookmark(db, id, input) {
+        Ok(()) => get(db, id),
+        Err(e) => db_error(e),
+    }
+}
+
+// Delete bookmark id with its offline copies.
+pub fn remove_bookmark(db: &DB, id: i64) -> Result<(), &str> {
+    db.get_bookmark_by_id(id)?;
+    // Snapshots stay until the bookmark leaves the trash.
+    db.delete_bookmark(id);
+
+    Ok(())
+}
+
+// DELETE /bookmarks/<id>
+fn delete(db: &DB, id: i64) -> Response {
+    match remove_bookmark(db, id) {
+        Ok(()) => Response::empty(204),
+        Err(e) => db_error(e),
+    }
+}
+
+// GET /tags, [{"name": ..., "count": ...}] by name.
+fn tags(db: &DB) -> Response {
+    let tags: Vec<Value> = db.get_tag_counts().into_iter()
+        .map(|(name, count)| json!({ "name": name, "count": count }))
+        .collect();
+
+    Response::json(200, &Value::Array(tags))
+}
+
+// DELETE /tags/<name>, untagging every bookmark.
+fn delete_tag(db: &DB, name: &str) -> Response {
+    if db.check_existence_tag(name) == 0 {
+        return Response::error(404, &format!("No tag matching \"{}\"", name));
+    }
+    db.delete_tag(name);
+
+    Response::empty(204)
+}
+
+// GET /export?format=json|html
+fn export(db: &DB, req: &Request) -> Response {
+    let bookmarks = db.get_all_bookmark(&ListOptions::default());
+
+    match req.param("format").unwrap_or("json") {
+        "json" => Response::new(200, "application/json", bookmarks_to_json(&bookmarks).into_bytes()),
+        "html" => {
+            let html = bookmarks_to_html(&bookmarks, &db.get_all_favicons());
+            Response::new(200, "text/html; charset=utf-8", html.into_bytes())
+        },
+        format => Response::error(400, &format!("Unknown format \"{}\"", format)),
+    }
+}
+
+// POST /import?format=json|html with the exported file as body, answering
+// the ids of the imported bookmarks and the URLs which failed.
+fn import(db: &DB, req: &Request) -> Response {
+    let body = String::from_utf8_lossy(&req.body);
+
+    let bookmarks = match req.param("format").unwrap_or("json") {
+        "json" => match parse_bookmarks_json(&body) {
+            Ok(bookmarks) => bookmarks,
+            Err(e) => return Response::error(400, &format!("Invalid bookmarks: {}", e)),
+        },
+        "html" => {
+            db.import_favicons(&parse_icons_html(&body));
+            parse_bookmarks_html(&body)
+        },
+        format => return Response::error(400, &format!("Unknown format \"{}\"", format)),
+    };
+
+    // Backed up first like by bkm import, but for libraries in memory, those of tests.
+    if !db.is_in_memory() {
+        if let Err(e) = backup::create(db, "import") {
+            return Response::error(500, &e);
+        }
+    }
+
+    let mut imported: Vec<i64> = Vec::new();
+    let mut failed: Vec<Value> = Vec::new();
+    for mut bookmark in bookmarks {
+        bookmark.url = normalize_url(&bookmark.url);
+        match db.insert_bookmark(&bookmark) {
+            Ok(id) => imported.push(id),
+            Err(e) => failed.push(json!({ "url": bookmark.url, "error": e.trim_start_matches("Error: ") })),
+        }
+    }
+
+    Response::json(200, &json!({ "imported": imported, "failed": failed }))
+}
+
+// POST /sync/ops with the operation log of a device, answering the changes
+// its new operations made here.
+fn merge(db: &DB, req: &Request) -> Response {
+    let ops: Vec<Op> = match serde_json::from_slice(&req.body) {
+        Ok(ops) => ops,
+        Err(e) => return Response::error(400, &format!("Invalid operations: {}", e)),
+    };
+
+    let (new, report) = merge_ops(db, &ops);
+    Response::json(200, &json!({
+        "operations": new,
+        "added": report.added,
+        "updated": report.updated,
+        "deleted": report.deleted,
+        "conflicts": report.conflicts,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::serve;
+    use reqwest::{Client, Method, StatusCode};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    const TOKEN: &str = "secret";
+
+    // Serve an empty database, answering its base URL.
+    fn start() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let api = Arc::new(Api::new(DB::open_in_memory(), TOKEN));
+        thread::spawn(move || serve(listener, move |req| api.handle(req)));
+
+        format!("http://{}", addr)
+    }
+
+    fn send(base: &str, method: Method, path: &str, body: Option<&str>) -> (StatusCode, Value) {
+        let mut req = Client::new().request(method, &format!("{}{}", base, path))
+            .header("Authorization", format!("Bearer {}", TOKEN));
+        if let Some(body) = body {
+            req = req.body(body.to_string());
+        }
+
+        let mut res = req.send().unwrap();
+        let text = res.text().unwrap();
+        (res.status(), serde_json::from_str(&text).unwrap_or(Value::Null))
+    }
+
+    #[test]
+    fn test_auth() {
+        let base = start();
+
+        let res = Client::new().get(&format!("{}/bookmarks", base)).send().unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = Client::new().get(&format!("{}/bookmarks", base))
+            .header("Authorization", "Bearer wrong").send().unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        assert_eq!(send(&base, Method::GET, "/bookmarks", None).0, StatusCode::OK);
+        assert_eq!(send(&base, Method::GET, "/nothing", None).0, StatusCode::NOT_FOUND);
+        assert_eq!(send(&base, Method::POST, "/tags", None).0, StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn test_crud() {
+        let base = start();
+
+        let body = r#"{"title": "GitHub", "url": "https://github.com/?utm_source=x", "tags": ["git"]}"#;
+        let (status, bookmark) = send(&base, Method::POST, "/bookmarks", Some(body));
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(bookmark["id"], 1);
+        assert_eq!(bookmark["url"], "https://github.com/");
+        assert_eq!(bookmark["tags"], json!(["git"]));
+
+        let (status, _) = send(&base, Method::POST, "/bookmarks", Some(r#"{"url": "http://github.com"}"#));
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send(&base, Method::POST, "/bookmarks", Some(r#"{"title": "No URL"}"#));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&base, Method::POST, "/bookmarks", Some("not json"));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, bookmark) = send(&base, Method::POST, "/bookmarks",
+                                      Some(r#"{"url": "https://www.rust-lang.org/learn"}"#));
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(bookmark["title"], "www.rust-lang.org/learn");
+
+        let body = r#"{"description": "Hosting service", "tags": ["git", "hosting"]}"#;
+        let (status, bookmark) = send(&base, Method::PATCH, "/bookmarks/1", Some(body));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(bookmark["title"], "GitHub");
+        assert_eq!(bookmark["description"], "Hosting service");
+        assert_eq!(bookmark["tags"], json!(["git", "hosting"]));
+
+        let (status, _) = send(&base, Method::PATCH, "/bookmarks/2", Some(r#"{"url": "https://github.com"}"#));
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send(&base, Method::PATCH, "/bookmarks/2", Some(r#"{"url": " "}"#));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&base, Method::PATCH, "/bookmarks/9", Some("{}"));
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, bookmarks) = send(&base, Method::GET, "/bookmarks?q=tag:hosting", None);
+        assert_eq!(bookmarks.as_array().unwrap().len(), 1);
+        let (_, bookmarks) = send(&base, Method::GET, "/bookmarks?sort=title&limit=1", None);
+        assert_eq!(bookmarks[0]["id"], 1);
+        let (status, _) = send(&base, Method::GET, "/bookmarks?sort=nothing", None);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (_, tags) = send(&base, Method::GET, "/tags", None);
+        assert_eq!(tags, json!([{"name": "git", "count": 1}, {"name": "hosting", "count": 1}]));
+        assert_eq!(send(&base, Method::DELETE, "/tags/hosting", None).0, StatusCode::NO_CONTENT);
+        assert_eq!(send(&base, Method::DELETE, "/tags/hosting", None).0, StatusCode::NOT_FOUND);
+
+        assert_eq!(send(&base, Method::DELETE, "/bookmarks/1", None).0, StatusCode::NO_CONTENT);
+        assert_eq!(send(&base, Method::GET, "/bookmarks/1", None).0, StatusCode::NOT_FOUND);
+        assert_eq!(send(&base, Method::DELETE, "/bookmarks/1", None).0, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_import_export() {
+        let base = start();
+
+        let body = r#"[{"title": "GitHub", "url": "https://github.com", "tags": ["git"]},
+                       {"title": "Again", "url": "https://github.com/"}]"#;
+        let (status, result) = send(&base, Method::POST, "/import", Some(body));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["imported"], json!([1]));
+        assert_eq!(result["failed"][0]["error"], "URL already exists");
+
+        let html = "<DL><p><DT><A HREF=\"https://example.com\" TAGS=\"web\">Example</A></DL>";
+        let (_, result) = send(&base, Method::POST, "/import?format=html", Some(html));
+        assert_eq!(result["imported"], json!([2]));
+
+        let (status, bookmarks) = send(&base, Method::GET, "/export", None);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(bookmarks[1]["tags"], json!(["web"]));
+
+        let mut res = Client::new().get(&format!("{}/export?format=html", base))
+            .header("Authorization", format!("Bearer {}", TOKEN)).send().unwrap();
+        assert!(res.text().unwrap().contains("<A HREF=\"https://github.com/\" TAGS=\"git\">GitHub</A>"));
+    }
+
+    #[test]
+    fn test_sync_ops() {
+        use oplog::{pull, push};
+        use sync::records;
+
+        let base = start();
+        let (a, b) = (DB::open_in_memory(), DB::open_in_memory());
+        a.add_bookmark(&"GitHub".to_string(), &"https://github.com/".to_string()).unwrap();
+
+        assert!(push(&a, &base, Some("wrong")).is_err());
+        assert!(push(&a, &base, Some(TOKEN)).unwrap() > 0);
+        b.add_bookmark(&"Rust".to_string(), &"https://www.rust-lang.org/".to_string()).unwrap();
+        push(&b, &base, Some(TOKEN)).unwrap();
+
+        // The server relays the operations of every device.
+        assert_eq!(pull(&b, &base, Some(TOKEN)).unwrap().1.added, 1);
+        assert_eq!(pull(&a, &base, Some(TOKEN)).unwrap().1.added, 1);
+        assert_eq!(records(&a), records(&b));
+        assert_eq!(records(&a).len(), 2);
+    }
+}
@@ -0,0 +1,517 @@
+// Self-contained copies of pages: stylesheets, scripts, images and fonts are
+// inlined as data URIs, links are made absolute.
+
+use reqwest::Url;
+use std::collections::HashMap;
+use std::fmt;
+
+use fetch::{decode, Fetcher};
+use utils::data_uri;
+
+// How deep stylesheets importing stylesheets are followed.
+const MAX_CSS_DEPTH: usize = 3;
+
+pub struct Archive {
+    pub html: String,
+    pub resources: usize,
+    pub failed: usize
+}
+
+pub fn archive(fetcher: &Fetcher, url: &str) -> Result<Archive, String> {
+    let page = fetcher.get(url)?;
+    let html = decode(&page.body, page.content_type.as_deref());
+
+    let mut archiver = Archiver { fetcher, cache: HashMap::new(), resources: 0, failed: 0 };
+    let html = archiver.rewrite_html(&html, &page.url);
+
+    Ok(Archive { html, resources: archiver.resources, failed: archiver.failed })
+}
+
+struct Archiver<'a> {
+    fetcher: &'a Fetcher,
+    // Data URIs of resources by absolute URL, None for the ones failing.
+    cache: HashMap<String, Option<String>>,
+    resources: usize,
+    failed: usize
+}
+
+impl<'a> Archiver<'a> {
+    fn rewrite_html(&mut self, html: &str, url: &Url) -> String {
+        let mut base = url.clone();
+        let mut out = String::with_capacity(html.len());
+        let mut rest = html;
+
+        while let Some(start) = rest.find('<') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            // A lone < in text.
+            if !rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!') {
+                out.push('<');
+                rest = &rest[1..];
+                continue;
+            }
+
+            if rest.starts_with("<!--") {
+                let end = rest.find("-->").map_or(rest.len(), |e| e + 3);
+                out.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+
+            let end = tag_end(rest);
+            let mut tag = match Tag::parse(&rest[..end]) {
+                Some(tag) => tag,
+                None => {
+                    out.push_str(&rest[..end]);
+                    rest = &rest[end..];
+                    continue;
+                }
+            };
+            rest = &rest[end..];
+
+            if tag.name == "base" {
+                if let Some(href) = tag.attr("href") {
+                    if let Ok(url) = base.join(&href) {
+                        base = url;
+                    }
+                }
+                continue;
+            }
+
+            self.rewrite_tag(&mut tag, &base);
+            out.push_str(&tag.to_string());
+
+            // Contents of <style> and <script> are not HTML.
+            if tag.name == "style" || tag.name == "script" {
+                let end = find_ignore_case(rest, &format!("</{}", tag.name)).unwrap_or(rest.len());
+                if tag.name == "style" {
+                    out.push_str(&self.rewrite_css(&rest[..end], &base, 0));
+                } else {
+                    out.push_str(&rest[..end]);
+                }
+                rest = &rest[end..];
+            }
+        }
+
+        out.push_str(rest);
+        out
+    }
+
+    fn rewrite_tag(&mut self, tag: &mut Tag, base: &Url) {
+        let rel = tag.attr("rel").unwrap_or_default().to_lowercase();
+
+        match tag.name.as_str() {
+            "img" | "source" | "input" => {
+                tag.remove_attr("srcset");
+                self.inline_attr(tag, "src", base);
+            },
+            "script" | "audio" | "video" | "track" | "embed" => self.inline_attr(tag, "src", base),
+            "link" if rel.split_whitespace().any(|r| r == "stylesheet" || r.contains("icon")) => {
+                tag.remove_attr("integrity");
+                self.inline_attr(tag, "href", base);
+            },
+            "a" | "area" | "link" => absolute_attr(tag, "href", base),
+            "iframe" | "frame" => absolute_attr(tag, "src", base),
+            "form" => absolute_attr(tag, "action", base),
+            _ => {},
+        }
+
+        if tag.name == "script" {
+            tag.remove_attr("integrity");
+        }
+        if tag.name == "video" {
+            self.inline_attr(tag, "poster", base);
+        }
+        if let Some(style) = tag.attr("style") {
+            let style = self.rewrite_css(&style, base, 0);
+            tag.set_attr("style", &style);
+        }
+    }
+
+    // Replace attribute name with a data URI, or an absolute URL when
+    // downloading fails.
+    fn inline_attr(&mut self, tag: &mut Tag, name: &str, base: &Url) {
+        let value = match tag.attr(name) {
+            Some(value) => value,
+            None => return,
+        };
+        let url = match base.join(value.trim()) {
+            Ok(ref url) if url.scheme() == "http" || url.scheme() == "https" => url.clone(),
+            _ => return,
+        };
+
+        let data = self.inline(&url, 0).unwrap_or_else(|| url.to_string());
+        tag.set_attr(name, &data);
+    }
+
+    // Rewrite url() and @import of css into data URIs.
+    fn rewrite_css(&mut self, css: &str, base: &Url, depth: usize) -> String {
+        let mut out = String::with_capacity(css.len());
+        let mut rest = css;
+
+        loop {
+            let url_start = rest.find("url(");
+            let import_start = rest.find("@import");
+            let start = match (url_start, import_start) {
+                (Some(u), Some(i)) => u.min(i),
+                (Some(u), None) => u,
+                (None, Some(i)) => i,
+                (None, None) => break,
+            };
+
+            let (prefix, value, end) = if Some(start) == url_start {
+                match css_url(&rest[start..]) {
+                    Some((value, end)) => ("url(", value, start + end),
+                    None => {
+                        out.push_str(&rest[..start + 4]);
+                        rest = &rest[start + 4..];
+                        continue;
+                    }
+                }
+            } else {
+                match css_import(&rest[start..]) {
+                    Some((value, end)) => ("@import url(", value, start + end),
+                    // @import url(...) is rewritten as url(...).
+                    None => {
+                        out.push_str(&rest[..start + 7]);
+                        rest = &rest[start + 7..];
+                        continue;
+                    }
+                }
+            };
+
+            out.push_str(&rest[..start]);
+            rest = &rest[end..];
+
+            let url = match base.join(&value) {
+                Ok(ref url) if !value.starts_with("data:") && !value.starts_with('#') => url.clone(),
+                _ => {
+                    out.push_str(&format!("{}\"{}\")", prefix, value));
+                    continue;
+                }
+            };
+            let data = self.inline(&url, depth).unwrap_or_else(|| url.to_string());
+            out.push_str(&format!("{}\"{}\")", prefix, data));
+        }
+
+        out.push_str(rest);
+        out
+    }
+
+    // Data URI of the resource at url, stylesheets with their own resources inlined.
+    fn inline(&mut self, url: &Url, depth: usize) -> Option<String> {
+        let key = url.as_str().split('#').next().unwrap().to_string();
+        if let Some(data) = self.cache.get(&key) {
+            return data.clone();
+        }
+
+        let data = match self.fetcher.get(&key) {
+            Ok(page) => {
+                self.resources += 1;
+                let mime = page.content_type.as_deref()
+                    .and_then(|t| t.split(';').next())
+                    .map(|t| t.trim().to_lowercase())
+                    .unwrap_or_else(|| guess_mime(url.path()).to_string());
+
+                if mime == "text/css" && depth < MAX_CSS_DEPTH {
+                    let css = decode(&page.body, page.content_type.as_deref());
+                    let css = self.rewrite_css(&css, &page.url, depth + 1);
+                    Some(data_uri("text/css;charset=utf-8", css.as_bytes()))
+                } else {
+                    Some(data_uri(&mime, &page.body))
+                }
+            },
+            Err(_) => {
+                self.failed += 1;
+                None
+            }
+        };
+
+        self.cache.insert(key, data.clone());
+        data
+    }
+}
+
+fn absolute_attr(tag: &mut Tag, name: &str, base: &Url) {
+    if let Some(value) = tag.attr(name) {
+        if value.trim().starts_with('#') {
+            return;
+        }
+        if let Ok(url) = base.join(value.trim()) {
+            tag.set_attr(name, url.as_str());
+        }
+    }
+}
+
+fn guess_mime(path: &str) -> &'static str {
+    let ext = path.rsplit('.').next().unwrap_or("").to_lowercase();
+
+    match ext.as_str() {
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        _ => "application/octet-stream",
+    }
+}
+
+// Value and length of url(...) at the start of s.
+fn css_url(s: &str) -> Option<(String, usize)> {
+    let inner = &s[4..];
+    let trimmed = inner.trim_start();
+    let skipped = inner.len() - trimmed.len();
+
+    let (value, len) = match trimmed.chars().next()? {
+        q @ '"' | q @ '\'' => {
+            let end = trimmed[1..].find(q)? + 1;
+            let close = trimmed[end + 1..].find(')')? + end + 1;
+            (&trimmed[1..end], close + 1)
+        },
+        _ => {
+            let close = trimmed.find(')')?;
+            (&trimmed[..close], close + 1)
+        },
+    };
+
+    Some((value.trim().to_string(), 4 + skipped + len))
+}
+
+// Value and length of @import "..." at the start of s.
+fn css_import(s: &str) -> Option<(String, usize)> {
+    let inner = &s[7..];
+    let trimmed = inner.trim_start();
+    let skipped = inner.len() - trimmed.len();
+
+    let q = trimmed.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let end = trimmed[1..].find(q)? + 1;
+
+    Some((trimmed[1..end].to_string(), 7 + skipped + end + 1))
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    let needle = needle.as_bytes();
+
+    haystack.as_bytes().windows(needle.len())
+        .position(|w| w.eq_ignore_ascii_case(needle))
+}
+
+// Index just past the '>' closing the tag at the start of s.
+fn tag_end(s: &str) -> usize {
+    let mut quote: Option<char> = None;
+
+    for (i, c) in s.char_indices().skip(1) {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return i + 1,
+            None => {},
+        }
+    }
+
+    s.len()
+}
+
+// Start tag, keeping the source of attributes that are not changed.
+struct Tag {
+    name: String,
+    attrs: Vec<(String, Option<String>)>,
+    source: String,
+    changed: bool
+}
+
+impl Tag {
+    fn parse(source: &str) -> Option<Tag> {
+        let inner = source.trim_start_matches('<').trim_end_matches('>');
+        if !inner.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return None;
+        }
+
+        let name_end = inner.find(|c: char| c.is_whitespace() || c == '/').unwrap_or(inner.len());
+        let name = inner[..name_end].to_lowercase();
+        let mut rest = &inner[name_end..];
+        let mut attrs: Vec<(String, Option<String>)> = Vec::new();
+
+        loop {
+            rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+            if rest.is_empty() {
+                break;
+            }
+
+            let end = rest.find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+                .unwrap_or(rest.len()).max(1);
+            let attr = rest[..end].to_lowercase();
+            rest = rest[end..].trim_start();
+
+            if !rest.starts_with('=') {
+                attrs.push((attr, None));
+                continue;
+            }
+            rest = rest[1..].trim_start();
+
+            let value = match rest.chars().next() {
+                Some(q @ '"') | Some(q @ '\'') => {
+                    let end = rest[1..].find(q).map_or(rest.len(), |e| e + 1);
+                    let value = &rest[1..end];
+                    rest = &rest[(end + 1).min(rest.len())..];
+                    value
+                },
+                _ => {
+                    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                    let value = &rest[..end];
+                    rest = &rest[end..];
+                    value
+                },
+            };
+            attrs.push((attr, Some(unescape(value))));
+        }
+
+        Some(Tag { name, attrs, source: source.to_string(), changed: false })
+    }
+
+    fn attr(&self, name: &str) -> Option<String> {
+        self.attrs.iter()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, v)| v.clone())
+    }
+
+    fn set_attr(&mut self, name: &str, value: &str) {
+        for attr in self.attrs.iter_mut().filter(|(n, _)| n == name) {
+            if attr.1.as_deref() != Some(value) {
+                attr.1 = Some(value.to_string());
+                self.changed = true;
+            }
+        }
+    }
+
+    fn remove_attr(&mut self, name: &str) {
+        let len = self.attrs.len();
+        self.attrs.retain(|(n, _)| n != name);
+        self.changed = self.changed || self.attrs.len() != len;
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.changed {
+            return write!(f, "{}", self.source);
+        }
+
+        write!(f, "<{}", self.name)?;
+        for (name, value) in &self.attrs {
+            match value {
+                Some(value) => write!(f, " {}=\"{}\"", name, escape(value))?,
+                None => write!(f, " {}", name)?,
+            }
+        }
+        if self.source.ends_with("/>") {
+            write!(f, " /")?;
+        }
+        write!(f, ">")
+    }
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64;
+    use std::time::Duration;
+    use testserver::{serve, Response};
+
+    fn decode_data(uri: &str) -> String {
+        let data = uri.split(";base64,").nth(1).unwrap();
+        String::from_utf8(base64::decode(data).unwrap()).unwrap()
+    }
+
+    // Contents of the first attribute value starting with prefix.
+    fn find_value<'a>(html: &'a str, prefix: &str) -> &'a str {
+        let start = html.find(prefix).unwrap();
+        let end = html[start..].find('"').unwrap();
+        &html[start..start + end]
+    }
+
+    #[test]
+    fn test_archive() {
+        let server = serve(|req| match req.path.split('?').next().unwrap() {
+            "/page" => Response::html("<html><head><title>Page</title>
+                <link rel=\"stylesheet\" href=\"/style.css\" integrity=\"sha384-x\">
+                <script src='/app.js?v=1&amp;x=2'></script>
+                <style>body { background: url(img/bg.png) }</style></head>
+                <body><!-- <img src=\"/comment.png\"> --><p>1 < 2</p>
+                <img src=\"img/logo.png\" srcset=\"img/logo@2x.png 2x\" alt=\"a > b\">
+                <img src=\"/missing.png\"><a href=\"/other\">Other</a><a href=\"#top\">Top</a>
+                </body></html>"),
+            "/style.css" => Response::new(200, b"@import 'print.css'; h1 { background: url(\"/img/bg.png\") }")
+                .header("Content-Type", "text/css"),
+            "/print.css" => Response::new(200, b"h2 { color: red }").header("Content-Type", "text/css"),
+            "/app.js" => Response::new(200, b"console.log('<b>');")
+                .header("Content-Type", "application/javascript"),
+            "/img/bg.png" | "/img/logo.png" => Response::new(200, b"\x89PNG")
+                .header("Content-Type", "image/png"),
+            _ => Response::new(404, b""),
+        });
+        let fetcher = Fetcher::new(Duration::from_secs(1));
+
+        let page = archive(&fetcher, &server.url("/page")).unwrap();
+        let html = &page.html;
+        assert_eq!((page.resources, page.failed), (5, 1));
+
+        assert!(html.contains("<title>Page</title>"));
+        assert!(html.contains("<!-- <img src=\"/comment.png\"> -->"));
+        assert!(html.contains("background: url(\"data:image/png;base64,iVBORw==\")"));
+        assert!(html.contains("<img src=\"data:image/png;base64,iVBORw==\" alt=\"a > b\">"));
+        assert!(html.contains(&format!("<img src=\"{}\">", server.url("/missing.png"))));
+        assert!(html.contains(&format!("<a href=\"{}\">", server.url("/other"))));
+        assert!(html.contains("<a href=\"#top\">"));
+        assert!(!html.contains("integrity"));
+        assert!(!html.contains("srcset"));
+
+        let script = find_value(html, "data:application/javascript");
+        assert_eq!(decode_data(script), "console.log('<b>');");
+
+        let css = decode_data(find_value(html, "data:text/css"));
+        assert!(css.starts_with("@import url(\"data:text/css;charset=utf-8;base64,"));
+        assert!(css.ends_with("h1 { background: url(\"data:image/png;base64,iVBORw==\") }"));
+
+        assert!(archive(&fetcher, &server.url("/gone")).is_err());
+        assert_eq!(server.requests().iter().filter(|r| r.path == "/img/bg.png").count(), 1);
+    }
+
+    #[test]
+    fn test_tag() {
+        let tag = Tag::parse("<IMG Src=\"a.png\" alt='x \"y\"' hidden data-x=1 />").unwrap();
+        assert_eq!(tag.name, "img");
+        assert_eq!(tag.attr("src"), Some("a.png".to_string()));
+        assert_eq!(tag.attr("alt"), Some("x \"y\"".to_string()));
+        assert_eq!(tag.attr("data-x"), Some("1".to_string()));
+        assert_eq!(tag.to_string(), "<IMG Src=\"a.png\" alt='x \"y\"' hidden data-x=1 />");
+
+        let mut tag = tag;
+        tag.set_attr("src", "b.png");
+        assert_eq!(tag.to_string(), "<img src=\"b.png\" alt=\"x &quot;y&quot;\" hidden data-x=\"1\" />");
+
+        assert!(Tag::parse("</div>").is_none());
+        assert!(Tag::parse("<!DOCTYPE html>").is_none());
+        assert_eq!(tag_end("<a title=\"a > b\">text"), 17);
+    }
+}
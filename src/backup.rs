@@ -0,0 +1,227 @@
+// Backups of the library in ~/.bkm/backups, taken with the online backup API
+// of SQLite before destructive operations, on a schedule and on demand, the
+// oldest being removed. Backups of an encrypted library are encrypted with
+// its key.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use config::Config;
+use database::DB;
+use encryption::{is_encrypted, runtime_dir, seal_with_session, unseal_with_session, write_private};
+use utils::{bkm_dir, expand_home};
+
+pub struct Backup {
+    pub path: PathBuf,
+    // Date it was taken, as "YYYY-MM-DD HH:MM:SS" in UTC.
+    pub time: String,
+    // Operation it was taken before, "scheduled" or "manual".
+    pub reason: String
+}
+
+pub fn backup_dir(config: &Config) -> Result<PathBuf, String> {
+    let dir = match config.backup.dir {
+        Some(ref dir) => expand_home(dir),
+        None => bkm_dir().join("backups"),
+    };
+    fs::create_dir_all(&dir).map_err(|e| format!("Error: Failed to create {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+// UTC date of a unix time, as "YYYYMMDD-HHMMSS".
+fn stamp(secs: u64) -> String {
+    let (days, rest) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Civil date of the days since 1970-01-01, counted in eras of 400 years
+    // starting on March 1st.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, rest / 3600, rest / 60 % 60, rest % 60)
+}
+
+// Backup of a file named "bookmarks-YYYYMMDD-HHMMSS-<reason>.db[.enc]".
+fn parse_name(path: &Path) -> Option<Backup> {
+    let name = path.file_name()?.to_str()?;
+    let rest = name.strip_prefix("bookmarks-")?;
+    let rest = rest.strip_suffix(".db.enc").or_else(|| rest.strip_suffix(".db"))?;
+
+    let mut parts = rest.splitn(3, '-');
+    let (date, time, reason) = (parts.next()?, parts.next()?, parts.next()?);
+    let digits = |s: &str, n: usize| s.len() == n && s.bytes().all(|b| b.is_ascii_digit());
+    if !digits(date, 8) || !digits(time, 6) || reason.is_empty() {
+        return None;
+    }
+
+    Some(Backup {
+        path: path.to_path_buf(),
+        time: format!("{}-{}-{} {}:{}:{}", &date[..4], &date[4..6], &date[6..],
+                      &time[..2], &time[2..4], &time[4..]),
+        reason: reason.to_string(),
+    })
+}
+
+// Backups in dir, last taken first.
+pub fn list_in(dir: &Path) -> Vec<Backup> {
+    let mut backups: Vec<Backup> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).filter_map(|e| parse_name(&e.path())).collect(),
+        Err(_) => Vec::new(),
+    };
+    // Backups of the same second are ordered by modification time.
+    let modified = |b: &Backup| fs::metadata(&b.path).and_then(|m| m.modified()).ok();
+    backups.sort_by_cached_key(|b| (b.time.clone(), modified(b)));
+    backups.reverse();
+    backups
+}
+
+pub fn list() -> Result<Vec<Backup>, String> {
+    Ok(list_in(&backup_dir(&Config::load())?))
+}
+
+// Back the library up into dir, without removing older backups.
+pub fn create_in(db: &DB, dir: &Path, reason: &str) -> Result<PathBuf, String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let name = format!("bookmarks-{}-{}.db", stamp(now), reason);
+
+    if is_encrypted() {
+        // The plain copy stays in the runtime directory, like the library.
+        let tmp = runtime_dir().join("backup.db");
+        db.backup_to(&tmp)?;
+        let plaintext = fs::read(&tmp).map_err(|e| format!("Error: Failed to read {}: {}", tmp.display(), e));
+        let _ = fs::remove_file(&tmp);
+
+        let sealed = seal_with_session(&plaintext?).ok_or("Error: Library is locked")?;
+        let path = dir.join(format!("{}.enc", name));
+        write_private(&path, &sealed)?;
+        Ok(path)
+    } else {
+        // Written aside and renamed, a backup cut short is never listed.
+        let path = dir.join(&name);
+        let tmp = dir.join(format!(".{}", name));
+        db.backup_to(&tmp)?;
+        fs::rename(&tmp, &path).map_err(|e| format!("Error: Failed to write {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+}
+
+// Remove the oldest backups of dir, keeping keep of them and the one at kept.
+pub fn rotate(dir: &Path, keep: usize, kept: Option<&Path>) {
+    let kept = kept.and_then(|path| fs::canonicalize(path).ok());
+    let backups = list_in(dir).into_iter()
+        .filter(|b| kept.is_none() || fs::canonicalize(&b.path).ok() != kept);
+    for backup in backups.skip(keep.max(1)) {
+        let _ = fs::remove_file(backup.path);
+    }
+}
+
+// Back the library up before the operation reason.
+pub fn create(db: &DB, reason: &str) -> Result<PathBuf, String> {
+    let config = Config::load();
+    let dir = backup_dir(&config)?;
+    let path = create_in(db, &dir, reason)?;
+    rotate(&dir, config.backup.keep, None);
+    Ok(path)
+}
+
+// Back the library up when the last backup is older than the interval of the
+// config, unless it is empty.
+pub fn scheduled(db: &DB) {
+    let config = Config::load();
+    if config.backup.interval == 0 || db.get_record_count("bookmarks") == 0 {
+        return;
+    }
+
+    let interval = Duration::from_secs(config.backup.interval * 60 * 60);
+    let due = backup_dir(&config).map(|dir| {
+        list_in(&dir).first()
+            .and_then(|last| fs::metadata(&last.path).and_then(|m| m.modified()).ok())
+            .and_then(|modified| modified.elapsed().ok())
+            .is_none_or(|age| age >= interval)
+    });
+
+    if due.unwrap_or(false) {
+        if let Err(e) = create(db, "scheduled") {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+// Replace the library with backup, backing it up first.
+pub fn restore(db: &mut DB, backup: &Path) -> Result<(), String> {
+    let config = Config::load();
+    let dir = backup_dir(&config)?;
+    create_in(db, &dir, "restore")?;
+
+    if backup.extension().is_some_and(|e| e == "enc") {
+        let data = fs::read(backup).map_err(|e| format!("Error: Failed to read {}: {}", backup.display(), e))?;
+        let tmp = runtime_dir().join("restore.db");
+        write_private(&tmp, &unseal_with_session(&data)?)?;
+        let restored = db.restore_from(&tmp);
+        let _ = fs::remove_file(&tmp);
+        restored?;
+    } else {
+        db.restore_from(backup)?;
+    }
+
+    // Only now, and never removing the backup restored, which may be the oldest.
+    rotate(&dir, config.backup.keep, Some(backup));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile;
+
+    #[test]
+    fn test_stamp() {
+        assert_eq!(stamp(0), "19700101-000000");
+        assert_eq!(stamp(951_782_400), "20000229-000000");
+        assert_eq!(stamp(1_791_793_376), "20261012-082256");
+    }
+
+    #[test]
+    fn test_parse_name() {
+        let backup = parse_name(Path::new("/b/bookmarks-20261012-082256-delete-all.db.enc")).unwrap();
+        assert_eq!(backup.time, "2026-10-12 08:22:56");
+        assert_eq!(backup.reason, "delete-all");
+
+        assert!(parse_name(Path::new("bookmarks-20261012-082256-import.db")).is_some());
+        assert!(parse_name(Path::new(".bookmarks-20261012-082256-import.db")).is_none());
+        assert!(parse_name(Path::new("bookmarks-20261012-082256-import.db.tmp")).is_none());
+        assert!(parse_name(Path::new("bookmarks-2026-082256-import.db")).is_none());
+    }
+
+    #[test]
+    fn test_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = DB::open_in_memory();
+        db.add_bookmark(&"GitHub".to_string(), &"https://github.com/".to_string()).unwrap();
+
+        let path = create_in(&db, dir.path(), "manual").unwrap();
+        db.add_bookmark(&"Rust".to_string(), &"https://www.rust-lang.org/".to_string()).unwrap();
+        create_in(&db, dir.path(), "import").unwrap();
+        let backups = list_in(dir.path());
+        assert_eq!(backups.len(), 2);
+
+        db.restore_from(&path).unwrap();
+        assert_eq!(db.get_record_count("bookmarks"), 1);
+
+        let third = create_in(&db, dir.path(), "dedupe").unwrap();
+        rotate(dir.path(), 1, Some(&path));
+        let kept: Vec<PathBuf> = list_in(dir.path()).into_iter().map(|b| b.path).collect();
+        assert_eq!(kept, vec![third.clone(), path]);
+
+        rotate(dir.path(), 1, None);
+        assert_eq!(list_in(dir.path()).len(), 1);
+        assert_eq!(list_in(dir.path())[0].path, third);
+    }
+}
@@ -1,22 +1,66 @@
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Default)]
 pub struct Bookmark {
+    #[serde(default)]
     pub id: i64,
     pub title: String,
     pub url: String,
-    pub tags: Vec<String>
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub notes: String
 }
 
 impl Bookmark {
     pub fn new(id: i64, title: String, url: String, tags: Vec<String>) -> Self {
-        Bookmark { id, title, url, tags }
+        Bookmark { id, title, url, tags, ..Default::default() }
     }
 
     pub fn print(&self) {
+        println!("{}", self);
+    }
+}
+
+// `{:#}` also shows description and notes.
+impl fmt::Display for Bookmark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let space = "    ";
-        let bookmark = format!("{} {}\n{}{}\n", self.id, self.title, space, self.url);
-        if self.tags.is_empty() {
-            println!("{}", bookmark);
-        } else {
-            println!("{}{}{}\n", bookmark, space, self.tags.join(", "));
+        writeln!(f, "{} {}\n{}{}", self.id, self.title, space, self.url)?;
+        if !self.tags.is_empty() {
+            writeln!(f, "{}{}", space, self.tags.join(", "))?;
+        }
+
+        if f.alternate() {
+            if !self.description.is_empty() {
+                writeln!(f, "{}Description: {}", space, self.description)?;
+            }
+            for (i, line) in self.notes.lines().enumerate() {
+                let label = if i == 0 { "Notes: " } else { "       " };
+                writeln!(f, "{}{}{}", space, label, line)?;
+            }
         }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let mut bookmark = Bookmark::new(
+            1, "GitHub".to_string(), "https://github.com".to_string(), vec!["git".to_string()]
+        );
+        bookmark.description = "Hosting service".to_string();
+        bookmark.notes = "Saved for\nbkm".to_string();
+
+        assert_eq!(format!("{}", bookmark), "1 GitHub\n    https://github.com\n    git\n");
+        assert_eq!(format!("{:#}", bookmark), "1 GitHub\n    https://github.com\n    git\n\
+                   \x20   Description: Hosting service\n    Notes: Saved for\n           bkm\n");
     }
 }
@@ -2,9 +2,10 @@ use clap::{App, ArgMatches, SubCommand};
 use std::process;
 
 use bookmark::Bookmark;
+use cmd::fetch_favicons;
 use database::DB;
-
-use utils::get_title_from_url;
+use fetch::{Fetcher, DEFAULT_TIMEOUT};
+use normalize::{canonical_url, normalize_url, placeholder_title};
 
 pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("add")
@@ -12,28 +13,58 @@ pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
         .arg_from_usage("<URL> 'Bookmark URL'")
         .arg_from_usage("-t --tag=[tag]... 'Add tags to bookmark'")
         .arg_from_usage("-i --title=[title] 'Decide bookmark title yourself'")
+        .arg_from_usage("-n --note=[note] 'Add notes to bookmark'")
+        .arg_from_usage("-c --content 'Index text of the page for search --content'")
+        .arg_from_usage("--offline 'Do not fetch the page, title it after its URL{n}\
+                         (Fill it in later with refresh --pending)'")
 }
 
 pub fn execute(args: &ArgMatches) {
     let db = DB::open();
 
-    let url = args.value_of("URL").unwrap();
+    let url = normalize_url(args.value_of("URL").unwrap());
 
-    let title = if let Some(title) = args.value_of("title") {
-        title.to_string()
+    let wanted = !args.is_present("title") || args.is_present("content");
+    let mut unreachable = false;
+    let page = if wanted && !args.is_present("offline") {
+        // The bookmark is saved anyway, the page can be fetched again later.
+        match Fetcher::load(DEFAULT_TIMEOUT).get(&url) {
+            Ok(page) => Some(page),
+            Err(e) => {
+                println!("Warning: {}", e.message.trim_start_matches("Error: "));
+                unreachable = true;
+                None
+            }
+        }
     } else {
-        get_title_from_url(url)
+        None
+    };
+
+    let metadata = page.as_ref().map(|p| p.metadata());
+
+    // Save the URL the page declares as its own, e.g. without session parameters.
+    let url = match metadata {
+        Some(ref metadata) => canonical_url(&url, metadata.canonical_url.as_deref()),
+        None => url,
     };
 
-    match db.add_bookmark(&title, &url.to_string()) {
-        Ok(_) => {},
+    let (title, description) = match (args.value_of("title"), metadata) {
+        (Some(title), _) => (title.to_string(), String::new()),
+        (None, Some(metadata)) => (metadata.title, metadata.description.unwrap_or_default()),
+        (None, None) => (placeholder_title(&url), String::new()),
+    };
+
+    let id = match db.add_bookmark(&title, &url) {
+        Ok(id) => id,
         Err(e) => {
             println!("{}", e);
             process::exit(1);
         }
-    }
+    };
 
-    let id = db.get_max_bookmark_id();
+    if !args.is_present("title") && page.is_none() {
+        db.set_needs_metadata(id, true);
+    }
 
     let mut tags: Vec<String> = Vec::new();
 
@@ -44,9 +75,29 @@ pub fn execute(args: &ArgMatches) {
         }
     }
 
-    let bookmark = Bookmark::new(
-        id, title, url.to_string(), tags
+    let mut bookmark = Bookmark::new(
+        id, title, url, tags
     );
 
-    bookmark.print();
+    if !description.is_empty() {
+        db.update_description(id, &description);
+        bookmark.description = description;
+    }
+
+    if let Some(note) = args.value_of("note") {
+        db.update_notes(id, note);
+        bookmark.notes = note.to_string();
+    }
+
+    if let (true, Some(page)) = (args.is_present("content"), &page) {
+        db.set_content(id, &page.text());
+    }
+
+    // The icon of a page which could not be fetched is left to refresh --pending.
+    if !args.is_present("offline") && !unreachable {
+        let icon_url = page.and_then(|p| p.metadata().icon_url);
+        fetch_favicons(&db, vec![(bookmark.url.clone(), icon_url)]);
+    }
+
+    println!("{:#}", bookmark);
 }
@@ -0,0 +1,78 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use archive::archive;
+use cmd::select_bookmarks;
+use database::DB;
+use encryption::{seal_with_session, write_private};
+use fetch::{Fetcher, DEFAULT_TIMEOUT};
+use pool;
+use utils::bkm_dir;
+
+// Pages archived at once, each of them downloading its resources in turn.
+const WORKERS: usize = 4;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("archive")
+        .about("Save offline copies of bookmarks")
+        .arg_from_usage("<ID|QUERY>... 'Archive bookmarks matching the specified ids or search keywords'")
+        .after_help("Pages are saved with their stylesheets, scripts and images as single \
+                     HTML files under ~/.bkm/archive. Open them with `bkm open --archived`.")
+}
+
+// Directory holding the archived pages.
+pub fn archive_dir() -> PathBuf {
+    let mut path = bkm_dir();
+    path.push("archive");
+    fs::create_dir_all(&path).unwrap();
+
+    path
+}
+
+pub fn execute(args: &ArgMatches) {
+    let db = DB::open();
+    let bookmarks = select_bookmarks(args, "ID|QUERY", &db);
+
+    if bookmarks.is_empty() {
+        println!("Error: No matching any bookmark");
+        process::exit(1);
+    }
+
+    let fetcher = Fetcher::load(DEFAULT_TIMEOUT);
+    let urls: Vec<String> = bookmarks.iter().map(|b| b.url.clone()).collect();
+    let archives = pool::map(urls, WORKERS, |url| archive(&fetcher, &url));
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    for (bookmark, result) in bookmarks.iter().zip(archives) {
+        println!("{} {}\n    {}", bookmark.id, bookmark.title, bookmark.url);
+
+        let archive = match result {
+            Ok(archive) => archive,
+            Err(e) => {
+                println!("    {}\n", e);
+                continue;
+            }
+        };
+
+        let mut path = archive_dir();
+        path.push(format!("{}-{}.html", bookmark.id, now));
+        // The BOM makes browsers read the page as UTF-8 whatever its <meta> says.
+        let html = format!("\u{feff}{}", archive.html);
+        // Pages of an encrypted library are encrypted as well, under the same name.
+        let written = match seal_with_session(html.as_bytes()) {
+            Some(sealed) => write_private(&path, &sealed),
+            None => fs::write(&path, html).map_err(|e| format!("Error: Failed to write {}: {}", path.display(), e)),
+        };
+        if let Err(e) = written {
+            println!("    {}\n", e);
+            continue;
+        }
+        db.add_snapshot(bookmark.id, &path.to_string_lossy());
+
+        println!("    Archived to {} ({} resources, {} failed)\n",
+                 path.display(), archive.resources, archive.failed);
+    }
+}
@@ -0,0 +1,80 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::path::PathBuf;
+use std::process;
+
+use backup::{self, Backup};
+use cmd::confirm;
+use database::DB;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("backup")
+        .about("Back up the library or restore a backup")
+        .subcommand(SubCommand::with_name("create")
+            .about("Back up the library now"))
+        .subcommand(SubCommand::with_name("list")
+            .about("Print backups, last taken first"))
+        .subcommand(SubCommand::with_name("restore")
+            .about("Replace the library with a backup")
+            .arg_from_usage("<BACKUP> 'Number printed by bkm backup list, or path of a backup'")
+            .arg_from_usage("-y --yes 'Do not ask for confirmation'"))
+        .after_help("Backups are also taken before deleting all bookmarks, importing, deduplicating \
+                     and migrating the library, and every day. They go to ~/.bkm/backups, \
+                     where the last 10 are kept; see [backup] in the configuration.")
+}
+
+pub fn execute(args: &ArgMatches) {
+    match args.subcommand() {
+        ("create", Some(_)) => {
+            let db = DB::open();
+            match backup::create(&db, "manual") {
+                Ok(path) => println!("Backed up to {}", path.display()),
+                Err(e) => exit(&e),
+            }
+        },
+        ("restore", Some(args)) => {
+            let path = find(args.value_of("BACKUP").unwrap());
+            if !args.is_present("yes") && !confirm(&format!("Replace the library with {}?", path.display())) {
+                process::exit(1);
+            }
+
+            let mut db = DB::open_for_restore();
+            match backup::restore(&mut db, &path) {
+                Ok(()) => println!("Restored {}", path.display()),
+                Err(e) => exit(&e),
+            }
+        },
+        _ => {
+            let backups = backup::list().unwrap_or_else(|e| exit(&e));
+            if backups.is_empty() {
+                println!("No backups");
+                return;
+            }
+
+            for (i, Backup { path, time, reason }) in backups.iter().enumerate() {
+                println!("{} {} UTC  {}\n    {}", i + 1, time, reason, path.display());
+            }
+        },
+    }
+}
+
+// Backup numbered as by list, or at a path.
+fn find(backup: &str) -> PathBuf {
+    if let Ok(n) = backup.parse::<usize>() {
+        let backups = backup::list().unwrap_or_else(|e| exit(&e));
+        return match n.checked_sub(1).and_then(|i| backups.into_iter().nth(i)) {
+            Some(backup) => backup.path,
+            None => exit(&format!("Error: No backup numbered {}", n)),
+        };
+    }
+
+    let path = PathBuf::from(backup);
+    if !path.is_file() {
+        exit(&format!("Error: No backup at {}", path.display()));
+    }
+    path
+}
+
+fn exit(e: &str) -> ! {
+    println!("{}", e);
+    process::exit(1);
+}
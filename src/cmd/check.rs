@@ -0,0 +1,135 @@
+use clap::{App, ArgMatches, SubCommand};
+use reqwest::StatusCode;
+use std::process;
+use std::time::Duration;
+
+use bookmark::Bookmark;
+use cmd::{confirm, select_bookmarks};
+use database::DB;
+use fetch::{Fetcher, DEFAULT_TIMEOUT};
+use normalize::normalize_url;
+use pool;
+
+const DEFAULT_WORKERS: usize = 8;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("check")
+        .about("Check bookmarks for dead links")
+        .arg_from_usage("[ID|QUERY]... 'Check bookmarks matching the specified ids or search keywords{n}\
+                         (If nothing is specified, check all bookmarks)'")
+        .arg_from_usage("--tag-broken=[tag] 'Tag broken bookmarks, untag the ones working again'")
+        .arg_from_usage("--fix-redirects 'Update URLs of bookmarks moved permanently{n}\
+                         (Bookmarks moved to saved URLs are merged into them)'")
+        .arg_from_usage("-y --yes 'Fix redirects without asking'")
+        .arg_from_usage("-j --workers=[workers] 'Number of links checked at once (default: 8)'")
+        .arg_from_usage("--timeout=[seconds] 'Give up on links not answering in time (default: 10)'")
+}
+
+pub fn execute(args: &ArgMatches) {
+    let db = DB::open();
+    let bookmarks = select_bookmarks(args, "ID|QUERY", &db);
+
+    if bookmarks.is_empty() {
+        println!("Error: No matching any bookmark");
+        process::exit(1);
+    }
+
+    let workers = if args.is_present("workers") {
+        value_t!(args, "workers", usize).unwrap_or_else(|e| e.exit())
+    } else {
+        DEFAULT_WORKERS
+    };
+    let timeout = if args.is_present("timeout") {
+        Duration::from_secs(value_t!(args, "timeout", u64).unwrap_or_else(|e| e.exit()))
+    } else {
+        DEFAULT_TIMEOUT
+    };
+
+    let fetcher = Fetcher::load(timeout);
+    let urls: Vec<String> = bookmarks.iter().map(|b| b.url.clone()).collect();
+    let results = pool::map(urls, workers, |url| fetcher.check(&url));
+
+    let mut broken = 0;
+    let mut moved: Vec<(&Bookmark, String)> = Vec::new();
+    for (bookmark, result) in bookmarks.iter().zip(results) {
+        let problem = match result {
+            Ok(ref link) => {
+                if let Some(ref url) = link.permanent_url {
+                    let url = normalize_url(url);
+                    if url != bookmark.url {
+                        moved.push((bookmark, url));
+                    }
+                }
+                db.record_check(bookmark.id, Some(link.status), Some(&link.final_url));
+                if link.is_broken() {
+                    Some(status_text(link.status))
+                } else {
+                    None
+                }
+            },
+            Err(e) => {
+                db.record_check(bookmark.id, None, None);
+                Some(e)
+            }
+        };
+
+        if let Some(tag) = args.value_of("tag-broken") {
+            tag_broken(&db, bookmark, tag, problem.is_some());
+        }
+
+        if let Some(problem) = problem {
+            broken += 1;
+            println!("{} {}\n    {}\n    {}\n", bookmark.id, bookmark.title, bookmark.url, problem);
+        }
+    }
+
+    println!("Checked {} bookmarks, {} broken, {} moved permanently",
+             bookmarks.len(), broken, moved.len());
+
+    if args.is_present("fix-redirects") {
+        for (bookmark, url) in moved {
+            fix_redirect(&db, bookmark, &url, args.is_present("yes"));
+        }
+    }
+}
+
+// Point bookmark at url, or merge it into the bookmark already saved there.
+fn fix_redirect(db: &DB, bookmark: &Bookmark, url: &str, yes: bool) {
+    println!("\n{} {}\n    {}\n    -> {}", bookmark.id, bookmark.title, bookmark.url, url);
+
+    let result = match db.get_id_by_url(url) {
+        Some(id) if id != bookmark.id => {
+            if !yes && !confirm(&format!("Merge into bookmark {}?", id)) {
+                return;
+            }
+            db.merge_bookmark(bookmark.id, id)
+        },
+        _ => {
+            if !yes && !confirm("Update URL?") {
+                return;
+            }
+            db.update_bookmark(bookmark.id, &bookmark.title, url)
+        },
+    };
+
+    if let Err(e) = result {
+        println!("{}", e);
+    }
+}
+
+fn tag_broken(db: &DB, bookmark: &Bookmark, tag: &str, broken: bool) {
+    let tagged = bookmark.tags.iter().any(|t| t == tag);
+
+    if broken && !tagged {
+        db.add_tag(bookmark.id, tag);
+    } else if !broken && tagged {
+        db.remove_tag(bookmark.id, tag);
+    }
+}
+
+fn status_text(status: u16) -> String {
+    match StatusCode::from_u16(status) {
+        Ok(code) => code.to_string(),
+        Err(_) => status.to_string(),
+    }
+}
@@ -0,0 +1,20 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::process;
+
+use encryption::{decrypt_library, plain_path};
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("decrypt")
+        .about("Decrypt the library back into a plain database")
+        .arg_from_usage("-k --keyfile=[path] 'Keyfile the library was encrypted with'")
+}
+
+pub fn execute(args: &ArgMatches) {
+    match decrypt_library(args.value_of("keyfile")) {
+        Ok(()) => println!("Decrypted the library into {}", plain_path().display()),
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    }
+}
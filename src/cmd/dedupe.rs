@@ -0,0 +1,76 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::io::{self, Write};
+
+use bookmark::Bookmark;
+use cmd::backup_before;
+use database::DB;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("dedupe")
+        .about("Merge bookmarks leading to the same page")
+        .arg_from_usage("-y --yes 'Merge into the oldest bookmark without asking'")
+        .after_help("URLs differing only in http or https, trailing slashes, order of \
+                     query parameters, tracking parameters or in-page anchors lead to \
+                     the same page.")
+}
+
+pub fn execute(args: &ArgMatches) {
+    let db = DB::open();
+    let groups = db.get_duplicates();
+
+    if groups.is_empty() {
+        println!("No duplicates");
+        return;
+    }
+    backup_before(&db, "dedupe");
+
+    let mut merged = 0;
+    for group in groups {
+        for bookmark in &group {
+            println!("{:#}", bookmark);
+        }
+
+        let into = if args.is_present("yes") {
+            Some(group[0].id)
+        } else {
+            choose(&group)
+        };
+
+        if let Some(into) = into {
+            for bookmark in group.iter().filter(|b| b.id != into) {
+                match db.merge_bookmark(bookmark.id, into) {
+                    Ok(()) => merged += 1,
+                    Err(e) => println!("{} {}", e, bookmark.id),
+                }
+            }
+        }
+        println!();
+    }
+
+    println!("Merged {} bookmarks", merged);
+}
+
+// Ask which bookmark of group to keep, None to leave them all alone.
+fn choose(group: &[Bookmark]) -> Option<i64> {
+    loop {
+        print!("Merge into which bookmark? (id, enter for {}, s to skip) ", group[0].id);
+        io::stdout().flush().unwrap();
+
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer).expect("Failed to read line") == 0 {
+            return None;
+        }
+
+        match answer.trim() {
+            "" => return Some(group[0].id),
+            "S" | "s" => return None,
+            id => {
+                if let Ok(id) = id.parse::<i64>() {
+                    if group.iter().any(|b| b.id == id) {
+                        return Some(id);
+                    }
+                }
+            },
+        }
+    }
+}
@@ -1,15 +1,19 @@
 use clap::{App, Arg, ArgMatches, SubCommand};
-use std::{io, process};
+use std::process;
 
+use cmd::{backup_before, confirm, is_pick, pick_args, pick_ids};
 use database::DB;
+use query::ListOptions;
 
 pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
-    SubCommand::with_name("delete")
-        .about("Delete bookmark")
+    let app = SubCommand::with_name("delete")
+        .about("Move bookmarks to the trash")
         .arg(Arg::from_usage("<ID>... 'Delete bookmarks matching the specified ids{n}\
                              (If id is not specified, delete all bookmarks)'").required(false))
         .arg_from_usage("-T 'Delete all tags'")
-        .arg_from_usage("-t --tag=[tag]... 'Delete tags matching the specified tags'")
+        .arg_from_usage("-t --tag=[tag]... 'Delete tags matching the specified tags'");
+
+    pick_args(app)
 }
 
 pub fn execute(args: &ArgMatches) {
@@ -18,8 +22,9 @@ pub fn execute(args: &ArgMatches) {
     if args.is_present("T") {
         yes_or_no("tags");
 
-        db.clear("tags");
-        db.clear("bookmark_tag");
+        for tag in db.get_all_tag() {
+            db.delete_tag(&tag);
+        }
 
         process::exit(0);
     }
@@ -39,22 +44,35 @@ pub fn execute(args: &ArgMatches) {
         process::exit(0);
     }
 
-    if !args.is_present("ID") {
+    if !args.is_present("ID") && !is_pick(args) {
         yes_or_no("bookmarks");
-
-        db.clear("bookmarks");
-        db.clear("tags");
-        db.clear("bookmark_tag");
+        backup_before(&db, "delete-all");
+
+        let bookmarks = db.get_all_bookmark(&ListOptions::default());
+        let ids: Vec<i64> = bookmarks.iter().map(|b| b.id).collect();
+        // All at once, or none when interrupted.
+        let _ = db.transaction(|| {
+            for id in &ids {
+                db.delete_bookmark(*id);
+            }
+            Ok::<_, ()>(())
+        });
+        println!("{} bookmarks moved to the trash", ids.len());
 
         process::exit(0);
     }
 
-    let ids = values_t!(args, "ID", i64).unwrap();
+    let ids = if is_pick(args) {
+        pick_ids(args, &db)
+    } else {
+        values_t!(args, "ID", i64).unwrap_or_else(|e| e.exit())
+    };
+
     for id in ids {
         let result: i64 = db.check_existence_bookmark(id);
         if result == 1 {
             db.delete_bookmark(id);
-            println!("Index {} deleted", id);
+            println!("Index {} moved to the trash", id);
         } else {
             println!("Error: No match index");
         }
@@ -62,19 +80,7 @@ pub fn execute(args: &ArgMatches) {
 }
 
 fn yes_or_no(table_name: &str) {
-    loop {
-        println!("Delete all {}? (y/n)", table_name);
-
-        let mut answer = String::new();
-        io::stdin().read_line(&mut answer).expect("Failed to read line");
-        let answer = answer.trim();
-
-        match answer {
-            "Y" | "y" | "" => println!("Done!"),
-            "N" | "n" => process::exit(1),
-            _ => continue,
-        }
-
-        break;
+    if !confirm(&format!("Delete all {}?", table_name)) {
+        process::exit(1);
     }
 }
@@ -0,0 +1,122 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::process;
+use tempfile;
+
+use bookmark::Bookmark;
+use cmd::{is_pick, pick_args, pick_ids};
+use database::DB;
+use editor;
+use normalize::url_key;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    let app = SubCommand::with_name("edit")
+        .about("Edit bookmark in editor")
+        .arg(Arg::from_usage("<ID>... 'Edit bookmarks matching the specified ids{n}\
+                             (If id is not specified, pick bookmarks)'").required(false))
+        .after_help("Bookmarks are opened as TOML in $VISUAL or $EDITOR.");
+
+    pick_args(app)
+}
+
+pub fn execute(args: &ArgMatches) {
+    let db = DB::open();
+
+    let ids = if args.is_present("ID") && !is_pick(args) {
+        values_t!(args, "ID", i64).unwrap_or_else(|e| e.exit())
+    } else {
+        pick_ids(args, &db)
+    };
+
+    let mut originals: Vec<Bookmark> = Vec::new();
+    for id in ids {
+        match db.get_bookmark_by_id(id) {
+            Ok(b) => originals.push(b),
+            Err(e) => {
+                println!("{} {}", e, id);
+                process::exit(1);
+            }
+        }
+    }
+
+    let file = tempfile::Builder::new()
+        .prefix("bkm-")
+        .suffix(".toml")
+        .tempfile()
+        .expect("Failed to create temporary file");
+    let mut content = editor::render(&originals);
+    let mut error: Option<String> = None;
+
+    let changed = loop {
+        fs::write(file.path(), &content).expect("Failed to write temporary file");
+        if let Err(e) = editor::open_editor(file.path()) {
+            println!("Error: {}", e);
+            process::exit(1);
+        }
+        let edited = fs::read_to_string(file.path()).expect("Failed to read temporary file");
+
+        // Quitting without saving gives up on the edit.
+        if edited == content {
+            match error {
+                Some(e) => {
+                    println!("Error: {}", e);
+                    process::exit(1);
+                },
+                None => {
+                    println!("No changes");
+                    process::exit(0);
+                }
+            }
+        }
+
+        match editor::changes(&edited, &originals).and_then(|c| check_urls(&db, c)) {
+            Ok(changed) => break changed,
+            Err(e) => {
+                content = editor::annotate(&edited, &e);
+                error = Some(e);
+            }
+        }
+    };
+
+    if changed.is_empty() {
+        println!("No changes");
+    }
+
+    // All bookmarks are saved or none, URLs may move between them.
+    let saved: Result<(), &str> = db.transaction(|| {
+        let titles: Vec<(i64, &str, &str)> = changed.iter()
+            .map(|b| (b.id, b.title.as_str(), b.url.as_str()))
+            .collect();
+        db.update_bookmarks(&titles)?;
+
+        for bookmark in &changed {
+            db.set_tags(bookmark.id, &bookmark.tags);
+            db.update_description(bookmark.id, &bookmark.description);
+            db.update_notes(bookmark.id, &bookmark.notes);
+        }
+        Ok(())
+    });
+
+    if let Err(e) = saved {
+        println!("{}", e);
+        process::exit(1);
+    }
+    for bookmark in changed {
+        println!("{:#}", bookmark);
+    }
+}
+
+// URLs may only move between the edited bookmarks, away from the one
+// holding them.
+fn check_urls(db: &DB, changed: Vec<Bookmark>) -> Result<Vec<Bookmark>, String> {
+    for bookmark in &changed {
+        if let Some(id) = db.get_id_by_url(&bookmark.url) {
+            let moved = changed.iter().any(|b| b.id == id && url_key(&b.url) != url_key(&bookmark.url));
+            if id != bookmark.id && !moved {
+                return Err(format!("URL \"{}\" already exists at index {}", bookmark.url, id));
+            }
+        }
+    }
+
+    Ok(changed)
+}
@@ -0,0 +1,36 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::process;
+
+use database::DB;
+use encryption::{encrypt_library, encrypted_path, is_encrypted, read_secret};
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("encrypt")
+        .about("Encrypt the library with a passphrase or a keyfile")
+        .arg_from_usage("-k --keyfile=[path] 'Encrypt with the content of a file instead of a passphrase'")
+        .after_help("Commands then ask for the passphrase, unless the library is unlocked \
+                     with bkm unlock, $BKM_PASSPHRASE is set, or a keyfile is set with \
+                     $BKM_KEYFILE or keyfile in the [encryption] table of ~/.bkm/config.toml.")
+}
+
+pub fn execute(args: &ArgMatches) {
+    if is_encrypted() {
+        println!("Error: Library is encrypted already");
+        process::exit(1);
+    }
+    // Create and migrate the library first, so that it is encrypted up to date,
+    // without taking a backup which would be left in plain.
+    drop(DB::open_for_restore());
+
+    let encrypted = read_secret(args.value_of("keyfile"), true)
+        .and_then(|secret| encrypt_library(&secret));
+    if let Err(e) = encrypted {
+        println!("{}", e);
+        process::exit(1);
+    }
+
+    println!("Encrypted the library into {}", encrypted_path().display());
+    if args.is_present("keyfile") {
+        println!("Set keyfile in the [encryption] table of ~/.bkm/config.toml to unlock it with the keyfile");
+    }
+}
@@ -0,0 +1,44 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use database::DB;
+use query::ListOptions;
+use utils::{bookmarks_to_html, bookmarks_to_json};
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("export")
+        .about("Export bookmark")
+        .arg_from_usage("[FILE] 'Export bookmarks to file (If file is not specified, print them)'")
+        .arg(Arg::from_usage("-f --format=[format] 'Export format (default: from file extension or html)'")
+             .possible_values(&["html", "json"]))
+}
+
+pub fn execute(args: &ArgMatches) {
+    let db = DB::open();
+    let bookmarks = db.get_all_bookmark(&ListOptions::default());
+
+    let path = args.value_of("FILE").map(Path::new);
+    let format = match args.value_of("format") {
+        Some(format) => format,
+        None if path.and_then(|p| p.extension()).is_some_and(|e| e == "json") => "json",
+        None => "html",
+    };
+
+    let output = match format {
+        "json" => bookmarks_to_json(&bookmarks),
+        _ => bookmarks_to_html(&bookmarks, &db.get_all_favicons()),
+    };
+
+    match path {
+        Some(path) => {
+            if let Err(e) = fs::write(path, output) {
+                println!("Error: Failed to write {}: {}", path.display(), e);
+                process::exit(1);
+            }
+            println!("Exported {} bookmarks to {}", bookmarks.len(), path.display());
+        },
+        None => print!("{}", output),
+    }
+}
@@ -0,0 +1,49 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::process;
+
+use database::DB;
+use history::decode_tags;
+use pager;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("history")
+        .about("Print the changes of a bookmark")
+        .arg_from_usage("<ID> 'Bookmark id'")
+        .arg_from_usage("--no-pager 'Do not pipe output into $PAGER'")
+        .after_help("Every change of the title, URL, description, notes or tags of a bookmark \
+                     is a revision, which bkm revert goes back to.")
+}
+
+pub fn execute(args: &ArgMatches) {
+    let id = value_t!(args, "ID", i64).unwrap_or_else(|e| e.exit());
+    let db = DB::open();
+
+    if let Err(e) = db.get_bookmark_by_id(id) {
+        println!("{} {}", e, id);
+        process::exit(1);
+    }
+
+    let history = db.get_history(id);
+    if history.is_empty() {
+        println!("No changes of bookmark {}", id);
+        return;
+    }
+
+    let mut output = String::new();
+    for revision in history {
+        output.push_str(&format!("{} {} {}\n", revision.rev, revision.time, revision.field));
+        // Tags are shown comma separated, as they are typed.
+        let text = |value: &str| if revision.field == "tags" {
+            decode_tags(value).join(", ")
+        } else {
+            value.to_string()
+        };
+        for line in text(&revision.old).lines() {
+            output.push_str(&format!("    - {}\n", line));
+        }
+        for line in text(&revision.new).lines() {
+            output.push_str(&format!("    + {}\n", line));
+        }
+    }
+    pager::page(&output, !args.is_present("no-pager"));
+}
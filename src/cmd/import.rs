@@ -1,33 +1,52 @@
 use clap::{App, ArgMatches, SubCommand};
+use std::collections::HashMap;
 use std::path::Path;
 
+use cmd::{backup_before, fetch_favicons};
 use database::DB;
-use utils::get_bookmarks_from_html;
+use normalize::normalize_url;
+use utils::{get_bookmarks_from_html, get_bookmarks_from_json, get_icons_from_html};
 
 pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("import")
         .about("Import bookmark")
-        .arg_from_usage("<FILE> 'Import bookmarks from html file'")
+        .arg_from_usage("<FILE> 'Import bookmarks from html or json file'")
+        .arg_from_usage("--no-icons 'Do not fetch icons of sites missing from the file'")
 }
 
 pub fn execute(args: &ArgMatches) {
     let db = DB::open();
     let path = Path::new(args.value_of("FILE").unwrap());
-    let bookmarks = get_bookmarks_from_html(path.to_path_buf());
-
-    for bookmark in bookmarks {
-        match db.add_bookmark(&bookmark.title, &bookmark.url) {
-            Ok(_) => {},
-            Err(e) => {
-                println!("{} for \"{}\"\n", e, &bookmark.url);
-                continue;
-            }
-        }
+    let (bookmarks, icons) = if path.extension().is_some_and(|e| e == "json") {
+        (get_bookmarks_from_json(path.to_path_buf()), HashMap::new())
+    } else {
+        (get_bookmarks_from_html(path.to_path_buf()), get_icons_from_html(path.to_path_buf()))
+    };
+
+    backup_before(&db, "import");
+    db.import_favicons(&icons);
+
+    let mut imported: Vec<(String, Option<String>)> = Vec::new();
+
+    // All at once, or none when interrupted.
+    let _ = db.transaction(|| {
+        for mut bookmark in bookmarks {
+            bookmark.url = normalize_url(&bookmark.url);
+            bookmark.id = match db.insert_bookmark(&bookmark) {
+                Ok(id) => id,
+                Err(e) => {
+                    println!("{} for \"{}\"\n", e, &bookmark.url);
+                    continue;
+                }
+            };
 
-        for tag in &bookmark.tags {
-            db.add_tag(bookmark.id, tag);
+            imported.push((bookmark.url.clone(), None));
+            bookmark.print();
         }
+        Ok::<_, ()>(())
+    });
 
-        bookmark.print();
+    if !args.is_present("no-icons") {
+        fetch_favicons(&db, imported);
     }
 }
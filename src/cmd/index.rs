@@ -0,0 +1,66 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use cmd::select_bookmarks;
+use database::DB;
+use fetch::{Fetcher, DEFAULT_TIMEOUT};
+use pool;
+
+const DEFAULT_WORKERS: usize = 8;
+const DEFAULT_MAX_AGE: u64 = 30;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("index")
+        .about("Index text of pages for search --content")
+        .arg_from_usage("[ID|QUERY]... 'Index bookmarks matching the specified ids or search keywords{n}\
+                         (If nothing is specified, index all bookmarks)'")
+        .arg_from_usage("--max-age=[days] 'Index again pages indexed more than days ago (default: 30)'")
+        .arg_from_usage("-f --force 'Index pages even when their text is fresh'")
+        .arg_from_usage("-j --workers=[workers] 'Number of pages fetched at once (default: 8)'")
+        .after_help("Pages never indexed or indexed more than --max-age days ago are fetched.")
+}
+
+pub fn execute(args: &ArgMatches) {
+    let db = DB::open();
+
+    let max_age = if args.is_present("max-age") {
+        value_t!(args, "max-age", u64).unwrap_or_else(|e| e.exit())
+    } else {
+        DEFAULT_MAX_AGE
+    };
+    let workers = if args.is_present("workers") {
+        value_t!(args, "workers", usize).unwrap_or_else(|e| e.exit())
+    } else {
+        DEFAULT_WORKERS
+    };
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let stale_before = now - (max_age * 24 * 60 * 60) as i64;
+
+    let bookmarks: Vec<_> = select_bookmarks(args, "ID|QUERY", &db).into_iter()
+        .filter(|b| args.is_present("force") || db.get_content_indexed(b.id) <= stale_before)
+        .collect();
+
+    if bookmarks.is_empty() {
+        println!("No pages to index");
+        process::exit(0);
+    }
+
+    let fetcher = Fetcher::load(DEFAULT_TIMEOUT);
+    let urls: Vec<String> = bookmarks.iter().map(|b| b.url.clone()).collect();
+    let texts = pool::map(urls, workers, |url| fetcher.get(&url).map(|page| page.text()));
+
+    let mut failed = 0;
+    for (bookmark, text) in bookmarks.iter().zip(texts) {
+        match text {
+            Ok(text) => db.set_content(bookmark.id, &text),
+            Err(e) => {
+                failed += 1;
+                println!("{} {}\n    {}\n", bookmark.id, bookmark.title, e);
+            }
+        }
+    }
+
+    println!("Indexed {} pages, {} failed", bookmarks.len() - failed, failed);
+}
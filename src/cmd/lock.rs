@@ -0,0 +1,13 @@
+use clap::{App, ArgMatches, SubCommand};
+
+use encryption::forget_key;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("lock")
+        .about("Forget the key kept by bkm unlock")
+}
+
+pub fn execute(_args: &ArgMatches) {
+    forget_key();
+    println!("Locked");
+}
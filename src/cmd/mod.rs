@@ -1,3 +1,19 @@
+use clap::{App, Arg, ArgMatches};
+use std::io::{self, Write};
+use std::process;
+
+use backup::create as create_backup;
+use bookmark::Bookmark;
+use config::Config;
+use database::DB;
+use fetch::{Fetcher, DEFAULT_TIMEOUT};
+use normalize::host;
+use pager;
+use pool;
+use query::{ListOptions, Query, SortKey};
+use reqwest::Url;
+use ui;
+
 pub mod print;
 pub mod add;
 pub mod delete;
@@ -5,3 +21,217 @@ pub mod update;
 pub mod open;
 pub mod search;
 pub mod import;
+pub mod export;
+pub mod saved;
+pub mod tui;
+pub mod edit;
+pub mod check;
+pub mod dedupe;
+pub mod archive;
+pub mod index;
+pub mod refresh;
+pub mod serve;
+pub mod native_host;
+pub mod sync;
+pub mod encrypt;
+pub mod decrypt;
+pub mod unlock;
+pub mod lock;
+pub mod trash;
+pub mod undo;
+pub mod history;
+pub mod revert;
+pub mod backup;
+
+// Back the library up before operation, exiting when it fails.
+pub fn backup_before(db: &DB, operation: &str) {
+    if let Err(e) = create_backup(db, operation) {
+        println!("{}", e);
+        process::exit(1);
+    }
+}
+
+// Options shared by subcommands listing many bookmarks.
+pub fn list_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(Arg::from_usage("--sort=[key] 'Sort bookmarks by key'")
+            .possible_values(SortKey::NAMES))
+        .arg_from_usage("--reverse 'Reverse sort order'")
+        .arg_from_usage("--limit=[limit] 'Print at most limit bookmarks'")
+        .arg_from_usage("--offset=[offset] 'Skip offset bookmarks'")
+        .arg_from_usage("-v --verbose 'Print description and notes'")
+        .arg_from_usage("--no-pager 'Do not pipe output into $PAGER'")
+}
+
+pub fn list_options(args: &ArgMatches) -> ListOptions {
+    let sort = args.value_of("sort").unwrap_or("id").parse().unwrap();
+
+    let limit = if args.is_present("limit") {
+        Some(value_t!(args, "limit", i64).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    };
+
+    let offset = if args.is_present("offset") {
+        Some(value_t!(args, "offset", i64).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    };
+
+    ListOptions::new(sort, args.is_present("reverse"), limit, offset)
+}
+
+pub fn print_list(args: &ArgMatches, bookmarks: Vec<Bookmark>) {
+    if bookmarks.is_empty() {
+        println!("Error: No matching any bookmark");
+        process::exit(1);
+    }
+
+    let output: String = if args.is_present("verbose") {
+        bookmarks.iter().map(|b| format!("{:#}\n", b)).collect()
+    } else {
+        bookmarks.iter().map(|b| format!("{}\n", b)).collect()
+    };
+    pager::page(&output, !args.is_present("no-pager"));
+}
+
+// Query of search keywords, where tag:<tag> searches tag and @<name> runs
+// saved search. Every keyword is a tag when tag is set.
+pub fn keywords_query(db: &DB, keywords: &[&str], tag: bool) -> Query {
+    let mut query = Query::new(Vec::new(), Vec::new());
+
+    for keyword in keywords {
+        if let Some(name) = keyword.strip_prefix('@') {
+            match db.get_saved_search(name) {
+                Ok(saved) => {
+                    let saved = Query::parse(&saved);
+                    query.keywords.extend(saved.keywords);
+                    query.tags.extend(saved.tags);
+                },
+                Err(e) => {
+                    println!("{} \"{}\"", e, name);
+                    process::exit(1);
+                }
+            }
+        } else if let Some(t) = keyword.strip_prefix("tag:") {
+            query.tags.push(t.to_string());
+        } else if tag {
+            query.tags.push(keyword.to_string());
+        } else {
+            query.keywords.push(keyword.to_string());
+        }
+    }
+
+    query
+}
+
+// Bookmarks given as ids or as search keywords in arg, all when it is empty.
+pub fn select_bookmarks(args: &ArgMatches, arg: &str, db: &DB) -> Vec<Bookmark> {
+    let values: Vec<&str> = args.values_of(arg).map(|v| v.collect()).unwrap_or_default();
+    let ids: Vec<i64> = values.iter().filter_map(|v| v.parse().ok()).collect();
+
+    if values.is_empty() || ids.len() < values.len() {
+        let query = keywords_query(db, &values, false);
+        return db.search_query(&query, &ListOptions::default());
+    }
+
+    let mut bookmarks: Vec<Bookmark> = Vec::new();
+    for id in ids {
+        match db.get_bookmark_by_id(id) {
+            Ok(b) => bookmarks.push(b),
+            Err(e) => {
+                println!("{} {}", e, id);
+                process::exit(1);
+            }
+        }
+    }
+
+    bookmarks
+}
+
+// Fetch and cache the icons of the sites of pages, given with the URL of
+// their <link rel=icon>, skipping sites looked up before.
+pub fn fetch_favicons(db: &DB, pages: Vec<(String, Option<String>)>) {
+    let mut hosts: Vec<String> = Vec::new();
+    let mut lookups: Vec<(String, Url, Option<String>)> = Vec::new();
+
+    for (url, icon_url) in pages {
+        let (host, url) = match (host(&url), Url::parse(&url)) {
+            (Some(host), Ok(url)) => (host, url),
+            _ => continue,
+        };
+        if hosts.contains(&host) || db.has_favicon(&host) {
+            continue;
+        }
+        hosts.push(host.clone());
+        lookups.push((host, url, icon_url));
+    }
+
+    let fetcher = Fetcher::load(DEFAULT_TIMEOUT);
+    let icons = pool::map(lookups, 8, |(host, url, icon_url)| {
+        (host, fetcher.favicon(&url, icon_url.as_deref()))
+    });
+
+    // Hosts which could not be reached are looked up again next time.
+    for (host, icon) in icons {
+        match icon {
+            Ok(Some(icon)) => db.set_favicon(&host, &icon.mime, &icon.data),
+            Ok(None) => db.set_favicon(&host, "", b""),
+            Err(_) => (),
+        }
+    }
+}
+
+// Ask question until answered with y or n, no when just pressing enter.
+pub fn confirm(question: &str) -> bool {
+    loop {
+        print!("{} (y/N) ", question);
+        io::stdout().flush().unwrap();
+
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer).expect("Failed to read line") == 0 {
+            return false;
+        }
+
+        match answer.trim() {
+            "Y" | "y" => return true,
+            "N" | "n" | "" => return false,
+            _ => continue,
+        }
+    }
+}
+
+// Options of subcommands acting on bookmarks picked interactively.
+pub fn pick_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg_from_usage("-p --pick 'Pick bookmarks interactively'")
+        .arg_from_usage("-q --query=[query] 'Pick from bookmarks matching search query'")
+}
+
+pub fn is_pick(args: &ArgMatches) -> bool {
+    args.is_present("pick") || args.is_present("query")
+}
+
+// Let the user pick bookmarks with the builtin picker, or with the command
+// set as `picker` in the config file.
+pub fn pick_ids(args: &ArgMatches, db: &DB) -> Vec<i64> {
+    let query = Query::parse(args.value_of("query").unwrap_or(""));
+    let bookmarks = db.search_query(&query, &ListOptions::default());
+
+    if bookmarks.is_empty() {
+        println!("Error: No matching any bookmark");
+        process::exit(1);
+    }
+
+    let result = match Config::load().picker {
+        Some(command) => ui::pick_external(&command, &bookmarks),
+        None => ui::pick(&bookmarks),
+    };
+
+    match result {
+        Ok(ref ids) if ids.is_empty() => process::exit(1),
+        Ok(ids) => ids,
+        Err(e) => {
+            println!("Error: Failed to pick bookmarks: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use dirs;
+use serde_json;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process;
+
+use database::DB;
+use native;
+use utils::bkm_dir;
+
+// Name browsers know the host by, extensions connect to it with.
+const HOST_NAME: &str = "bkm";
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("native-host")
+        .about("Talk to the browser extension over native messaging")
+        .arg(Arg::from_usage("--install=[browser] 'Register bkm as native messaging host of browser'")
+             .possible_values(&["firefox", "chromium", "chrome"]))
+        .arg_from_usage("--extension-id=[id] 'Id of the extension allowed to connect (required by --install)'")
+        // Browsers pass the manifest path and the extension to the host.
+        .arg(Arg::from_usage("[BROWSER_ARGS]... 'Arguments passed by the browser'").hidden(true))
+        .after_help("Without --install, answers length-prefixed JSON messages on stdin and stdout: \
+                     add, search, lookup and suggest.")
+}
+
+pub fn execute(args: &ArgMatches) {
+    if let Some(browser) = args.value_of("install") {
+        let id = match args.value_of("extension-id") {
+            Some(id) => id,
+            None => {
+                println!("Error: --install needs the --extension-id of the extension");
+                process::exit(1);
+            }
+        };
+        match install(browser, id) {
+            Ok(path) => println!("Installed native messaging host manifest {}", path.display()),
+            Err(e) => {
+                println!("{}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Nothing but messages may be written to stdout from here.
+    let db = DB::open_shared();
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    if let Err(e) = native::run(&db, &mut stdin.lock(), &mut stdout.lock()) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+// Directory browser reads native messaging host manifests of the user from.
+fn manifest_dir(browser: &str) -> PathBuf {
+    let home = dirs::home_dir().unwrap();
+    let dir = match (browser, cfg!(target_os = "macos")) {
+        ("firefox", false) => ".mozilla/native-messaging-hosts",
+        ("firefox", true) => "Library/Application Support/Mozilla/NativeMessagingHosts",
+        ("chromium", false) => ".config/chromium/NativeMessagingHosts",
+        ("chromium", true) => "Library/Application Support/Chromium/NativeMessagingHosts",
+        (_, false) => ".config/google-chrome/NativeMessagingHosts",
+        (_, true) => "Library/Application Support/Google/Chrome/NativeMessagingHosts",
+    };
+    home.join(dir)
+}
+
+// Manifest of the host started by the script at path.
+fn manifest(browser: &str, path: &str, id: &str) -> serde_json::Value {
+    let mut manifest = json!({
+        "name": HOST_NAME,
+        "description": "bkm bookmark manager",
+        "path": path,
+        "type": "stdio",
+    });
+    if browser == "firefox" {
+        manifest["allowed_extensions"] = json!([id]);
+    } else {
+        manifest["allowed_origins"] = json!([format!("chrome-extension://{}/", id)]);
+    }
+    manifest
+}
+
+// Write a script starting `bkm native-host`, as browsers run manifest paths
+// without arguments, and the manifest pointing at it.
+fn install(browser: &str, id: &str) -> Result<PathBuf, String> {
+    let exe = env::current_exe().map_err(|e| format!("Error: Failed to locate bkm: {}", e))?;
+
+    let mut script = bkm_dir();
+    script.push("native-host.sh");
+    let content = format!("#!/bin/sh\nexec '{}' native-host \"$@\"\n",
+                          exe.display().to_string().replace('\'', "'\\''"));
+    fs::write(&script, content)
+        .and_then(|_| fs::set_permissions(&script, fs::Permissions::from_mode(0o755)))
+        .map_err(|e| format!("Error: Failed to write {}: {}", script.display(), e))?;
+
+    let dir = manifest_dir(browser);
+    let path = dir.join(format!("{}.json", HOST_NAME));
+    let manifest = manifest(browser, &script.display().to_string(), id);
+    fs::create_dir_all(&dir)
+        .and_then(|_| fs::write(&path, serde_json::to_string_pretty(&manifest).unwrap()))
+        .map_err(|e| format!("Error: Failed to write {}: {}", path.display(), e))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let firefox = manifest("firefox", "/home/u/.bkm/native-host.sh", "bkm@example.com");
+        assert_eq!(firefox["allowed_extensions"], json!(["bkm@example.com"]));
+        assert_eq!(firefox["type"], "stdio");
+        assert!(firefox.get("allowed_origins").is_none());
+
+        let chromium = manifest("chromium", "/home/u/.bkm/native-host.sh", "abcdef");
+        assert_eq!(chromium["allowed_origins"], json!(["chrome-extension://abcdef/"]));
+        assert_eq!(chromium["name"], "bkm");
+    }
+}
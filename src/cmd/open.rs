@@ -1,20 +1,61 @@
-use clap::{App, ArgMatches, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::process;
 use webbrowser;
 
+use cmd::{is_pick, pick_args, pick_ids};
 use database::DB;
+use encryption::{runtime_dir, unseal_with_session, write_private, Header};
 
 pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
-    SubCommand::with_name("open")
+    let app = SubCommand::with_name("open")
         .about("Open bookmark")
-        .arg_from_usage("<ID>... 'Open bookmark matching the specified ids'")
+        .arg(Arg::from_usage("<ID>... 'Open bookmark matching the specified ids{n}\
+                             (If id is not specified, pick bookmarks)'").required(false))
+        .arg_from_usage("-a --archived 'Open the latest offline copy saved by archive'");
+
+    pick_args(app)
 }
 
 pub fn execute(args: &ArgMatches) {
     let db = DB::open();
 
-    let ids = values_t!(args, "ID", i64).unwrap();
+    let ids = if args.is_present("ID") && !is_pick(args) {
+        values_t!(args, "ID", i64).unwrap_or_else(|e| e.exit())
+    } else {
+        pick_ids(args, &db)
+    };
 
     for id in ids {
-        webbrowser::open(&db.get_url_by_id(id)).unwrap();
+        if args.is_present("archived") {
+            let path = match db.get_snapshots(id).into_iter().next() {
+                Some((path, _)) => path,
+                None => {
+                    println!("Error: Bookmark {} is not archived", id);
+                    process::exit(1);
+                }
+            };
+            let path = plain_snapshot(id, &path).unwrap_or_else(|e| {
+                println!("{}", e);
+                process::exit(1);
+            });
+            webbrowser::open(&path).unwrap();
+        } else {
+            webbrowser::open(&db.get_url_by_id(id)).unwrap();
+        }
+        db.visit_bookmark(id);
     }
 }
+
+// Path of the snapshot at path readable by a browser: encrypted ones are
+// decrypted into the runtime directory.
+fn plain_snapshot(id: i64, path: &str) -> Result<String, String> {
+    let data = fs::read(path).map_err(|e| format!("Error: Failed to read {}: {}", path, e))?;
+    if Header::parse(&data).is_err() {
+        return Ok(path.to_string());
+    }
+
+    let plain = runtime_dir().join(format!("snapshot-{}.html", id));
+    write_private(&plain, &unseal_with_session(&data)?)?;
+    Ok(plain.to_string_lossy().into_owned())
+}
@@ -1,14 +1,17 @@
 use clap::{App, Arg, ArgMatches, SubCommand};
 use std::process;
 
+use cmd::{list_args, list_options, print_list};
 use database::DB;
 
 pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
-    SubCommand::with_name("print")
+    let app = SubCommand::with_name("print")
         .about("Print bookmark")
         .arg(Arg::from_usage("<ID>... 'Print bookmarks matching the specified ids{n}\
                              (If id is not specified, print all bookmarks)'").required(false))
-        .arg_from_usage("-T 'Print all tags'")
+        .arg_from_usage("-T 'Print all tags'");
+
+    list_args(app)
 }
 
 pub fn execute(args: &ArgMatches) {
@@ -34,10 +37,8 @@ pub fn execute(args: &ArgMatches) {
             process::exit(1);
         }
 
-        let bookmarks = db.get_all_bookmark();
-        for bookmark in bookmarks {
-            bookmark.print();
-        }
+        let bookmarks = db.get_all_bookmark(&list_options(args));
+        print_list(args, bookmarks);
 
         process::exit(0);
     }
@@ -45,9 +46,8 @@ pub fn execute(args: &ArgMatches) {
     let ids = values_t!(args, "ID", i64).unwrap();
     for id in ids {
         match db.get_bookmark_by_id(id) {
-            Ok(b) => {
-                b.print();
-            },
+            Ok(ref b) if args.is_present("verbose") => println!("{:#}", b),
+            Ok(b) => b.print(),
             Err(e) => println!("{} {}", e, id),
         }
     }
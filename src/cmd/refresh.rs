@@ -0,0 +1,241 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use bookmark::Bookmark;
+use cmd::{confirm, fetch_favicons, select_bookmarks};
+use config::Config;
+use database::DB;
+use fetch::{FetchError, Fetcher, Metadata, DEFAULT_TIMEOUT};
+use normalize::host;
+use pool::{self, HostLimiter};
+use progress::Progress;
+
+// Wait before the first retry, doubled for every following one.
+const BACKOFF: Duration = Duration::from_millis(500);
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("refresh")
+        .about("Fetch titles and descriptions of bookmarks again")
+        .arg_from_usage("[ID|QUERY]... 'Refresh bookmarks matching the specified ids or search keywords{n}\
+                         (If nothing is specified, refresh all bookmarks)'")
+        .arg_from_usage("--untitled 'Only refresh bookmarks without a title or titled with their URL'")
+        .arg_from_usage("--pending 'Only refresh bookmarks added while their page could not be fetched'")
+        .arg_from_usage("-j --workers=[workers] 'Number of pages fetched at once (default: 8)'")
+        .arg_from_usage("--host-delay=[ms] 'Milliseconds between requests to the same host (default: 1000)'")
+        .arg_from_usage("--retries=[retries] 'Retries of pages failing temporarily (default: 2)'")
+        .arg_from_usage("-n --dry-run 'Report changes without saving them'")
+        .arg_from_usage("-y --yes 'Save changes without asking'")
+        .after_help("Defaults can be set in the [refresh] table of ~/.bkm/config.toml.")
+}
+
+// New title and description found for a bookmark, None when unchanged.
+struct Change<'a> {
+    bookmark: &'a Bookmark,
+    title: Option<String>,
+    description: Option<String>
+}
+
+pub fn execute(args: &ArgMatches) {
+    let db = DB::open();
+    let config = Config::load().refresh;
+
+    let workers = if args.is_present("workers") {
+        value_t!(args, "workers", usize).unwrap_or_else(|e| e.exit())
+    } else {
+        config.workers
+    };
+    let host_delay = if args.is_present("host-delay") {
+        value_t!(args, "host-delay", u64).unwrap_or_else(|e| e.exit())
+    } else {
+        config.host_delay
+    };
+    let retries = if args.is_present("retries") {
+        value_t!(args, "retries", u32).unwrap_or_else(|e| e.exit())
+    } else {
+        config.retries
+    };
+
+    let bookmarks: Vec<Bookmark> = select_bookmarks(args, "ID|QUERY", &db).into_iter()
+        .filter(|b| !args.is_present("untitled") || b.title.trim().is_empty() || b.title == b.url)
+        .filter(|b| !args.is_present("pending") || db.needs_metadata(b.id))
+        .collect();
+
+    if bookmarks.is_empty() {
+        println!("Error: No matching any bookmark");
+        process::exit(1);
+    }
+
+    let fetcher = Fetcher::load(DEFAULT_TIMEOUT);
+    let limiter = HostLimiter::new(Duration::from_millis(host_delay));
+    let progress = Progress::new(bookmarks.len());
+
+    let urls: Vec<String> = bookmarks.iter().map(|b| b.url.clone()).collect();
+    let results = pool::map(urls, workers, |url| {
+        let result = fetch_metadata(&fetcher, &limiter, &url, retries, BACKOFF);
+        progress.inc();
+        result
+    });
+    progress.finish();
+
+    let mut changes: Vec<Change> = Vec::new();
+    let mut fetched: Vec<i64> = Vec::new();
+    let mut icons: Vec<(String, Option<String>)> = Vec::new();
+    let mut failed = 0;
+    for (bookmark, result) in bookmarks.iter().zip(results) {
+        match result {
+            Ok(metadata) => {
+                fetched.push(bookmark.id);
+                icons.push((bookmark.url.clone(), metadata.icon_url.clone()));
+                let change = diff(bookmark, metadata);
+                if change.title.is_some() || change.description.is_some() {
+                    changes.push(change);
+                }
+            },
+            Err(e) => {
+                failed += 1;
+                println!("{} {}\n    {}\n", bookmark.id, bookmark.title, e);
+            }
+        }
+    }
+
+    for change in &changes {
+        println!("{} {}", change.bookmark.id, change.bookmark.url);
+        if let Some(ref title) = change.title {
+            println!("    title: {}\n        -> {}", change.bookmark.title, title);
+        }
+        if let Some(ref description) = change.description {
+            println!("    description: {}\n        -> {}", change.bookmark.description, description);
+        }
+        println!();
+    }
+
+    println!("Refreshed {} bookmarks, {} changed, {} failed",
+             bookmarks.len() - failed, changes.len(), failed);
+
+    if args.is_present("dry-run") {
+        return;
+    }
+    if !changes.is_empty() && !args.is_present("yes")
+        && !confirm(&format!("Save {} changes?", changes.len())) {
+        return;
+    }
+
+    for change in changes {
+        let bookmark = change.bookmark;
+        if let Some(title) = change.title {
+            if let Err(e) = db.update_bookmark(bookmark.id, &title, &bookmark.url) {
+                println!("{}", e);
+            }
+        }
+        if let Some(description) = change.description {
+            db.update_description(bookmark.id, &description);
+        }
+    }
+
+    for id in fetched {
+        db.set_needs_metadata(id, false);
+    }
+    // Icons of the sites not looked up yet, those of bookmarks added offline.
+    fetch_favicons(&db, icons);
+}
+
+// Metadata of the page at url, trying again with growing waits while the
+// failure may be temporary (timeouts, 429 and 5xx).
+fn fetch_metadata(fetcher: &Fetcher, limiter: &HostLimiter, url: &str,
+                  retries: u32, backoff: Duration) -> Result<Metadata, FetchError> {
+    let host = host(url).unwrap_or_default();
+    let mut attempt = 0;
+
+    loop {
+        limiter.wait(&host);
+        match fetcher.get(url) {
+            Ok(page) => {
+                let mut metadata = page.metadata();
+                // A title falling back to the URL is no title at all.
+                if metadata.title == page.url.as_str() {
+                    metadata.title.clear();
+                }
+                return Ok(metadata);
+            },
+            Err(ref e) if e.is_transient() && attempt < retries => {
+                thread::sleep(backoff * 2u32.pow(attempt));
+                attempt += 1;
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Fields of metadata differing from the bookmark, empty ones never replace saved ones.
+fn diff(bookmark: &Bookmark, metadata: Metadata) -> Change<'_> {
+    let title = Some(metadata.title)
+        .filter(|t| !t.is_empty() && *t != bookmark.title);
+    let description = metadata.description
+        .filter(|d| !d.is_empty() && *d != bookmark.description);
+
+    Change { bookmark, title, description }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use testserver::{serve, Response};
+
+    #[test]
+    fn test_fetch_metadata() {
+        let attempts = AtomicUsize::new(0);
+        let server = serve(move |req| match req.path.as_str() {
+            "/flaky" if attempts.fetch_add(1, Ordering::SeqCst) < 2 => Response::new(503, b""),
+            "/flaky" => Response::html("<title>Back</title>"),
+            "/untitled" => Response::html("<p>No title</p>"),
+            _ => Response::new(404, b""),
+        });
+
+        let fetcher = Fetcher::default();
+        let limiter = HostLimiter::new(Duration::from_millis(0));
+        let backoff = Duration::from_millis(10);
+
+        let metadata = fetch_metadata(&fetcher, &limiter, &server.url("/flaky"), 2, backoff).unwrap();
+        assert_eq!(metadata.title, "Back");
+        assert_eq!(server.requests().len(), 3);
+
+        let metadata = fetch_metadata(&fetcher, &limiter, &server.url("/untitled"), 2, backoff).unwrap();
+        assert_eq!(metadata.title, "");
+
+        // Client errors are not retried.
+        assert!(fetch_metadata(&fetcher, &limiter, &server.url("/missing"), 2, backoff).is_err());
+        assert_eq!(server.requests().len(), 5);
+    }
+
+    #[test]
+    fn test_diff() {
+        let bookmark = Bookmark {
+            id: 1,
+            title: "GitHub".to_string(),
+            url: "https://github.com/".to_string(),
+            tags: Vec::new(),
+            description: "Old".to_string(),
+            notes: String::new(),
+        };
+
+        let metadata = |title: &str, description: Option<&str>| Metadata {
+            title: title.to_string(),
+            description: description.map(|d| d.to_string()),
+            canonical_url: None,
+            icon_url: None,
+        };
+
+        let change = diff(&bookmark, metadata("GitHub", Some("Old")));
+        assert!(change.title.is_none() && change.description.is_none());
+
+        let change = diff(&bookmark, metadata("", None));
+        assert!(change.title.is_none() && change.description.is_none());
+
+        let change = diff(&bookmark, metadata("GitHub: Let's build", Some("New")));
+        assert_eq!(change.title.as_deref(), Some("GitHub: Let's build"));
+        assert_eq!(change.description.as_deref(), Some("New"));
+    }
+}
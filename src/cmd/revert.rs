@@ -0,0 +1,27 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::process;
+
+use database::DB;
+use history::revert;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("revert")
+        .about("Set a bookmark back to a revision of its history")
+        .arg_from_usage("<ID> 'Bookmark id'")
+        .arg_from_usage("--to=<rev> 'Revision printed by bkm history, 0 for before the first change'")
+}
+
+pub fn execute(args: &ArgMatches) {
+    let id = value_t!(args, "ID", i64).unwrap_or_else(|e| e.exit());
+    let rev = value_t!(args, "to", i64).unwrap_or_else(|e| e.exit());
+    let db = DB::open();
+
+    match revert(&db, id, rev) {
+        Ok(0) => println!("Bookmark {} is already as at revision {}", id, rev),
+        Ok(count) => println!("Reverted {} fields of bookmark {} to revision {}", count, id, rev),
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        },
+    }
+}
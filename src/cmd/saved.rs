@@ -0,0 +1,64 @@
+use clap::{App, AppSettings, ArgMatches, SubCommand};
+use std::process;
+
+use database::DB;
+use query::Query;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("saved")
+        .about("Manage saved searches")
+        .setting(AppSettings::SubcommandRequired)
+        .subcommand(SubCommand::with_name("add")
+            .about("Save search query")
+            .arg_from_usage("<NAME> 'Saved search name (run it with `bkm search @<NAME>`)'")
+            .arg_from_usage("<QUERY> 'Search keywords, tag:<tag> searches tag'"))
+        .subcommand(SubCommand::with_name("list")
+            .about("List saved searches"))
+        .subcommand(SubCommand::with_name("delete")
+            .about("Delete saved search")
+            .arg_from_usage("<NAME>... 'Delete saved searches matching the specified names'"))
+}
+
+pub fn execute(args: &ArgMatches) {
+    let db = DB::open();
+
+    match args.subcommand() {
+        ("add", Some(args)) => {
+            let name = args.value_of("NAME").unwrap();
+            let query = args.value_of("QUERY").unwrap();
+
+            if Query::parse(query).is_empty() {
+                println!("Error: Query is empty");
+                process::exit(1);
+            }
+
+            match db.add_saved_search(name, query) {
+                Ok(_) => println!("@{}\n    {}", name, query),
+                Err(e) => {
+                    println!("{}", e);
+                    process::exit(1);
+                }
+            }
+        },
+        ("list", Some(_)) => {
+            let saved = db.get_all_saved_search();
+            if saved.is_empty() {
+                println!("Error: Saved search does not exist");
+                process::exit(1);
+            }
+
+            for (name, query) in saved {
+                println!("@{}\n    {}\n", name, query);
+            }
+        },
+        ("delete", Some(args)) => {
+            for name in args.values_of("NAME").unwrap() {
+                match db.delete_saved_search(name) {
+                    Ok(_) => println!("Saved search \"{}\" deleted", name),
+                    Err(e) => println!("{} \"{}\"", e, name),
+                }
+            }
+        },
+        _ => process::exit(1),
+    }
+}
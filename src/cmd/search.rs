@@ -1,33 +1,26 @@
 use clap::{App, ArgMatches, SubCommand};
-use std::process;
 
+use cmd::{keywords_query, list_args, list_options, print_list};
 use database::DB;
 
 pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
-    SubCommand::with_name("search")
+    let app = SubCommand::with_name("search")
         .about("Search bookmark")
-        .arg_from_usage("<KEYWORD>... 'Search bookmarks with keywords in title or URL'")
+        .arg_from_usage("<KEYWORD>... 'Search bookmarks with keywords in title or URL{n}\
+                         (tag:<tag> searches tag, @<name> runs saved search)'")
         .arg_from_usage("-t --tag 'Search bookmark with tag'")
+        .arg_from_usage("-c --content 'Search text of pages indexed by add --content or index too'");
+
+    list_args(app)
 }
 
 pub fn execute(args: &ArgMatches) {
     let db = DB::open();
 
-    let keywords: Vec<&str> = args.values_of("KEYWORD")
-        .unwrap().collect();
-
-    let bookmarks = if args.is_present("tag") {
-        db.search_by_tag(keywords)
-    } else {
-        db.search(keywords)
-    };
-
-    if bookmarks.len() == 0 {
-        println!("Error: No matching any bookmark");
-        process::exit(1);
-    }
+    let keywords: Vec<&str> = args.values_of("KEYWORD").unwrap().collect();
+    let mut query = keywords_query(&db, &keywords, args.is_present("tag"));
+    query.content = args.is_present("content");
 
-    for bookmark in bookmarks {
-        bookmark.print();
-    }
+    let bookmarks = db.search_query(&query, &list_options(args));
+    print_list(args, bookmarks);
 }
@@ -0,0 +1,76 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::TcpListener;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+
+use api::Api;
+use database::DB;
+use http::serve;
+use utils::{bkm_dir, random_hex};
+
+const DEFAULT_BIND: &str = "127.0.0.1:8990";
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("serve")
+        .about("Serve bookmarks over a local JSON API and web interface")
+        .arg_from_usage("-b --bind=[address] 'Address to listen on (default: 127.0.0.1:8990)'")
+        .arg_from_usage("--token=[token] 'Token clients have to send (default: from ~/.bkm/token)'")
+        .after_help("API clients authenticate with an \"Authorization: Bearer <token>\" header, \
+                     the web interface at / asks for the token once. \
+                     The token is generated into ~/.bkm/token on first use.")
+}
+
+pub fn execute(args: &ArgMatches) {
+    let db = DB::open_shared();
+
+    let token = match args.value_of("token") {
+        Some(token) => token.to_string(),
+        None => load_token(),
+    };
+
+    let bind = args.value_of("bind").unwrap_or(DEFAULT_BIND);
+    let listener = match TcpListener::bind(bind) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("Error: Failed to listen on {}: {}", bind, e);
+            process::exit(1);
+        }
+    };
+
+    println!("Serving bookmarks on http://{}/", listener.local_addr().unwrap());
+    let api = Arc::new(Api::new(db, &token));
+    serve(listener, move |req| api.handle(req));
+}
+
+fn token_path() -> PathBuf {
+    let mut path = bkm_dir();
+    path.push("token");
+    path
+}
+
+// Token stored in ~/.bkm/token, readable by the user only, generated when missing.
+pub fn load_token() -> String {
+    let path = token_path();
+
+    if let Ok(token) = fs::read_to_string(&path) {
+        if !token.trim().is_empty() {
+            return token.trim().to_string();
+        }
+    }
+
+    let token = random_hex(16);
+
+    let written = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600)
+        .open(&path)
+        .and_then(|mut f| writeln!(f, "{}", token));
+    if let Err(e) = written {
+        println!("Error: Failed to write {}: {}", path.display(), e);
+        process::exit(1);
+    }
+
+    token
+}
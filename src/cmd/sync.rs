@@ -0,0 +1,90 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::process;
+
+use config::Config;
+use database::DB;
+use oplog;
+use sync::{sync, Repo, Report};
+use utils::bkm_dir;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    let target = "[TARGET] 'Directory shared by the devices or URL of a bkm serve (default: target of [sync] in config)'";
+    let token = "--token=[token] 'Token of the bkm serve at TARGET (default: token of [sync] in config)'";
+
+    SubCommand::with_name("sync")
+        .about("Sync bookmarks with other machines through a git repository")
+        .arg_from_usage("-r --remote=[url] 'Git repository to sync with (default: remote of [sync] in config)'")
+        .arg_from_usage("-b --branch=[branch] 'Branch to sync (default: main)'")
+        .subcommand(SubCommand::with_name("push")
+            .about("Send the operation log of this machine")
+            .arg_from_usage(target)
+            .arg_from_usage(token))
+        .subcommand(SubCommand::with_name("pull")
+            .about("Apply the operations logged by other machines")
+            .arg_from_usage(target)
+            .arg_from_usage(token))
+        .after_help("Bookmarks are written one per line to bookmarks.txt in ~/.bkm/sync, \
+                     merged with the changes of the remote since the last sync, and pushed back.\n\n\
+                     push and pull exchange the log of every change made to bookmarks instead, \
+                     through a directory or a bkm serve. The last change of each field wins, \
+                     so machines pulling the same changes end with the same bookmarks.")
+}
+
+pub fn execute(args: &ArgMatches) {
+    let config = Config::load();
+    let db = DB::open();
+
+    match args.subcommand() {
+        ("push", Some(args)) => {
+            let target = target(args, &config);
+            match oplog::push(&db, &target, args.value_of("token").or(config.sync.token.as_deref())) {
+                Ok(count) => println!("Pushed {} operations to {}", count, target),
+                Err(e) => exit(&e),
+            }
+        },
+        ("pull", Some(args)) => {
+            let target = target(args, &config);
+            match oplog::pull(&db, &target, args.value_of("token").or(config.sync.token.as_deref())) {
+                Ok((count, report)) => {
+                    println!("Pulled {} new operations from {}", count, target);
+                    print_report(&report);
+                },
+                Err(e) => exit(&e),
+            }
+        },
+        _ => {
+            let mut dir = bkm_dir();
+            dir.push("sync");
+            let repo = Repo::new(&dir);
+
+            // The remote given once is kept by the working tree.
+            let remote = args.value_of("remote").or(config.sync.remote.as_deref());
+            let branch = args.value_of("branch").unwrap_or(&config.sync.branch);
+
+            match sync(&db, &repo, remote, branch) {
+                Ok(report) => print_report(&report),
+                Err(e) => exit(&e),
+            }
+        },
+    }
+}
+
+fn target(args: &ArgMatches, config: &Config) -> String {
+    match args.value_of("TARGET").or(config.sync.target.as_deref()) {
+        Some(target) => target.to_string(),
+        None => exit("Error: No target, pass a directory or URL or set target in the [sync] table \
+                      of ~/.bkm/config.toml"),
+    }
+}
+
+fn print_report(report: &Report) {
+    for conflict in &report.conflicts {
+        println!("Conflict: {}", conflict);
+    }
+    println!("Synced: {} added, {} updated, {} deleted", report.added, report.updated, report.deleted);
+}
+
+fn exit(error: &str) -> ! {
+    println!("{}", error);
+    process::exit(1);
+}
@@ -0,0 +1,75 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::fs;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use cmd::confirm;
+use database::DB;
+use pager;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("trash")
+        .about("List, restore or empty deleted bookmarks")
+        .subcommand(SubCommand::with_name("list")
+            .about("Print deleted bookmarks, last deleted first")
+            .arg_from_usage("--no-pager 'Do not pipe output into $PAGER'"))
+        .subcommand(SubCommand::with_name("restore")
+            .about("Put deleted bookmarks back")
+            .arg_from_usage("<ID>... 'Ids of the trash printed by bkm trash list'"))
+        .subcommand(SubCommand::with_name("empty")
+            .about("Delete the bookmarks of the trash for good")
+            .arg_from_usage("-d --days=[days] 'Only those deleted more than days ago'")
+            .arg_from_usage("-y --yes 'Do not ask for confirmation'"))
+        .after_help("bkm delete moves bookmarks to the trash, with their tags and snapshots. \
+                     Snapshot files are removed when the trash is emptied.")
+}
+
+pub fn execute(args: &ArgMatches) {
+    let db = DB::open();
+
+    match args.subcommand() {
+        ("restore", Some(args)) => {
+            let ids = values_t!(args, "ID", i64).unwrap_or_else(|e| e.exit());
+            for id in ids {
+                match db.restore_bookmark(id) {
+                    Ok(restored) => println!("Restored as index {}", restored),
+                    Err(e) => println!("{} {}", e, id),
+                }
+            }
+        },
+        ("empty", Some(args)) => {
+            let before = if args.is_present("days") {
+                let days = value_t!(args, "days", i64).unwrap_or_else(|e| e.exit());
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+                Some(now - days * 24 * 60 * 60)
+            } else {
+                None
+            };
+
+            if !args.is_present("yes") && !confirm("Delete the bookmarks of the trash for good?") {
+                process::exit(1);
+            }
+
+            let count = db.get_trash().len();
+            for path in db.empty_trash(before) {
+                let _ = fs::remove_file(path);
+            }
+            println!("{} bookmarks deleted", count - db.get_trash().len());
+        },
+        (_, args) => {
+            let trash = db.get_trash();
+            if trash.is_empty() {
+                println!("Trash is empty");
+                return;
+            }
+
+            let output: String = trash.into_iter()
+                .map(|mut entry| {
+                    entry.bookmark.id = entry.id;
+                    format!("{}    Deleted: {}\n", entry.bookmark, entry.deleted)
+                })
+                .collect();
+            pager::page(&output, !args.is_some_and(|a| a.is_present("no-pager")));
+        },
+    }
+}
@@ -0,0 +1,19 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::process;
+
+use database::DB;
+use ui;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("tui")
+        .about("Browse bookmarks in terminal UI")
+}
+
+pub fn execute(_args: &ArgMatches) {
+    let db = DB::open_shared();
+
+    if let Err(e) = ui::run(&db) {
+        println!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,36 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::process;
+
+use database::DB;
+use journal::undo;
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("undo")
+        .about("Revert the changes of the last command")
+        .arg_from_usage("-l --list 'Print the commands which can be undone, last first'")
+        .after_help("The changes of the last 50 commands are kept, each undo reverting one more.")
+}
+
+pub fn execute(args: &ArgMatches) {
+    let db = DB::open();
+
+    if args.is_present("list") {
+        for (step, time, command) in db.get_steps() {
+            println!("{} {}  bkm {}", step, time, command);
+        }
+        return;
+    }
+
+    match undo(&db) {
+        Some((command, errors)) => {
+            println!("Undid \"bkm {}\"", command);
+            for e in errors {
+                println!("{}", e);
+            }
+        },
+        None => {
+            println!("Nothing to undo");
+            process::exit(1);
+        },
+    }
+}
@@ -0,0 +1,35 @@
+use clap::{App, ArgMatches, SubCommand};
+use std::process;
+
+use encryption::{cache_key, is_encrypted, unlock, DEFAULT_TIMEOUT};
+
+pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("unlock")
+        .about("Keep the key of the encrypted library for a while")
+        .arg_from_usage("-t --timeout=[minutes] 'Minutes to keep the key (default: 15)'")
+        .arg_from_usage("-k --keyfile=[path] 'Keyfile the library was encrypted with'")
+        .after_help("The key is kept in the runtime directory of the user until the timeout \
+                     or bkm lock.")
+}
+
+pub fn execute(args: &ArgMatches) {
+    if !is_encrypted() {
+        println!("Error: Library is not encrypted");
+        process::exit(1);
+    }
+    let timeout = if args.is_present("timeout") {
+        value_t!(args, "timeout", u64).unwrap_or_else(|e| e.exit())
+    } else {
+        DEFAULT_TIMEOUT
+    };
+
+    let unlocked = unlock(args.value_of("keyfile"))
+        .and_then(|(header, key, _)| cache_key(&header, &key, timeout));
+    match unlocked {
+        Ok(()) => println!("Unlocked for {} minutes", timeout),
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -1,54 +1,83 @@
-use clap::{App, ArgMatches, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::process;
 
-use bookmark::Bookmark;
+use cmd::{is_pick, pick_args, pick_ids};
 use database::DB;
-
-use utils::get_title_from_url;
+use fetch::{Fetcher, DEFAULT_TIMEOUT};
+use normalize::normalize_url;
 
 pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
-    SubCommand::with_name("update")
+    let app = SubCommand::with_name("update")
         .about("Update bookmark")
-        .arg_from_usage("<ID> 'Update bookmark matching the specified id'")
+        .arg(Arg::from_usage("<ID> 'Update bookmark matching the specified id{n}\
+                             (If id is not specified, pick bookmarks)'").required(false))
         .arg_from_usage("-u --url=[url] 'Update bookmark URL'")
         .arg_from_usage("-i --title=[title] 'Update bookmark title'")
         .arg_from_usage("-t --tag=[tag]... 'Update bookmark tags'")
-        .after_help("If no option is specified, get title from url and update.")
+        .arg_from_usage("-n --note=[note] 'Update bookmark notes'")
+        .after_help("If no option is specified, get title and description from url and update.");
+
+    pick_args(app)
 }
 
 pub fn execute(args: &ArgMatches) {
     let db = DB::open();
 
-    let id = value_t!(args, "ID", i64).unwrap();
-
-    let url = if let Some(url) = args.value_of("url") {
-        url.to_string()
+    let ids = if args.is_present("ID") && !is_pick(args) {
+        vec![value_t!(args, "ID", i64).unwrap_or_else(|e| e.exit())]
     } else {
-        db.get_url_by_id(id)
+        pick_ids(args, &db)
     };
 
-    let title = if let Some(title) = args.value_of("title") {
-        title.to_string()
-    } else if ! args.is_present("title") &&
-              ! args.is_present("url") &&
-              ! args.is_present("tag") {
-        get_title_from_url(&url)
-    } else {
-        db.get_title_by_id(id)
-    };
+    // A bookmark failing does not keep the others from being updated.
+    let mut failed = false;
+    for id in ids {
+        if let Err(e) = update(&db, args, id) {
+            println!("{}", e);
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn update(db: &DB, args: &ArgMatches, id: i64) -> Result<(), String> {
+    let mut bookmark = db.get_bookmark_by_id(id).map_err(|e| format!("{} {}", e, id))?;
+    let mut description = None;
 
-    let mut tags: Vec<String> = Vec::new();
+    if let Some(url) = args.value_of("url") {
+        bookmark.url = normalize_url(url);
+    }
+
+    if let Some(title) = args.value_of("title") {
+        bookmark.title = title.to_string();
+    } else if ! args.is_present("url") &&
+              ! args.is_present("tag") &&
+              ! args.is_present("note") {
+        let metadata = Fetcher::load(DEFAULT_TIMEOUT).fetch(&bookmark.url)?;
+        bookmark.title = metadata.title;
+        description = metadata.description;
+    }
+
+    // The URL may be taken, nothing else is saved then.
+    db.update_bookmark(bookmark.id, &bookmark.title, &bookmark.url).map_err(|e| e.to_string())?;
+
+    if let Some(description) = description {
+        db.update_description(id, &description);
+        bookmark.description = description;
+    }
 
     if let Some(tag) = args.values_of("tag") {
-        db.delete_bookmark_tag_by_id(id);
-        for t in tag {
-            db.add_tag(id, t);
-            tags.push(t.to_string());
-        }
-    } else {
-        tags = db.get_tags(id).unwrap();
+        bookmark.tags = tag.map(|t| t.to_string()).collect();
+        db.set_tags(id, &bookmark.tags);
+    }
+
+    if let Some(note) = args.value_of("note") {
+        bookmark.notes = note.to_string();
+        db.update_notes(id, note);
     }
 
-    let bookmark = Bookmark::new(id, title, url, tags);
-    db.update_bookmark(bookmark.id, &bookmark.title, &bookmark.url);
-    bookmark.print();
+    println!("{:#}", bookmark);
+    Ok(())
 }
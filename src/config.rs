@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+use toml;
+
+use utils::bkm_dir;
+
+// Settings read from ~/.bkm/config.toml, every field is optional.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    // Command picking bookmarks instead of the builtin picker (e.g. "fzf -m").
+    // Candidates are written to its stdin as "<id>\t<title>\t<url>\t<tags>",
+    // selected lines are read from its stdout.
+    pub picker: Option<String>,
+    pub refresh: RefreshConfig,
+    pub network: NetworkConfig,
+    pub sync: SyncConfig,
+    pub encryption: EncryptionConfig,
+    pub backup: BackupConfig
+}
+
+// [network] table, how pages are fetched.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct NetworkConfig {
+    // Proxy for every request, instead of $HTTPS_PROXY and $HTTP_PROXY.
+    pub proxy: Option<String>,
+    // Hosts reached without the proxy (e.g. "localhost", ".corp.example.com"),
+    // instead of $NO_PROXY.
+    pub no_proxy: Option<Vec<String>>,
+    // Headers sent to hosts matching a pattern, "*.example.com" matching
+    // example.com and its subdomains.
+    pub headers: BTreeMap<String, BTreeMap<String, String>>,
+    // Cookie jar in the Netscape format, as exported by browsers and curl.
+    pub cookies: Option<String>,
+    // Client certificate as a PKCS #12 archive, with its password.
+    pub client_cert: Option<String>,
+    pub client_cert_password: Option<String>,
+    // PEM certificate of an authority trusted besides the system ones.
+    pub ca_cert: Option<String>
+}
+
+// [refresh] table, defaults of the refresh subcommand options.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RefreshConfig {
+    // Number of pages fetched at once.
+    pub workers: usize,
+    // Milliseconds between two requests to the same host.
+    pub host_delay: u64,
+    // Attempts after a failure which may be temporary.
+    pub retries: u32
+}
+
+impl Default for RefreshConfig {
+    fn default() -> RefreshConfig {
+        RefreshConfig { workers: 8, host_delay: 1000, retries: 2 }
+    }
+}
+
+// [encryption] table, how the encrypted library is unlocked.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    // File whose content is the secret, instead of a passphrase.
+    pub keyfile: Option<String>
+}
+
+// [backup] table, where backups of the library go and how many are kept.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    // Directory of the backups, ~/.bkm/backups by default.
+    pub dir: Option<String>,
+    // Number of backups kept, the oldest being removed.
+    pub keep: usize,
+    // Hours between two scheduled backups, 0 for none.
+    pub interval: u64
+}
+
+impl Default for BackupConfig {
+    fn default() -> BackupConfig {
+        BackupConfig { dir: None, keep: 10, interval: 24 }
+    }
+}
+
+// [sync] table, the git repository the library is synced through and the
+// target of `sync push` and `sync pull`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    // Remote repository, any URL git accepts.
+    pub remote: Option<String>,
+    pub branch: String,
+    // Directory shared by the devices or URL of a `bkm serve`, with its token.
+    pub target: Option<String>,
+    pub token: Option<String>
+}
+
+impl Default for SyncConfig {
+    fn default() -> SyncConfig {
+        SyncConfig { remote: None, branch: "main".to_string(), target: None, token: None }
+    }
+}
+
+impl Config {
+    pub fn load() -> Config {
+        let mut path = bkm_dir();
+        path.push("config.toml");
+
+        let mut content = String::new();
+        match File::open(&path) {
+            Ok(mut file) => {
+                file.read_to_string(&mut content).expect("Failed to read config file");
+            },
+            Err(_) => return Config::default(),
+        }
+
+        match Config::parse(&content) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Error: Invalid config file {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert!(Config::parse("").unwrap().picker.is_none());
+        assert_eq!(Config::parse("picker = \"fzf -m\"").unwrap().picker,
+                   Some("fzf -m".to_string()));
+        assert!(Config::parse("picker = 1").is_err());
+    }
+
+    #[test]
+    fn test_parse_network() {
+        let config = Config::parse("").unwrap();
+        assert!(config.network.proxy.is_none() && config.network.headers.is_empty());
+
+        let config = Config::parse("[network]\n\
+                                    proxy = \"http://proxy:3128\"\n\
+                                    no_proxy = [\"localhost\"]\n\
+                                    [network.headers.\"*.example.com\"]\n\
+                                    Authorization = \"Bearer token\"").unwrap();
+        assert_eq!(config.network.proxy.as_deref(), Some("http://proxy:3128"));
+        assert_eq!(config.network.no_proxy, Some(vec!["localhost".to_string()]));
+        assert_eq!(config.network.headers["*.example.com"]["Authorization"], "Bearer token");
+    }
+
+    #[test]
+    fn test_parse_refresh() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.refresh.workers, 8);
+        assert_eq!(config.refresh.retries, 2);
+
+        let config = Config::parse("[refresh]\nworkers = 2\nhost_delay = 0").unwrap();
+        assert_eq!(config.refresh.workers, 2);
+        assert_eq!(config.refresh.host_delay, 0);
+        assert_eq!(config.refresh.retries, 2);
+    }
+
+    #[test]
+    fn test_parse_sync() {
+        let config = Config::parse("").unwrap();
+        assert!(config.sync.remote.is_none());
+        assert_eq!(config.sync.branch, "main");
+
+        let config = Config::parse("[sync]\nremote = \"git@example.com:bookmarks.git\"").unwrap();
+        assert_eq!(config.sync.remote.as_deref(), Some("git@example.com:bookmarks.git"));
+        assert_eq!(config.sync.branch, "main");
+
+        let config = Config::parse("[sync]\ntarget = \"http://nas:8990\"\ntoken = \"abc\"").unwrap();
+        assert_eq!(config.sync.target.as_deref(), Some("http://nas:8990"));
+        assert_eq!(config.sync.token.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn test_parse_backup() {
+        let config = Config::parse("").unwrap();
+        assert_eq!((config.backup.keep, config.backup.interval), (10, 24));
+
+        let config = Config::parse("[backup]\nkeep = 3\ninterval = 0").unwrap();
+        assert_eq!((config.backup.keep, config.backup.interval), (3, 0));
+        assert!(config.backup.dir.is_none());
+    }
+}
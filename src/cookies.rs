@@ -0,0 +1,96 @@
+use reqwest::Url;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+struct Cookie {
+    domain: String,
+    // Whether subdomains of domain get the cookie too.
+    subdomains: bool,
+    path: String,
+    secure: bool,
+    // Seconds since the epoch, 0 for session cookies.
+    expires: i64,
+    name: String,
+    value: String
+}
+
+// Cookies read from a jar in the Netscape format written by browser
+// extensions and curl -c, one tab separated cookie per line:
+// domain, subdomains, path, secure, expires, name, value.
+pub struct CookieJar {
+    cookies: Vec<Cookie>
+}
+
+impl CookieJar {
+    pub fn parse(content: &str) -> CookieJar {
+        let mut cookies: Vec<Cookie> = Vec::new();
+
+        for line in content.lines() {
+            // curl marks HttpOnly cookies with a prefix looking like a comment.
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+            if fields.len() != 7 {
+                continue;
+            }
+            cookies.push(Cookie {
+                domain: fields[0].trim_start_matches('.').to_lowercase(),
+                subdomains: fields[1].eq_ignore_ascii_case("TRUE"),
+                path: fields[2].to_string(),
+                secure: fields[3].eq_ignore_ascii_case("TRUE"),
+                expires: fields[4].parse().unwrap_or(0),
+                name: fields[5].to_string(),
+                value: fields[6].to_string(),
+            });
+        }
+
+        CookieJar { cookies }
+    }
+
+    // Value of the Cookie header sent with a request to url, None when no
+    // cookie applies.
+    pub fn header(&self, url: &Url) -> Option<String> {
+        let host = url.host_str()?.to_lowercase();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+
+        let pairs: Vec<String> = self.cookies.iter()
+            .filter(|c| c.expires == 0 || c.expires > now)
+            .filter(|c| !c.secure || url.scheme() == "https")
+            .filter(|c| {
+                host == c.domain || (c.subdomains && host.ends_with(&format!(".{}", c.domain)))
+            })
+            .filter(|c| url.path().starts_with(&c.path))
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect();
+
+        if pairs.is_empty() {
+            None
+        } else {
+            Some(pairs.join("; "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let jar = CookieJar::parse("# Netscape HTTP Cookie File\n\
+                                    \n\
+                                    .example.com\tTRUE\t/\tFALSE\t0\tsession\tabc\n\
+                                    #HttpOnly_wiki.example.com\tFALSE\t/docs\tTRUE\t0\tauth\txyz\n\
+                                    example.com\tFALSE\t/\tFALSE\t1\texpired\t1\n\
+                                    broken line\n");
+        let header = |url: &str| jar.header(&Url::parse(url).unwrap());
+
+        assert_eq!(header("http://example.com/"), Some("session=abc".to_string()));
+        assert_eq!(header("https://wiki.example.com/docs/a"), Some("session=abc; auth=xyz".to_string()));
+        assert_eq!(header("http://wiki.example.com/docs/a"), Some("session=abc".to_string()));
+        assert_eq!(header("https://wiki.example.com/"), Some("session=abc".to_string()));
+        assert_eq!(header("https://github.com/"), None);
+    }
+}
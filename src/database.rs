@@ -1,25 +1,210 @@
 use rusqlite;
-use rusqlite::Connection;
-use dirs;
-use std::fs;
+use rusqlite::{Connection, DatabaseName, Row};
+use rusqlite::backup::Progress;
+use rusqlite::types::ToSql;
+use serde_json;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use backup;
 use bookmark::Bookmark;
+use encryption;
+use history::{encode_tags, Revision};
+use journal::{Action, JOURNAL_STEPS};
+use normalize::url_key;
+use oplog::Op;
+use query::{ListOptions, Query};
+use utils::{data_uri, parse_data_uri, random_hex};
+
+// Schema changes applied in order to databases created by older versions,
+// PRAGMA user_version records how many of them have been applied.
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE bookmarks ADD COLUMN created INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE bookmarks ADD COLUMN visited INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE bookmarks ADD COLUMN visits INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE bookmarks ADD COLUMN description TEXT NOT NULL DEFAULT '';
+     ALTER TABLE bookmarks ADD COLUMN notes TEXT NOT NULL DEFAULT '';",
+    "ALTER TABLE bookmarks ADD COLUMN status INTEGER;
+     ALTER TABLE bookmarks ADD COLUMN final_url TEXT;
+     ALTER TABLE bookmarks ADD COLUMN checked INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE bookmarks ADD COLUMN url_key TEXT NOT NULL DEFAULT '';
+     CREATE INDEX bookmarks_url_key ON bookmarks (url_key);",
+    "ALTER TABLE bookmarks ADD COLUMN content_indexed INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE bookmarks ADD COLUMN needs_metadata INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE bookmarks ADD COLUMN sync_key TEXT NOT NULL DEFAULT '';
+     CREATE INDEX bookmarks_sync_key ON bookmarks (sync_key);
+     CREATE TABLE oplog (
+        device    TEXT NOT NULL,
+        ts    INTEGER NOT NULL,
+        key    TEXT NOT NULL,
+        field    TEXT NOT NULL,
+        value    TEXT NOT NULL,
+        PRIMARY KEY (device, ts)
+     );
+     CREATE INDEX oplog_key ON oplog (key);
+     CREATE TABLE meta (
+        name    TEXT PRIMARY KEY,
+        value    TEXT NOT NULL
+     );",
+    "CREATE TABLE trash (
+        id    INTEGER PRIMARY KEY,
+        bookmark_id    INTEGER NOT NULL,
+        deleted    INTEGER NOT NULL,
+        data    TEXT NOT NULL
+     );
+     CREATE TABLE journal (
+        step    INTEGER PRIMARY KEY,
+        time    INTEGER NOT NULL,
+        command    TEXT NOT NULL
+     );
+     CREATE TABLE journal_actions (
+        step    INTEGER NOT NULL,
+        action    TEXT NOT NULL
+     );",
+    "CREATE TABLE history (
+        bookmark_id    INTEGER NOT NULL,
+        rev    INTEGER NOT NULL,
+        time    INTEGER NOT NULL,
+        field    TEXT NOT NULL,
+        old    TEXT NOT NULL,
+        new    TEXT NOT NULL,
+        PRIMARY KEY (bookmark_id, rev)
+     );",
+];
+
+// Columns read into Bookmark by bookmark_from_row.
+const BOOKMARK_COLUMNS: &str = "id, title, url, description, notes";
+
+// Bookmark kept in the trash, with what its row held besides its fields.
+#[derive(Serialize, Deserialize)]
+struct Trashed {
+    bookmark: Bookmark,
+    created: i64,
+    visited: i64,
+    visits: i64,
+    sync_key: String,
+    // Snapshot files with their creation time, removed with the trash.
+    snapshots: Vec<(String, i64)>,
+    // Rows of the history: rev, time, field, old and new values.
+    #[serde(default)]
+    history: Vec<(i64, i64, String, String, String)>
+}
+
+// Bookmark of the trash, by the id of its entry.
+pub struct TrashEntry {
+    pub id: i64,
+    // Deletion date, as "YYYY-MM-DD HH:MM:SS" in local time.
+    pub deleted: String,
+    pub bookmark: Bookmark
+}
+
+// Savepoint of a transaction, rolled back unless released, also when the
+// code in it panics.
+struct Savepoint<'a> {
+    conn: &'a Connection,
+    released: bool
+}
+
+impl<'a> Drop for Savepoint<'a> {
+    fn drop(&mut self) {
+        if !self.released {
+            let _ = self.conn.execute_batch("ROLLBACK TO tx; RELEASE tx");
+        }
+    }
+}
 
 pub struct DB {
-    conn: Connection
+    conn: Connection,
+    // Whether changes are recorded in the operation log and the journal, not
+    // while applying the operations of other devices.
+    logging: Cell<bool>,
+    // Whether changes are recorded in the journal, not while undoing.
+    journaling: Cell<bool>,
+    // Journal step of the changes of the running command, created by the
+    // first of them, and the command it is named after.
+    step: Cell<Option<i64>>,
+    command: RefCell<String>
 }
 
 impl DB {
     pub fn open() -> DB {
-        let home_dir = dirs::home_dir().unwrap();
-        let bkm_dir = format!("{}/.bkm", home_dir.display());
-        let path = Path::new(&bkm_dir);
-        fs::create_dir_all(&path).unwrap();
+        DB::open_with(true)
+    }
 
-        let full_path = format!("{}/bookmarks.db", bkm_dir);
-        let conn = Connection::open(full_path).unwrap();
-        let db = DB { conn: conn };
+    // Library opened without taking backups, for restoring one of them, which
+    // backs the library up itself and must not remove it as one of the oldest,
+    // and for encrypting the library.
+    pub fn open_for_restore() -> DB {
+        DB::open_with(false)
+    }
+
+    fn open_with(backups: bool) -> DB {
+        let path = if encryption::is_encrypted() {
+            encryption::open_session()
+        } else {
+            encryption::plain_path()
+        };
+
+        let conn = Connection::open(path).unwrap();
+        let db = DB::with_connection(conn);
+        let args: Vec<String> = env::args().skip(1).collect();
+        db.begin_step(&args.join(" "));
+
+        if backups && db.needs_migration() {
+            if let Err(e) = backup::create(&db, "migration") {
+                eprintln!("{}", e);
+            }
+        }
+        db.init();
+        if backups {
+            backup::scheduled(&db);
+        }
+        db
+    }
+
+    // Database of a long-running command, which lets other commands use an
+    // encrypted library between its requests.
+    pub fn open_shared() -> DB {
+        let db = DB::open();
+        if let Err(e) = encryption::pause_session() {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        db
+    }
+
+    // Run f as a request of a long-running command: an encrypted library is
+    // locked meanwhile, with the changes of other commands loaded before and
+    // its own encrypted right after.
+    pub fn request<T, F: FnOnce() -> T>(&self, f: F) -> Result<T, String> {
+        encryption::resume_session()?;
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        encryption::pause_session()?;
+        match result {
+            Ok(result) => Ok(result),
+            Err(e) => panic::resume_unwind(e),
+        }
+    }
+
+    fn with_connection(conn: Connection) -> DB {
+        DB {
+            conn,
+            logging: Cell::new(true),
+            journaling: Cell::new(true),
+            step: Cell::new(None),
+            command: RefCell::new(String::new()),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> DB {
+        let conn = Connection::open_in_memory().unwrap();
+        let db = DB::with_connection(conn);
 
         db.init();
         db
@@ -41,24 +226,129 @@ impl DB {
             bookmark_id    INTEGER NOT NULL,
             tag_id    INTEGER NOT NULL
         )", &[]).unwrap();
+
+        self.conn.execute("CREATE TABLE IF NOT EXISTS saved_searches (
+            id    INTEGER PRIMARY KEY,
+            name    TEXT NOT NULL UNIQUE,
+            query    TEXT NOT NULL
+        )", &[]).unwrap();
+
+        // Text of pages by bookmark id as rowid.
+        self.conn.execute("CREATE VIRTUAL TABLE IF NOT EXISTS contents USING fts5(text)", &[])
+            .unwrap();
+
+        // Icons by host, with an empty type and data for sites having none.
+        self.conn.execute("CREATE TABLE IF NOT EXISTS favicons (
+            host    TEXT PRIMARY KEY,
+            mime    TEXT NOT NULL,
+            data    BLOB NOT NULL,
+            fetched    INTEGER NOT NULL
+        )", &[]).unwrap();
+
+        self.conn.execute("CREATE TABLE IF NOT EXISTS snapshots (
+            id    INTEGER PRIMARY KEY,
+            bookmark_id    INTEGER NOT NULL,
+            path    TEXT NOT NULL,
+            created    INTEGER NOT NULL
+        )", &[]).unwrap();
+
+        self.migrate();
+    }
+
+    // Whether the library was created by an older version, to be migrated.
+    fn needs_migration(&self) -> bool {
+        let version: i64 = self.conn.query_row("PRAGMA user_version", &[], |r| r.get(0)).unwrap();
+        let query = "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'bookmarks'";
+        let tables: i64 = self.conn.query_row(query, &[], |r| r.get(0)).unwrap();
+
+        tables > 0 && (version as usize) < MIGRATIONS.len()
+    }
+
+    // Copy the library to path with the online backup API, consistent even
+    // while other commands write to it.
+    pub fn backup_to(&self, path: &Path) -> Result<(), String> {
+        self.conn.backup(DatabaseName::Main, path, None)
+            .map_err(|e| format!("Error: Failed to back up to {}: {}", path.display(), e))
+    }
+
+    // Replace the library with the database at path.
+    // Whether the library has no file, only tests opening such ones.
+    pub fn is_in_memory(&self) -> bool {
+        let query = "SELECT file FROM pragma_database_list WHERE name = 'main'";
+        self.conn.query_row(query, &[], |r| r.get::<_, String>(0)).map_or(true, |file| file.is_empty())
+    }
+
+    pub fn restore_from(&mut self, path: &Path) -> Result<(), String> {
+        self.conn.restore(DatabaseName::Main, path, None::<fn(Progress)>)
+            .map_err(|e| format!("Error: Failed to restore {}: {}", path.display(), e))
+    }
+
+    // Run f in a transaction, committed when it succeeds and rolled back when
+    // it fails. Transactions nest.
+    pub fn transaction<T, E, F: FnOnce() -> Result<T, E>>(&self, f: F) -> Result<T, E> {
+        let step = self.step.get();
+        self.conn.execute_batch("SAVEPOINT tx").unwrap();
+        let mut savepoint = Savepoint { conn: &self.conn, released: false };
+
+        let result = f();
+        if result.is_ok() {
+            self.conn.execute_batch("RELEASE tx").unwrap();
+            savepoint.released = true;
+        } else {
+            // The journal step may have been created by the changes undone.
+            self.step.set(step);
+        }
+        result
+    }
+
+    fn migrate(&self) {
+        let version: i64 = self.conn.query_row(
+            "PRAGMA user_version", &[], |r| r.get(0)).unwrap();
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            self.conn.execute_batch(&format!(
+                "BEGIN; {} PRAGMA user_version = {}; COMMIT;", migration, i + 1
+            )).expect("Failed to migrate database");
+        }
+
+        self.fill_url_keys();
+        self.fill_sync_keys();
+    }
+
+    // Compute the keys of bookmarks saved before keys existed.
+    fn fill_url_keys(&self) {
+        let mut stmt = self.conn.prepare("SELECT id, url FROM bookmarks WHERE url_key = ''").unwrap();
+        let rows: Vec<(i64, String)> = stmt.query_map(&[], |r| (r.get(0), r.get(1)))
+            .unwrap().map(|r| r.unwrap()).collect();
+
+        for (id, url) in rows {
+            let query = "UPDATE bookmarks SET url_key = $1 WHERE id = $2";
+            self.conn.execute(query, &[&url_key(&url), &id]).unwrap();
+        }
     }
 
-    pub fn get_all_bookmark(&self) -> Vec<Bookmark> {
-        let query = "SELECT * FROM bookmarks";
-        self.vectorize_bookmarks(query)
+    // Give bookmarks saved before the operation log their key in it.
+    fn fill_sync_keys(&self) {
+        let mut stmt = self.conn.prepare("SELECT id, url FROM bookmarks WHERE sync_key = '' ORDER BY id")
+            .unwrap();
+        let rows: Vec<(i64, String)> = stmt.query_map(&[], |r| (r.get(0), r.get(1)))
+            .unwrap().map(|r| r.unwrap()).collect();
+
+        for (id, url) in rows {
+            let key = self.new_sync_key(&url);
+            self.set_sync_key(id, &key);
+        }
+    }
+
+    pub fn get_all_bookmark(&self, options: &ListOptions) -> Vec<Bookmark> {
+        let query = format!("SELECT {} FROM bookmarks{}", BOOKMARK_COLUMNS, options.to_sql());
+        self.vectorize_bookmarks(query.as_str(), &[])
     }
 
     pub fn get_bookmark_by_id(&self, id: i64) -> Result<Bookmark, &str> {
-        let query = "SELECT * FROM bookmarks WHERE id=?";
-
-        match self.conn.query_row(query, &[&id], |r| {
-            Bookmark {
-                id: r.get(0),
-                title: r.get(1),
-                url: r.get(2),
-                tags: self.get_tags(r.get(0)).unwrap()
-            }
-        }) {
+        let query = format!("SELECT {} FROM bookmarks WHERE id=?", BOOKMARK_COLUMNS);
+
+        match self.conn.query_row(query.as_str(), &[&id], |r| self.bookmark_from_row(r)) {
             Ok(b) => Ok(b),
             Err(_) => Err("Error: Did not match index"),
         }
@@ -78,16 +368,96 @@ impl DB {
         tags
     }
 
-    pub fn add_bookmark(&self, title: &String, url: &String) -> Result<(), &str> {
-        let query = "INSERT INTO bookmarks (title, url) VALUES ($1, $2)";
+    // Tags with the number of bookmarks tagged with them, by name.
+    pub fn get_tag_counts(&self) -> Vec<(String, i64)> {
+        let query = "SELECT tags.name, COUNT(bookmark_tag.bookmark_id) FROM tags
+            LEFT JOIN bookmark_tag ON bookmark_tag.tag_id = tags.id
+            GROUP BY tags.id ORDER BY tags.name";
+        let mut stmt = self.conn.prepare(query).unwrap();
+
+        stmt.query_map(&[], |r| (r.get(0), r.get(1))).unwrap()
+            .map(|r| r.unwrap())
+            .collect()
+    }
+
+    pub fn add_bookmark(&self, title: &String, url: &String) -> Result<i64, &str> {
+        if self.get_id_by_url(url).is_some() {
+            return Err("Error: URL already exists");
+        }
 
-        match self.conn.execute(query, &[title, url]) {
-            Ok(_) => Ok(()),
+        let query = "INSERT INTO bookmarks (title, url, url_key, sync_key, created)
+            VALUES ($1, $2, $3, $4, strftime('%s', 'now'))";
+
+        match self.conn.execute(query, &[title, url, &url_key(url), &self.new_sync_key(url)]) {
+            Ok(_) => {
+                let id = self.conn.last_insert_rowid();
+                self.log_bookmark(id);
+                self.journal(&Action::Purge { id });
+                Ok(id)
+            },
             Err(_) => Err("Error: URL already exists"),
         }
     }
 
+    // Add bookmark with its tags, description and notes, ignoring its id.
+    pub fn insert_bookmark(&self, bookmark: &Bookmark) -> Result<i64, &str> {
+        let id = self.add_bookmark(&bookmark.title, &bookmark.url)?;
+
+        for tag in &bookmark.tags {
+            self.add_tag(id, tag);
+        }
+        if !bookmark.description.is_empty() {
+            self.update_description(id, &bookmark.description);
+        }
+        if !bookmark.notes.is_empty() {
+            self.update_notes(id, &bookmark.notes);
+        }
+
+        Ok(id)
+    }
+
+    pub fn update_description(&self, id: i64, description: &str) {
+        self.update_text(id, "description", description);
+    }
+
+    pub fn update_notes(&self, id: i64, notes: &str) {
+        self.update_text(id, "notes", notes);
+    }
+
+    // Set a text column, logged when its value changes.
+    fn update_text(&self, id: i64, column: &str, value: &str) {
+        let query = format!("SELECT {} FROM bookmarks WHERE id=?", column);
+        let old: String = match self.conn.query_row(&query, &[&id], |r| r.get(0)) {
+            Ok(old) => old,
+            Err(_) => return,
+        };
+        if old == value {
+            return;
+        }
+
+        let query = format!("UPDATE bookmarks SET {} = $1 WHERE id = $2", column);
+        self.conn.execute(&query, &[&value, &id]).unwrap();
+        self.log(id, column, value);
+        self.record_change(id, column, &old, value);
+        self.journal(&match column {
+            "description" => Action::Description { id, value: old },
+            _ => Action::Notes { id, value: old },
+        });
+    }
+
     pub fn add_tag(&self, id: i64, tag: &str) {
+        let old = self.get_tags(id).unwrap();
+        if !self.link_tag(id, tag) {
+            return;
+        }
+
+        self.log(id, &format!("tag:{}", tag), "1");
+        self.journal(&Action::RemoveTag { id, tag: tag.to_string() });
+        self.record_tags(id, &old);
+    }
+
+    // Tag bookmark id, answering whether it was not tagged yet.
+    fn link_tag(&self, id: i64, tag: &str) -> bool {
         let select_query = "SELECT id FROM tags WHERE name=?";
         let insert_query = "INSERT INTO tags (name) VALUES ($1)";
 
@@ -96,28 +466,75 @@ impl DB {
             Err(_) => {
                 self.conn.execute(insert_query, &[&tag]).unwrap();
                 let tag_id = self.conn.last_insert_rowid();
-                self.add_bookmark_tag(id, tag_id);
+                self.add_bookmark_tag(id, tag_id)
             }
         }
     }
 
-    pub fn add_bookmark_tag(&self, bookmark_id: i64, tag_id: i64) {
-        let query = "INSERT INTO bookmark_tag (bookmark_id, tag_id) VALUES ($1, $2)";
-        self.conn.execute(query, &[&bookmark_id, &tag_id]).unwrap();
+    pub fn set_tags(&self, id: i64, tags: &[String]) {
+        let old = self.get_tags(id).unwrap();
+        self.conn.execute("DELETE FROM bookmark_tag WHERE bookmark_id=?", &[&id]).unwrap();
+        for tag in tags {
+            self.link_tag(id, tag);
+        }
+
+        // Log the tags changed only, not those kept.
+        for tag in old.iter().filter(|t| !tags.contains(t)) {
+            self.log(id, &format!("tag:{}", tag), "0");
+        }
+        for tag in tags.iter().filter(|t| !old.contains(t)) {
+            self.log(id, &format!("tag:{}", tag), "1");
+        }
+        self.journal(&Action::Tags { id, tags: old.clone() });
+        self.record_tags(id, &old);
+    }
+
+    pub fn add_bookmark_tag(&self, bookmark_id: i64, tag_id: i64) -> bool {
+        let query = "INSERT INTO bookmark_tag (bookmark_id, tag_id) SELECT $1, $2
+            WHERE NOT EXISTS (SELECT 1 FROM bookmark_tag WHERE bookmark_id = $1 AND tag_id = $2)";
+        self.conn.execute(query, &[&bookmark_id, &tag_id]).unwrap() > 0
     }
 
+    // Move bookmark id to the trash.
     pub fn delete_bookmark(&self, id: i64) {
+        if let Some(trash_id) = self.trash_bookmark(id) {
+            self.journal(&Action::Restore { trash_id });
+        }
+        self.purge_bookmark(id);
+    }
+
+    // Delete bookmark id for good.
+    pub fn purge_bookmark(&self, id: i64) {
+        self.log(id, "deleted", "1");
         self.delete_bookmark_tag_by_id(id);
+        self.conn.execute("DELETE FROM snapshots WHERE bookmark_id=?", &[&id]).unwrap();
+        self.conn.execute("DELETE FROM contents WHERE rowid=?", &[&id]).unwrap();
+        self.conn.execute("DELETE FROM history WHERE bookmark_id=?", &[&id]).unwrap();
 
         let query = "DELETE FROM bookmarks WHERE id=?";
         self.conn.execute(query, &[&id]).unwrap();
     }
 
     pub fn delete_bookmark_tag_by_id(&self, id: i64) {
+        for tag in self.get_tags(id).unwrap() {
+            self.log(id, &format!("tag:{}", tag), "0");
+        }
+
         let query = "DELETE FROM bookmark_tag WHERE bookmark_id=?";
         self.conn.execute(query, &[&id]).unwrap();
     }
 
+    pub fn remove_tag(&self, id: i64, tag: &str) {
+        let old = self.get_tags(id).unwrap();
+        let query = "DELETE FROM bookmark_tag WHERE bookmark_id = $1
+            AND tag_id IN (SELECT id FROM tags WHERE name = $2)";
+        if self.conn.execute(query, &[&id, &tag]).unwrap() > 0 {
+            self.log(id, &format!("tag:{}", tag), "0");
+            self.journal(&Action::AddTag { id, tag: tag.to_string() });
+            self.record_tags(id, &old);
+        }
+    }
+
     pub fn delete_tag(&self, name: &str) {
         self.delete_bookmark_tag_by_name(name);
 
@@ -126,13 +543,19 @@ impl DB {
     }
 
     fn delete_bookmark_tag_by_name(&self, name: &str) {
+        let query = "SELECT bookmark_id FROM bookmark_tag
+            WHERE tag_id IN (SELECT id FROM tags WHERE name=?)";
+        let ids = self.get_ids(query, &[&name]);
+        let old: Vec<Vec<String>> = ids.iter().map(|&id| self.get_tags(id).unwrap()).collect();
+
         let query = "DELETE FROM bookmark_tag WHERE tag_id IN (SELECT id FROM tags WHERE name=?)";
         self.conn.execute(query, &[&name]).unwrap();
-    }
 
-    pub fn clear(&self, table_name: &str) {
-        let query = format!("DELETE FROM {}", table_name);
-        self.conn.execute(query.as_str(), &[]).unwrap();
+        for (&id, old) in ids.iter().zip(&old) {
+            self.log(id, &format!("tag:{}", name), "0");
+            self.journal(&Action::AddTag { id, tag: name.to_string() });
+            self.record_tags(id, old);
+        }
     }
 
     pub fn check_existence_bookmark(&self, id: i64) -> i64 {
@@ -145,44 +568,301 @@ impl DB {
         self.conn.query_row(query, &[&name], |r| r.get(0)).unwrap()
     }
 
-    pub fn update_bookmark(&self, id: i64, title: &String, url: &String) {
-        let query = "Update bookmarks SET title = $1, url = $2 WHERE id=?";
-        self.conn.execute(query, &[title, url, &id])
-            .expect("Failed to update");
+    pub fn update_bookmark(&self, id: i64, title: &str, url: &str) -> Result<(), &str> {
+        if self.get_id_by_url(url).is_some_and(|other| other != id) {
+            return Err("Error: URL already exists");
+        }
+
+        let old = self.get_bookmark_by_id(id).ok();
+        self.set_title_url(id, old, title, url)
     }
 
-    pub fn search(&self, keywords: Vec<&str>) -> Vec<Bookmark> {
-        let query = format!(
-            "SELECT * FROM bookmarks WHERE (title || url) LIKE \"%{}%\"",
-            keywords.join("%")
-        );
+    // Set the titles and URLs of bookmarks at once, URLs may move between
+    // them. None is changed when one fails.
+    pub fn update_bookmarks(&self, changes: &[(i64, &str, &str)]) -> Result<(), &str> {
+        self.transaction(|| {
+            let olds: Vec<Option<Bookmark>> = changes.iter()
+                .map(|&(id, _, _)| self.get_bookmark_by_id(id).ok())
+                .collect();
+
+            // URLs are parked first, so that no two bookmarks hold the same one
+            // while they move.
+            for &(id, _, _) in changes {
+                let query = "UPDATE bookmarks SET url = 'bkm:parked:' || id, url_key = 'bkm:parked:' || id
+                    WHERE id=?";
+                self.conn.execute(query, &[&id]).unwrap();
+            }
 
-        self.vectorize_bookmarks(query.as_str())
+            for (&(id, title, url), old) in changes.iter().zip(olds) {
+                if self.get_id_by_url(url).is_some_and(|other| other != id) {
+                    return Err("Error: URL already exists");
+                }
+                self.set_title_url(id, old, title, url)?;
+            }
+            Ok(())
+        })
     }
 
-    pub fn search_by_tag(&self, keywords: Vec<&str>) -> Vec<Bookmark> {
-        let query = format!(
-            "select b.id, b.title, b.url from bookmark_tag bt
-            inner join bookmarks b on b.id = bt.bookmark_id
-            inner join tags t on t.id = bt.tag_id
-            where t.name like \"%{}%\"", keywords.join("%")
-        );
+    // Set the title and URL of bookmark id, logged against old.
+    fn set_title_url(&self, id: i64, old: Option<Bookmark>, title: &str, url: &str) -> Result<(), &str> {
+        let query = "Update bookmarks SET title = $1, url = $2, url_key = $3 WHERE id=?";
+
+        match self.conn.execute(query, &[&title, &url, &url_key(url), &id]) {
+            Ok(_) => {
+                if let Some(old) = old {
+                    if old.title != title {
+                        self.log(id, "title", title);
+                        self.record_change(id, "title", &old.title, title);
+                    }
+                    if old.url != url {
+                        self.log(id, "url", url);
+                        self.record_change(id, "url", &old.url, url);
+                    }
+                    if old.title != title || old.url != url {
+                        self.journal(&Action::Update { id, title: old.title, url: old.url });
+                    }
+                }
+                Ok(())
+            },
+            Err(_) => Err("Error: URL already exists"),
+        }
+    }
 
-        self.vectorize_bookmarks(query.as_str())
+    // Fold bookmark from into bookmark into, keeping the tags, description,
+    // notes and visits of both, then delete bookmark from.
+    pub fn merge_bookmark(&self, from: i64, into: i64) -> Result<(), &str> {
+        self.transaction(|| self.merge_into(from, into))
     }
 
-    fn vectorize_bookmarks(&self, query: &str) -> Vec<Bookmark> {
-        let mut stmt = self.conn.prepare(query).unwrap();
+    fn merge_into(&self, from: i64, into: i64) -> Result<(), &str> {
+        let source = self.get_bookmark_by_id(from)?;
+        let target = self.get_bookmark_by_id(into)?;
+
+        for tag in source.tags.iter().filter(|t| !target.tags.contains(t)) {
+            self.add_tag(into, tag);
+        }
+
+        if target.description.is_empty() {
+            self.update_description(into, &source.description);
+        }
+        if target.notes.is_empty() {
+            self.update_notes(into, &source.notes);
+        } else if !source.notes.is_empty() && source.notes != target.notes {
+            self.update_notes(into, &format!("{}\n{}", target.notes, source.notes));
+        }
+
+        let query = "UPDATE bookmarks SET
+            created = MIN(created, (SELECT created FROM bookmarks WHERE id = $1)),
+            visited = MAX(visited, (SELECT visited FROM bookmarks WHERE id = $1)),
+            visits = visits + (SELECT visits FROM bookmarks WHERE id = $1)
+            WHERE id = $2";
+        self.conn.execute(query, &[&from, &into]).unwrap();
+
+        let query = "UPDATE snapshots SET bookmark_id = $1 WHERE bookmark_id = $2";
+        self.conn.execute(query, &[&into, &from]).unwrap();
+
+        self.delete_bookmark(from);
+        Ok(())
+    }
+
+    pub fn visit_bookmark(&self, id: i64) {
+        let query = "UPDATE bookmarks SET visited = strftime('%s', 'now'),
+            visits = visits + 1 WHERE id=?";
+        self.conn.execute(query, &[&id]).unwrap();
+    }
+
+    // Result of the last link check, status is None when no response came back.
+    pub fn record_check(&self, id: i64, status: Option<u16>, final_url: Option<&str>) {
+        let query = "UPDATE bookmarks SET status = $1, final_url = $2,
+            checked = strftime('%s', 'now') WHERE id = $3";
+        self.conn.execute(query, &[&status.map(i64::from), &final_url, &id]).unwrap();
+    }
+
+    pub fn set_content(&self, id: i64, text: &str) {
+        self.conn.execute("DELETE FROM contents WHERE rowid=?", &[&id]).unwrap();
+        self.conn.execute("INSERT INTO contents (rowid, text) VALUES ($1, $2)", &[&id, &text])
+            .unwrap();
 
-        let bookmark_iter = stmt.query_map(&[], |r| {
-            Bookmark {
-                id: r.get(0),
-                title: r.get(1),
-                url: r.get(2),
-                tags: self.get_tags(r.get(0)).unwrap()
+        let query = "UPDATE bookmarks SET content_indexed = strftime('%s', 'now') WHERE id=?";
+        self.conn.execute(query, &[&id]).unwrap();
+    }
+
+    // When the text of the page was last indexed, 0 when never.
+    pub fn get_content_indexed(&self, id: i64) -> i64 {
+        let query = "SELECT content_indexed FROM bookmarks WHERE id=?";
+        self.conn.query_row(query, &[&id], |r| r.get(0)).unwrap_or(0)
+    }
+
+    // Mark bookmarks saved without fetching their page, for refresh --pending.
+    pub fn set_needs_metadata(&self, id: i64, needs: bool) {
+        let query = "UPDATE bookmarks SET needs_metadata = $1 WHERE id = $2";
+        self.conn.execute(query, &[&needs, &id]).unwrap();
+    }
+
+    pub fn needs_metadata(&self, id: i64) -> bool {
+        let query = "SELECT needs_metadata FROM bookmarks WHERE id=?";
+        self.conn.query_row(query, &[&id], |r| r.get(0)).unwrap_or(false)
+    }
+
+    pub fn set_favicon(&self, host: &str, mime: &str, data: &[u8]) {
+        let query = "INSERT OR REPLACE INTO favicons (host, mime, data, fetched)
+            VALUES ($1, $2, $3, strftime('%s', 'now'))";
+        self.conn.execute(query, &[&host, &mime, &data]).unwrap();
+    }
+
+    // Whether the icon of host was looked up, whether it has one or not.
+    pub fn has_favicon(&self, host: &str) -> bool {
+        let query = "SELECT COUNT(*) FROM favicons WHERE host=?";
+        self.conn.query_row(query, &[&host], |r| r.get::<_, i64>(0)).unwrap() > 0
+    }
+
+    // Store icons given as data URIs by host, keeping the ones cached already.
+    pub fn import_favicons(&self, icons: &HashMap<String, String>) {
+        for (host, icon) in icons {
+            if let Some((mime, data)) = parse_data_uri(icon) {
+                if !self.has_favicon(host) {
+                    self.set_favicon(host, &mime, &data);
+                }
             }
+        }
+    }
+
+    // Data URIs of the icons by host.
+    pub fn get_all_favicons(&self) -> HashMap<String, String> {
+        let query = "SELECT host, mime, data FROM favicons WHERE mime != ''";
+        let mut stmt = self.conn.prepare(query).unwrap();
+
+        let icon_iter = stmt.query_map(&[], |r| {
+            (r.get(0), data_uri(&r.get::<_, String>(1), &r.get::<_, Vec<u8>>(2)))
         }).unwrap();
 
+        let mut icons: HashMap<String, String> = HashMap::new();
+        for icon in icon_iter {
+            let (host, uri) = icon.unwrap();
+            icons.insert(host, uri);
+        }
+
+        icons
+    }
+
+    pub fn add_snapshot(&self, bookmark_id: i64, path: &str) {
+        let query = "INSERT INTO snapshots (bookmark_id, path, created)
+            VALUES ($1, $2, strftime('%s', 'now'))";
+        self.conn.execute(query, &[&bookmark_id, &path]).unwrap();
+    }
+
+    // Paths and times of the snapshots of a bookmark, newest first.
+    pub fn get_snapshots(&self, bookmark_id: i64) -> Vec<(String, i64)> {
+        let query = "SELECT path, created FROM snapshots WHERE bookmark_id=? ORDER BY created DESC, id DESC";
+        let mut stmt = self.conn.prepare(query).unwrap();
+
+        let snapshot_iter = stmt.query_map(&[&bookmark_id], |r| (r.get(0), r.get(1))).unwrap();
+
+        let mut snapshots: Vec<(String, i64)> = Vec::new();
+        for snapshot in snapshot_iter {
+            snapshots.push(snapshot.unwrap());
+        }
+
+        snapshots
+    }
+
+    // Bookmarks matching every keyword and tagged with every tag.
+    pub fn search_query(&self, query: &Query, options: &ListOptions) -> Vec<Bookmark> {
+        let mut conditions: Vec<String> = Vec::new();
+        let mut params: Vec<String> = Vec::new();
+
+        if !query.keywords.is_empty() {
+            let mut keyword_condition = "(title || ' ' || url || ' ' || description || ' ' || notes) LIKE ?";
+            if query.content {
+                keyword_condition = "((title || ' ' || url || ' ' || description || ' ' || notes) LIKE ?
+                    OR id IN (SELECT rowid FROM contents WHERE contents MATCH ?))";
+            }
+
+            // Every keyword has to match.
+            conditions.extend(query.keywords.iter().map(|_| keyword_condition.to_string()));
+            for keyword in &query.keywords {
+                params.push(format!("%{}%", keyword));
+                if query.content {
+                    // A quoted phrase, so that FTS syntax in keywords is matched literally.
+                    params.push(format!("\"{}\"", keyword.replace('"', "\"\"")));
+                }
+            }
+        }
+
+        // Every tag has to be set, by its whole name.
+        for tag in &query.tags {
+            conditions.push("EXISTS (SELECT 1 FROM bookmark_tag bt INNER JOIN tags t ON t.id = bt.tag_id
+                WHERE bt.bookmark_id = bookmarks.id AND t.name = ? COLLATE NOCASE)".to_string());
+            params.push(tag.to_string());
+        }
+
+        let mut sql = format!("SELECT {} FROM bookmarks", BOOKMARK_COLUMNS);
+        if !conditions.is_empty() {
+            sql = format!("{} WHERE {}", sql, conditions.join(" AND "));
+        }
+        sql.push_str(&options.to_sql());
+
+        let params: Vec<&dyn ToSql> = params.iter().map(|p| p as &dyn ToSql).collect();
+        self.vectorize_bookmarks(sql.as_str(), &params)
+    }
+
+    pub fn add_saved_search(&self, name: &str, query: &str) -> Result<(), &str> {
+        let sql = "INSERT INTO saved_searches (name, query) VALUES ($1, $2)";
+
+        match self.conn.execute(sql, &[&name, &query]) {
+            Ok(_) => Ok(()),
+            Err(_) => Err("Error: Saved search already exists"),
+        }
+    }
+
+    pub fn get_saved_search(&self, name: &str) -> Result<String, &str> {
+        let sql = "SELECT query FROM saved_searches WHERE name=?";
+
+        match self.conn.query_row(sql, &[&name], |r| r.get(0)) {
+            Ok(query) => Ok(query),
+            Err(_) => Err("Error: No saved search matching"),
+        }
+    }
+
+    pub fn get_all_saved_search(&self) -> Vec<(String, String)> {
+        let sql = "SELECT name, query FROM saved_searches ORDER BY name";
+        let mut stmt = self.conn.prepare(sql).unwrap();
+
+        let saved_iter = stmt.query_map(&[], |r| (r.get(0), r.get(1))).unwrap();
+
+        let mut saved: Vec<(String, String)> = Vec::new();
+        for s in saved_iter {
+            saved.push(s.unwrap());
+        }
+
+        saved
+    }
+
+    pub fn delete_saved_search(&self, name: &str) -> Result<(), &str> {
+        let sql = "DELETE FROM saved_searches WHERE name=?";
+
+        match self.conn.execute(sql, &[&name]) {
+            Ok(1) => Ok(()),
+            _ => Err("Error: No saved search matching"),
+        }
+    }
+
+    fn bookmark_from_row(&self, r: &Row) -> Bookmark {
+        Bookmark {
+            id: r.get(0),
+            title: r.get(1),
+            url: r.get(2),
+            tags: self.get_tags(r.get(0)).unwrap(),
+            description: r.get(3),
+            notes: r.get(4)
+        }
+    }
+
+    fn vectorize_bookmarks(&self, query: &str, params: &[&dyn ToSql]) -> Vec<Bookmark> {
+        let mut stmt = self.conn.prepare(query).unwrap();
+
+        let bookmark_iter = stmt.query_map(params, |r| self.bookmark_from_row(r)).unwrap();
+
         let mut bookmarks: Vec<Bookmark> = Vec::new();
         for bookmark in bookmark_iter {
             bookmarks.push(bookmark.unwrap());
@@ -199,12 +879,35 @@ impl DB {
         url
     }
 
-    pub fn get_title_by_id(&self, id: i64) -> String {
-        let query = "SELECT title FROM bookmarks WHERE id=?";
-        let title = self.conn.query_row(
-            query, &[&id], |r| r.get(0)).expect("no match index");
+    // Bookmark saved with url or a URL leading to the same page.
+    pub fn get_id_by_url(&self, url: &str) -> Option<i64> {
+        let query = "SELECT id FROM bookmarks WHERE url = $1 OR url_key = $2 ORDER BY url != $1, id";
+        self.conn.query_row(query, &[&url, &url_key(url)], |r| r.get(0)).ok()
+    }
 
-        title
+    // Groups of bookmarks leading to the same page, oldest first.
+    pub fn get_duplicates(&self) -> Vec<Vec<Bookmark>> {
+        let query = format!(
+            "SELECT {}, url_key FROM bookmarks WHERE url_key IN
+            (SELECT url_key FROM bookmarks GROUP BY url_key HAVING COUNT(*) > 1)
+            ORDER BY url_key, id", BOOKMARK_COLUMNS
+        );
+        let mut stmt = self.conn.prepare(&query).unwrap();
+        let rows: Vec<(String, Bookmark)> = stmt.query_map(&[], |r| (r.get(5), self.bookmark_from_row(r)))
+            .unwrap().map(|r| r.unwrap()).collect();
+
+        let mut groups: Vec<Vec<Bookmark>> = Vec::new();
+        let mut last_key: Option<String> = None;
+        for (key, bookmark) in rows {
+            if last_key.as_ref() == Some(&key) {
+                groups.last_mut().unwrap().push(bookmark);
+            } else {
+                groups.push(vec![bookmark]);
+                last_key = Some(key);
+            }
+        }
+
+        groups
     }
 
     pub fn get_record_count(&self, table_name: &str) -> i64 {
@@ -212,11 +915,6 @@ impl DB {
         self.conn.query_row(query.as_str(), &[], |r| r.get(0)).unwrap()
     }
 
-    pub fn get_max_bookmark_id(&self) -> i64 {
-        let query = "SELECT MAX(id) FROM bookmarks";
-        self.conn.query_row(query, &[], |r| r.get(0)).unwrap()
-    }
-
     pub fn get_tags(&self, bookmark_id: i64) -> rusqlite::Result<Vec<String>> {
         let query = format!(
             "SELECT name FROM tags t LEFT JOIN bookmark_tag bt
@@ -232,11 +930,358 @@ impl DB {
 
         Ok(tags)
     }
+
+    fn get_ids(&self, query: &str, params: &[&dyn ToSql]) -> Vec<i64> {
+        let mut stmt = self.conn.prepare(query).unwrap();
+        stmt.query_map(params, |r| r.get(0)).unwrap().map(|r| r.unwrap()).collect()
+    }
+
+    fn get_meta(&self, name: &str) -> Option<String> {
+        self.conn.query_row("SELECT value FROM meta WHERE name=?", &[&name], |r| r.get(0)).ok()
+    }
+
+    fn set_meta(&self, name: &str, value: &str) {
+        let query = "INSERT OR REPLACE INTO meta (name, value) VALUES ($1, $2)";
+        self.conn.execute(query, &[&name, &value]).unwrap();
+    }
+
+    // Identifier of this library in the operation logs, generated on first use.
+    pub fn device_id(&self) -> String {
+        if let Some(device) = self.get_meta("device") {
+            return device;
+        }
+
+        let device = random_hex(8);
+        self.set_meta("device", &device);
+        device
+    }
+
+    // Hybrid logical clock: milliseconds since the epoch shifted left by 16
+    // bits, plus a counter keeping timestamps increasing when the clock does not.
+    fn tick(&self) -> i64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
+        let last: i64 = self.get_meta("clock").and_then(|c| c.parse().ok()).unwrap_or(0);
+
+        let ts = (now << 16).max(last + 1);
+        self.set_meta("clock", &ts.to_string());
+        ts
+    }
+
+    // Move the clock past a timestamp received from another device, so that
+    // the changes made after it here are ordered after it everywhere.
+    pub fn observe(&self, ts: i64) {
+        let last: i64 = self.get_meta("clock").and_then(|c| c.parse().ok()).unwrap_or(0);
+        if ts > last {
+            self.set_meta("clock", &ts.to_string());
+        }
+    }
+
+    // Run f without recording its changes in the operation log.
+    pub fn without_log<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let logging = self.logging.replace(false);
+        let result = f();
+        self.logging.set(logging);
+        result
+    }
+
+    // Record the change of a field of bookmark id, "tag:<name>" fields being
+    // "1" while the bookmark is tagged with name and "0" after.
+    fn log(&self, id: i64, field: &str, value: &str) {
+        if !self.logging.get() {
+            return;
+        }
+        let key = match self.get_sync_key(id) {
+            Some(key) => key,
+            None => return,
+        };
+
+        let query = "INSERT INTO oplog (device, ts, key, field, value) VALUES ($1, $2, $3, $4, $5)";
+        self.conn.execute(query, &[&self.device_id(), &self.tick(), &key, &field, &value]).unwrap();
+    }
+
+    // Record every field of bookmark id.
+    fn log_bookmark(&self, id: i64) {
+        let bookmark = match self.get_bookmark_by_id(id) {
+            Ok(bookmark) => bookmark,
+            Err(_) => return,
+        };
+
+        self.log(id, "deleted", "0");
+        self.log(id, "url", &bookmark.url);
+        self.log(id, "title", &bookmark.title);
+        self.log(id, "description", &bookmark.description);
+        self.log(id, "notes", &bookmark.notes);
+        for tag in &bookmark.tags {
+            self.log(id, &format!("tag:{}", tag), "1");
+        }
+    }
+
+    // Record the bookmarks saved before the operation log existed.
+    pub fn log_unlogged(&self) {
+        let query = "SELECT id FROM bookmarks WHERE sync_key NOT IN (SELECT key FROM oplog)";
+        for id in self.get_ids(query, &[]) {
+            self.log_bookmark(id);
+        }
+    }
+
+    fn get_sync_key(&self, id: i64) -> Option<String> {
+        self.conn.query_row("SELECT sync_key FROM bookmarks WHERE id=?", &[&id], |r| r.get(0)).ok()
+    }
+
+    pub fn set_sync_key(&self, id: i64, key: &str) {
+        let query = "UPDATE bookmarks SET sync_key = $1 WHERE id = $2";
+        self.conn.execute(query, &[&key, &id]).unwrap();
+    }
+
+    // Keys of the bookmarks of other devices skipped for conflicting with
+    // bookmarks here.
+    pub fn get_sync_conflicts(&self) -> Vec<String> {
+        self.get_meta("conflicts").and_then(|c| serde_json::from_str(&c).ok()).unwrap_or_default()
+    }
+
+    pub fn set_sync_conflicts(&self, keys: &[String]) {
+        self.set_meta("conflicts", &serde_json::to_string(keys).unwrap());
+    }
+
+    pub fn get_id_by_sync_key(&self, key: &str) -> Option<i64> {
+        self.conn.query_row("SELECT id FROM bookmarks WHERE sync_key=?", &[&key], |r| r.get(0)).ok()
+    }
+
+    // Key of a new bookmark of url in the operation log: the key of its URL,
+    // so that the same page saved on two devices is one bookmark, unless
+    // another bookmark still has it after its URL changed.
+    fn new_sync_key(&self, url: &str) -> String {
+        let key = url_key(url);
+        match self.get_id_by_sync_key(&key) {
+            Some(_) => format!("{}#{}.{}", key, self.device_id(), self.tick()),
+            None => key,
+        }
+    }
+
+    // Operations of the log, oldest first.
+    pub fn get_ops(&self) -> Vec<Op> {
+        self.query_ops("SELECT device, ts, key, field, value FROM oplog ORDER BY ts, device", &[])
+    }
+
+    pub fn get_ops_by_key(&self, key: &str) -> Vec<Op> {
+        let query = "SELECT device, ts, key, field, value FROM oplog WHERE key=? ORDER BY ts, device";
+        self.query_ops(query, &[&key])
+    }
+
+    fn query_ops(&self, query: &str, params: &[&dyn ToSql]) -> Vec<Op> {
+        let mut stmt = self.conn.prepare(query).unwrap();
+        stmt.query_map(params, |r| Op {
+            device: r.get(0),
+            ts: r.get(1),
+            key: r.get(2),
+            field: r.get(3),
+            value: r.get(4),
+        }).unwrap().map(|r| r.unwrap()).collect()
+    }
+
+    // Add an operation of another device, false when the log has it already.
+    pub fn insert_op(&self, op: &Op) -> bool {
+        let query = "INSERT OR IGNORE INTO oplog (device, ts, key, field, value) VALUES ($1, $2, $3, $4, $5)";
+        self.conn.execute(query, &[&op.device, &op.ts, &op.key, &op.field, &op.value]).unwrap() == 1
+    }
+
+    // Copy bookmark id into the trash, answering the id of its entry.
+    fn trash_bookmark(&self, id: i64) -> Option<i64> {
+        let bookmark = self.get_bookmark_by_id(id).ok()?;
+        let query = "SELECT created, visited, visits, sync_key FROM bookmarks WHERE id=?";
+        let (created, visited, visits, sync_key) = self.conn.query_row(
+            query, &[&id], |r| (r.get(0), r.get(1), r.get(2), r.get(3))).ok()?;
+
+        let query = "SELECT rev, time, field, old, new FROM history WHERE bookmark_id=? ORDER BY rev";
+        let mut stmt = self.conn.prepare(query).unwrap();
+        let history = stmt.query_map(&[&id], |r| (r.get(0), r.get(1), r.get(2), r.get(3), r.get(4)))
+            .unwrap().map(|r| r.unwrap()).collect();
+
+        let trashed = Trashed {
+            bookmark, created, visited, visits, sync_key,
+            snapshots: self.get_snapshots(id),
+            history
+        };
+        let query = "INSERT INTO trash (bookmark_id, deleted, data) VALUES ($1, strftime('%s', 'now'), $2)";
+        self.conn.execute(query, &[&id, &serde_json::to_string(&trashed).unwrap()]).unwrap();
+        Some(self.conn.last_insert_rowid())
+    }
+
+    // Bookmarks of the trash, last deleted first.
+    pub fn get_trash(&self) -> Vec<TrashEntry> {
+        let query = "SELECT id, datetime(deleted, 'unixepoch', 'localtime'), data, bookmark_id
+            FROM trash ORDER BY deleted DESC, id DESC";
+        let mut stmt = self.conn.prepare(query).unwrap();
+
+        stmt.query_map(&[], |r| {
+            let data: String = r.get(2);
+            let mut bookmark = serde_json::from_str::<Trashed>(&data).unwrap().bookmark;
+            bookmark.id = r.get(3);
+            TrashEntry { id: r.get(0), deleted: r.get(1), bookmark }
+        }).unwrap().map(|r| r.unwrap()).collect()
+    }
+
+    // Put the bookmark of trash entry back, under its former id when still free.
+    pub fn restore_bookmark(&self, trash_id: i64) -> Result<i64, &str> {
+        let query = "SELECT bookmark_id, data FROM trash WHERE id=?";
+        let (old_id, data): (i64, String) = self.conn.query_row(query, &[&trash_id], |r| (r.get(0), r.get(1)))
+            .map_err(|_| "Error: No bookmark of the trash matching the id")?;
+        let trashed: Trashed = serde_json::from_str(&data).unwrap();
+        let bookmark = &trashed.bookmark;
+
+        if self.get_id_by_url(&bookmark.url).is_some() {
+            return Err("Error: URL already exists");
+        }
+        let id = if self.check_existence_bookmark(old_id) == 0 { Some(old_id) } else { None };
+        let sync_key = match self.get_id_by_sync_key(&trashed.sync_key) {
+            None if !trashed.sync_key.is_empty() => trashed.sync_key.clone(),
+            _ => self.new_sync_key(&bookmark.url),
+        };
+
+        let query = "INSERT INTO bookmarks (id, title, url, url_key, sync_key, description, notes,
+            created, visited, visits) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)";
+        self.conn.execute(query, &[&id, &bookmark.title, &bookmark.url, &url_key(&bookmark.url), &sync_key,
+                                   &bookmark.description, &bookmark.notes,
+                                   &trashed.created, &trashed.visited, &trashed.visits]).unwrap();
+        let id = self.conn.last_insert_rowid();
+
+        for tag in &bookmark.tags {
+            self.link_tag(id, tag);
+        }
+        for (path, created) in &trashed.snapshots {
+            let query = "INSERT INTO snapshots (bookmark_id, path, created) VALUES ($1, $2, $3)";
+            self.conn.execute(query, &[&id, path, created]).unwrap();
+        }
+        for (rev, time, field, old, new) in &trashed.history {
+            let query = "INSERT INTO history (bookmark_id, rev, time, field, old, new)
+                VALUES ($1, $2, $3, $4, $5, $6)";
+            self.conn.execute(query, &[&id, rev, time, field, old, new]).unwrap();
+        }
+        self.conn.execute("DELETE FROM trash WHERE id=?", &[&trash_id]).unwrap();
+
+        self.log_bookmark(id);
+        self.journal(&Action::Trash { id });
+        Ok(id)
+    }
+
+    // Remove the entries of the trash deleted before a time, all without one,
+    // answering the snapshot files of their bookmarks.
+    pub fn empty_trash(&self, before: Option<i64>) -> Vec<String> {
+        let before = before.unwrap_or(i64::MAX);
+        let mut stmt = self.conn.prepare("SELECT data FROM trash WHERE deleted < ?").unwrap();
+        let paths: Vec<String> = stmt.query_map(&[&before], |r| r.get::<_, String>(0)).unwrap()
+            .flat_map(|data| serde_json::from_str::<Trashed>(&data.unwrap()).unwrap().snapshots)
+            .map(|(path, _)| path)
+            .collect();
+
+        self.conn.execute("DELETE FROM trash WHERE deleted < ?", &[&before]).unwrap();
+        paths
+    }
+
+    // Record the change of a field of bookmark id as its next revision.
+    fn record_change(&self, id: i64, field: &str, old: &str, new: &str) {
+        if old == new {
+            return;
+        }
+
+        let query = "INSERT INTO history (bookmark_id, rev, time, field, old, new)
+            SELECT $1, COALESCE(MAX(rev), 0) + 1, strftime('%s', 'now'), $2, $3, $4
+            FROM history WHERE bookmark_id = $1";
+        self.conn.execute(query, &[&id, &field, &old, &new]).unwrap();
+    }
+
+    // Record the change of the tags of bookmark id from old.
+    fn record_tags(&self, id: i64, old: &[String]) {
+        let new = self.get_tags(id).unwrap();
+        self.record_change(id, "tags", &encode_tags(old), &encode_tags(&new));
+    }
+
+    // Revisions of bookmark id, first first.
+    pub fn get_history(&self, id: i64) -> Vec<Revision> {
+        let query = "SELECT rev, datetime(time, 'unixepoch', 'localtime'), field, old, new
+            FROM history WHERE bookmark_id=? ORDER BY rev";
+        let mut stmt = self.conn.prepare(query).unwrap();
+
+        stmt.query_map(&[&id], |r| {
+            Revision { rev: r.get(0), time: r.get(1), field: r.get(2), old: r.get(3), new: r.get(4) }
+        }).unwrap().map(|r| r.unwrap()).collect()
+    }
+
+    // Name the journal step of the changes made from now on after command.
+    pub fn begin_step(&self, command: &str) {
+        self.step.set(None);
+        *self.command.borrow_mut() = command.to_string();
+    }
+
+    // Run f without recording its changes in the journal.
+    pub fn without_journal<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let journaling = self.journaling.replace(false);
+        let result = f();
+        self.journaling.set(journaling);
+        result
+    }
+
+    // Record the action reverting a change in the step of the running command.
+    fn journal(&self, action: &Action) {
+        if !self.logging.get() || !self.journaling.get() {
+            return;
+        }
+
+        let step = match self.step.get() {
+            Some(step) => step,
+            None => {
+                let query = "INSERT INTO journal (time, command) VALUES (strftime('%s', 'now'), $1)";
+                self.conn.execute(query, &[&*self.command.borrow()]).unwrap();
+                let step = self.conn.last_insert_rowid();
+                self.step.set(Some(step));
+
+                // Forget the oldest steps.
+                let query = "DELETE FROM journal WHERE step <= $1";
+                self.conn.execute(query, &[&(step - JOURNAL_STEPS)]).unwrap();
+                let query = "DELETE FROM journal_actions WHERE step NOT IN (SELECT step FROM journal)";
+                self.conn.execute(query, &[]).unwrap();
+                step
+            },
+        };
+
+        let query = "INSERT INTO journal_actions (step, action) VALUES ($1, $2)";
+        self.conn.execute(query, &[&step, &serde_json::to_string(action).unwrap()]).unwrap();
+    }
+
+    // Last step of the journal, with its command and actions in the order they were recorded.
+    pub fn get_last_step(&self) -> Option<(i64, String, Vec<Action>)> {
+        let query = "SELECT step, command FROM journal ORDER BY step DESC LIMIT 1";
+        let (step, command): (i64, String) = self.conn.query_row(query, &[], |r| (r.get(0), r.get(1))).ok()?;
+
+        let query = "SELECT action FROM journal_actions WHERE step=? ORDER BY rowid";
+        let mut stmt = self.conn.prepare(query).unwrap();
+        let actions = stmt.query_map(&[&step], |r| r.get::<_, String>(0)).unwrap()
+            .filter_map(|action| serde_json::from_str(&action.unwrap()).ok())
+            .collect();
+
+        Some((step, command, actions))
+    }
+
+    // Recent steps of the journal, last first.
+    pub fn get_steps(&self) -> Vec<(i64, String, String)> {
+        let query = "SELECT step, datetime(time, 'unixepoch', 'localtime'), command FROM journal ORDER BY step DESC";
+        let mut stmt = self.conn.prepare(query).unwrap();
+        stmt.query_map(&[], |r| (r.get(0), r.get(1), r.get(2))).unwrap().map(|r| r.unwrap()).collect()
+    }
+
+    pub fn delete_step(&self, step: i64) {
+        self.conn.execute("DELETE FROM journal_actions WHERE step=?", &[&step]).unwrap();
+        self.conn.execute("DELETE FROM journal WHERE step=?", &[&step]).unwrap();
+        if self.step.get() == Some(step) {
+            self.step.set(None);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use query::SortKey;
 
     fn test_data() -> Vec<Bookmark> {
         let data: Vec<Bookmark> = vec![
@@ -245,18 +1290,21 @@ mod tests {
                 title: "GitHub".to_string(),
                 url: "https://github.com".to_string(),
                 tags: vec!["Git".to_string(), "Hosting service".to_string()],
+                ..Default::default()
             }),
             (Bookmark {
                 id: 2,
                 title: "Google".to_string(),
                 url: "https://google.com".to_string(),
                 tags: vec!["Search".to_string()],
+                ..Default::default()
             }),
             (Bookmark {
                 id: 3,
                 title: "Example Domain".to_string(),
                 url: "https://example.com".to_string(),
                 tags: vec!["".to_string()],
+                ..Default::default()
             }),
         ];
 
@@ -264,11 +1312,7 @@ mod tests {
     }
 
     fn open() -> DB {
-        let conn = Connection::open_in_memory().unwrap();
-        let db = DB { conn: conn};
-
-        db.init();
-        db
+        DB::open_in_memory()
     }
 
     #[test]
@@ -311,8 +1355,11 @@ mod tests {
         let new_bookmark = &test_data()[1];
 
         db.add_bookmark(&old_bookmark.title, &old_bookmark.url).unwrap();
-        db.update_bookmark(old_bookmark.id, &new_bookmark.title, &new_bookmark.url);
+        db.update_bookmark(old_bookmark.id, &new_bookmark.title, &new_bookmark.url).unwrap();
         assert!(db.get_bookmark_by_id(old_bookmark.id).is_ok());
+
+        db.add_bookmark(&old_bookmark.title, &old_bookmark.url).unwrap();
+        assert!(db.update_bookmark(old_bookmark.id, &old_bookmark.title, &old_bookmark.url).is_err());
     }
 
     #[test]
@@ -322,8 +1369,8 @@ mod tests {
         for bookmark in test_data() {
             db.add_bookmark(&bookmark.title, &bookmark.url).unwrap();
 
-            let t_search = &db.search(vec![&bookmark.title])[0];
-            let u_search = &db.search(vec![&bookmark.url])[0];
+            let t_search = &db.search_query(&Query::parse(&bookmark.title), &ListOptions::default())[0];
+            let u_search = &db.search_query(&Query::parse(&bookmark.url), &ListOptions::default())[0];
 
             assert_eq!((&bookmark.id, &bookmark.title, &bookmark.url),
                        (&t_search.id, &t_search.title, &t_search.url));
@@ -331,4 +1378,329 @@ mod tests {
                        (&u_search.id, &u_search.title, &u_search.url));
         }
     }
+
+    #[test]
+    fn test_search_query() {
+        let db = open();
+
+        for bookmark in test_data() {
+            db.add_bookmark(&bookmark.title, &bookmark.url).unwrap();
+            for tag in &bookmark.tags {
+                db.add_tag(bookmark.id, tag);
+            }
+        }
+
+        let search = |query: &str| -> Vec<String> {
+            db.search_query(&Query::parse(query), &ListOptions::default())
+                .into_iter().map(|b| b.title).collect()
+        };
+
+        // Keywords and tags narrow the results down.
+        assert!(search("github google").is_empty());
+        assert_eq!(search("git hub"), vec!["GitHub"]);
+        assert_eq!(search("google tag:search"), vec!["Google"]);
+        assert!(search("github tag:search").is_empty());
+        assert!(search("tag:git tag:search").is_empty());
+
+        // Tags match by their whole name only.
+        assert_eq!(search("tag:git"), vec!["GitHub"]);
+        assert!(search("tag:hosting").is_empty());
+        assert_eq!(search("tag:\"hosting service\""), vec!["GitHub"]);
+    }
+
+    #[test]
+    fn test_get_all_bookmark() {
+        let db = open();
+
+        for bookmark in test_data() {
+            db.add_bookmark(&bookmark.title, &bookmark.url).unwrap();
+        }
+        db.visit_bookmark(3);
+        db.visit_bookmark(3);
+        db.visit_bookmark(1);
+
+        let options = ListOptions::new(SortKey::Title, false, None, None);
+        let titles: Vec<String> = db.get_all_bookmark(&options)
+            .into_iter().map(|b| b.title).collect();
+        assert_eq!(titles, vec!["Example Domain", "GitHub", "Google"]);
+
+        let options = ListOptions::new(SortKey::Visits, true, Some(2), None);
+        let ids: Vec<i64> = db.get_all_bookmark(&options)
+            .into_iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![3, 1]);
+
+        let options = ListOptions::new(SortKey::Id, false, Some(1), Some(1));
+        let ids: Vec<i64> = db.get_all_bookmark(&options)
+            .into_iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![2]);
+    }
+
+    #[test]
+    fn test_description_and_notes() {
+        let db = open();
+        let bookmark = &test_data()[0];
+
+        let id = db.add_bookmark(&bookmark.title, &bookmark.url).unwrap();
+        db.update_description(id, "Software development platform");
+        db.update_notes(id, "Where bkm lives");
+
+        let b = db.get_bookmark_by_id(id).unwrap();
+        assert_eq!((b.description.as_str(), b.notes.as_str()),
+                   ("Software development platform", "Where bkm lives"));
+        assert_eq!(db.search_query(&Query::parse("lives"), &ListOptions::default()).len(), 1);
+        assert_eq!(db.search_query(&Query::parse("platform"), &ListOptions::default()).len(), 1);
+    }
+
+    #[test]
+    fn test_merge_bookmark() {
+        let db = open();
+        for bookmark in test_data() {
+            let id = db.add_bookmark(&bookmark.title, &bookmark.url).unwrap();
+            db.set_tags(id, &bookmark.tags);
+        }
+        db.update_notes(1, "Where bkm lives");
+        db.update_description(2, "Search engine");
+        db.visit_bookmark(2);
+
+        db.merge_bookmark(2, 1).unwrap();
+        assert!(db.get_bookmark_by_id(2).is_err());
+        assert!(db.merge_bookmark(2, 1).is_err());
+
+        let b = db.get_bookmark_by_id(1).unwrap();
+        assert_eq!(b.tags, vec!["Git", "Hosting service", "Search"]);
+        assert_eq!((b.description.as_str(), b.notes.as_str()), ("Search engine", "Where bkm lives"));
+
+        let options = ListOptions::new(SortKey::Visits, true, Some(1), None);
+        assert_eq!(db.get_all_bookmark(&options)[0].id, 1);
+    }
+
+    #[test]
+    fn test_url_key() {
+        let db = open();
+        for bookmark in test_data() {
+            db.add_bookmark(&bookmark.title, &bookmark.url).unwrap();
+        }
+
+        let title = "GitHub".to_string();
+        assert!(db.add_bookmark(&title, &"http://GitHub.com/?utm_source=x".to_string()).is_err());
+        assert_eq!(db.get_id_by_url("https://github.com/"), Some(1));
+        assert!(db.update_bookmark(2, "Google", "https://github.com/").is_err());
+        assert!(db.update_bookmark(1, "GitHub", "https://github.com/").is_ok());
+
+        // Duplicates saved before keys existed.
+        db.conn.execute("INSERT INTO bookmarks (title, url) VALUES ('GitHub', 'http://github.com')",
+                        &[]).unwrap();
+        db.conn.execute("INSERT INTO bookmarks (title, url) VALUES ('Google', 'https://google.com/#q')",
+                        &[]).unwrap();
+        db.fill_url_keys();
+
+        let groups: Vec<Vec<i64>> = db.get_duplicates().iter()
+            .map(|g| g.iter().map(|b| b.id).collect())
+            .collect();
+        assert_eq!(groups, vec![vec![1, 4], vec![2, 5]]);
+    }
+
+    #[test]
+    fn test_content() {
+        let db = open();
+        for bookmark in test_data() {
+            db.add_bookmark(&bookmark.title, &bookmark.url).unwrap();
+        }
+        assert_eq!(db.get_content_indexed(1), 0);
+
+        db.set_content(1, "Build software better, together");
+        db.set_content(2, "Search the world's information");
+        db.set_content(2, "I'm Feeling Lucky");
+        assert!(db.get_content_indexed(1) > 0);
+
+        let search = |keywords: &[&str], content: bool| -> Vec<i64> {
+            let mut query = Query::parse(&keywords.join(" "));
+            query.content = content;
+            db.search_query(&query, &ListOptions::default()).into_iter().map(|b| b.id).collect()
+        };
+        assert_eq!(search(&["together"], false), Vec::<i64>::new());
+        assert_eq!(search(&["together"], true), vec![1]);
+        assert_eq!(search(&["\"feeling lucky\""], true), vec![2]);
+        assert_eq!(search(&["information"], true), Vec::<i64>::new());
+        assert_eq!(search(&["github", "software"], true), vec![1]);
+        assert_eq!(search(&["example", "software"], true), Vec::<i64>::new());
+        assert_eq!(search(&["\"a OR\""], true), Vec::<i64>::new());
+
+        db.delete_bookmark(1);
+        assert_eq!(search(&["together"], true), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn test_needs_metadata() {
+        let db = open();
+        for bookmark in test_data() {
+            db.add_bookmark(&bookmark.title, &bookmark.url).unwrap();
+        }
+        assert!(!db.needs_metadata(1));
+
+        db.set_needs_metadata(1, true);
+        assert!(db.needs_metadata(1));
+        assert!(!db.needs_metadata(2));
+
+        db.set_needs_metadata(1, false);
+        assert!(!db.needs_metadata(1));
+    }
+
+    #[test]
+    fn test_insert_bookmark() {
+        let db = open();
+        let mut bookmark = Bookmark::new(
+            0, "GitHub".to_string(), "https://github.com".to_string(),
+            vec!["git".to_string(), "hosting service".to_string()]
+        );
+        bookmark.notes = "Read the guide".to_string();
+
+        let id = db.insert_bookmark(&bookmark).unwrap();
+        let saved = db.get_bookmark_by_id(id).unwrap();
+        assert_eq!(saved.tags, bookmark.tags);
+        assert_eq!(saved.notes, "Read the guide");
+        assert!(db.insert_bookmark(&bookmark).is_err());
+
+        db.add_tag(db.add_bookmark(&"Git".to_string(), &"https://git-scm.com".to_string()).unwrap(), "git");
+        assert_eq!(db.get_tag_counts(), vec![("git".to_string(), 2), ("hosting service".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_favicons() {
+        let db = open();
+        assert!(!db.has_favicon("github.com"));
+
+        db.set_favicon("github.com", "image/png", b"\x89PNG");
+        db.set_favicon("example.com", "", b"");
+        assert!(db.has_favicon("github.com") && db.has_favicon("example.com"));
+
+        let icons = db.get_all_favicons();
+        assert_eq!(icons.len(), 1);
+        assert_eq!(icons["github.com"], "data:image/png;base64,iVBORw==");
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let db = open();
+        for bookmark in test_data() {
+            db.add_bookmark(&bookmark.title, &bookmark.url).unwrap();
+        }
+
+        db.add_snapshot(1, "/archive/1-1.html");
+        db.add_snapshot(1, "/archive/1-2.html");
+        db.add_snapshot(2, "/archive/2-1.html");
+        let paths: Vec<String> = db.get_snapshots(1).into_iter().map(|(p, _)| p).collect();
+        assert_eq!(paths, vec!["/archive/1-2.html", "/archive/1-1.html"]);
+
+        db.merge_bookmark(2, 1).unwrap();
+        assert_eq!(db.get_snapshots(1).len(), 3);
+        db.delete_bookmark(1);
+        assert!(db.get_snapshots(1).is_empty());
+    }
+
+    #[test]
+    fn test_update_bookmarks() {
+        let db = open();
+        for bookmark in test_data() {
+            db.add_bookmark(&bookmark.title, &bookmark.url).unwrap();
+        }
+
+        // URLs swapped between two bookmarks.
+        db.update_bookmarks(&[(1, "GitHub", "https://google.com"), (2, "Google", "https://github.com")])
+            .unwrap();
+        assert_eq!(db.get_url_by_id(1), "https://google.com");
+        assert_eq!(db.get_url_by_id(2), "https://github.com");
+        let history = db.get_history(1);
+        assert_eq!((history[0].old.as_str(), history[0].new.as_str()), ("https://github.com", "https://google.com"));
+
+        // Taking the URL of a bookmark not changed leaves them all as they were.
+        let result = db.update_bookmarks(&[(1, "GitHub!", "https://github.com"), (3, "Example", "https://google.com")]);
+        assert!(result.is_err());
+        assert_eq!(db.get_bookmark_by_id(1).unwrap().title, "GitHub");
+        assert_eq!(db.get_url_by_id(1), "https://google.com");
+        assert_eq!(db.get_url_by_id(3), "https://example.com");
+    }
+
+    #[test]
+    fn test_trash() {
+        let db = open();
+        for bookmark in test_data() {
+            let id = db.add_bookmark(&bookmark.title, &bookmark.url).unwrap();
+            db.set_tags(id, &bookmark.tags);
+        }
+        db.add_snapshot(1, "/archive/1-1.html");
+
+        db.delete_bookmark(1);
+        db.delete_bookmark(2);
+        let trash = db.get_trash();
+        assert_eq!(trash.len(), 2);
+        assert_eq!(trash[1].bookmark.id, 1);
+        assert_eq!(trash[1].bookmark.tags, vec!["Git", "Hosting service"]);
+
+        // Restored under its former id, unless its URL was saved again.
+        db.add_bookmark(&"Google".to_string(), &"https://google.com".to_string()).unwrap();
+        assert!(db.restore_bookmark(trash[0].id).is_err());
+        assert_eq!(db.restore_bookmark(trash[1].id), Ok(1));
+        assert_eq!(db.get_bookmark_by_id(1).unwrap().tags, vec!["Git", "Hosting service"]);
+        assert_eq!(db.get_snapshots(1).len(), 1);
+
+        assert!(db.empty_trash(Some(0)).is_empty());
+        db.delete_bookmark(1);
+        assert_eq!(db.empty_trash(None), vec!["/archive/1-1.html"]);
+        assert!(db.get_trash().is_empty());
+    }
+
+    #[test]
+    fn test_record_check() {
+        let db = open();
+        for bookmark in test_data() {
+            db.add_bookmark(&bookmark.title, &bookmark.url).unwrap();
+        }
+
+        db.record_check(1, Some(200), Some("https://github.com/"));
+        db.record_check(2, None, None);
+
+        let check = |id: i64| -> (Option<i64>, Option<String>, i64) {
+            db.conn.query_row("SELECT status, final_url, checked FROM bookmarks WHERE id=?",
+                              &[&id], |r| (r.get(0), r.get(1), r.get(2))).unwrap()
+        };
+        let (status, final_url, checked) = check(1);
+        assert_eq!((status, final_url.as_deref()), (Some(200), Some("https://github.com/")));
+        assert!(checked > 0);
+        assert_eq!(check(2).0, None);
+        assert_eq!(check(3).2, 0);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE bookmarks (
+            id    INTEGER PRIMARY KEY,
+            title    TEXT NOT NULL,
+            url    TEXT NOT NULL UNIQUE
+        );
+        INSERT INTO bookmarks (title, url) VALUES ('GitHub', 'https://github.com');").unwrap();
+
+        let db = DB::with_connection(conn);
+        db.init();
+        db.init();
+
+        db.visit_bookmark(1);
+        let options = ListOptions::new(SortKey::Visited, false, None, None);
+        assert_eq!(db.get_all_bookmark(&options)[0].title, "GitHub");
+    }
+
+    #[test]
+    fn test_saved_search() {
+        let db = open();
+
+        db.add_saved_search("rust", "rust tag:async").unwrap();
+        assert!(db.add_saved_search("rust", "rust").is_err());
+        assert_eq!(db.get_saved_search("rust").unwrap(), "rust tag:async");
+        assert_eq!(db.get_all_saved_search().len(), 1);
+
+        db.delete_saved_search("rust").unwrap();
+        assert!(db.get_saved_search("rust").is_err());
+        assert!(db.delete_saved_search("rust").is_err());
+    }
 }
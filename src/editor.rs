@@ -0,0 +1,202 @@
+use reqwest::Url;
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use toml;
+
+use bookmark::Bookmark;
+use normalize::{normalize_url, url_key};
+
+const HEADER: &str = "\
+# Edit bookmarks below, then save and quit to apply the changes.
+# Removing a [[bookmark]] entry leaves the bookmark unchanged.
+";
+const ERROR_PREFIX: &str = "# ERROR: ";
+
+#[derive(Serialize, Deserialize)]
+struct EditFile {
+    #[serde(default)]
+    bookmark: Vec<Entry>
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    id: i64,
+    title: String,
+    url: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    notes: String
+}
+
+pub fn render(bookmarks: &[Bookmark]) -> String {
+    let file = EditFile {
+        bookmark: bookmarks.iter().map(|b| Entry {
+            id: b.id,
+            title: b.title.clone(),
+            url: b.url.clone(),
+            tags: b.tags.clone(),
+            description: b.description.clone(),
+            notes: b.notes.clone(),
+        }).collect(),
+    };
+
+    format!("{}\n{}", HEADER, toml::to_string(&file).unwrap())
+}
+
+// Bookmarks changed in the edited content, checked against the originals.
+pub fn changes(content: &str, originals: &[Bookmark]) -> Result<Vec<Bookmark>, String> {
+    let file: EditFile = toml::from_str(content).map_err(|e| e.to_string())?;
+
+    let mut changed: Vec<Bookmark> = Vec::new();
+    let mut ids: Vec<i64> = Vec::new();
+    let mut urls: Vec<String> = Vec::new();
+
+    for entry in file.bookmark {
+        let original = match originals.iter().find(|b| b.id == entry.id) {
+            Some(b) => b,
+            None => return Err(format!("Bookmark {} is not being edited", entry.id)),
+        };
+        if ids.contains(&entry.id) {
+            return Err(format!("Bookmark {} appears more than once", entry.id));
+        }
+        ids.push(entry.id);
+
+        let title = entry.title.trim().to_string();
+        let mut url = entry.url.trim().to_string();
+        if url != original.url {
+            url = normalize_url(&url);
+        }
+        if title.is_empty() {
+            return Err(format!("Title of bookmark {} is empty", entry.id));
+        }
+        if Url::parse(&url).is_err() {
+            return Err(format!("URL \"{}\" of bookmark {} is invalid", url, entry.id));
+        }
+        if urls.contains(&url_key(&url)) {
+            return Err(format!("URL \"{}\" appears more than once", url));
+        }
+        urls.push(url_key(&url));
+
+        let mut tags: Vec<String> = entry.tags.iter()
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+        tags.dedup();
+
+        let description = entry.description.trim().to_string();
+        let notes = entry.notes.trim_end().to_string();
+
+        if title != original.title || url != original.url || tags != original.tags
+            || description != original.description || notes != original.notes {
+            let mut bookmark = Bookmark::new(entry.id, title, url, tags);
+            bookmark.description = description;
+            bookmark.notes = notes;
+            changed.push(bookmark);
+        }
+    }
+
+    Ok(changed)
+}
+
+// Replace the error comments at the top of content with the new error.
+pub fn annotate(content: &str, error: &str) -> String {
+    let mut annotated: String = error.lines()
+        .map(|line| format!("{}{}\n", ERROR_PREFIX, line))
+        .collect();
+
+    for line in content.lines().skip_while(|line| line.starts_with(ERROR_PREFIX)) {
+        annotated.push_str(line);
+        annotated.push('\n');
+    }
+
+    annotated
+}
+
+// Open path in $VISUAL or $EDITOR and wait until it exits.
+pub fn open_editor(path: &Path) -> io::Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{} exited with {}", editor, status)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_data() -> Vec<Bookmark> {
+        let mut github = Bookmark::new(1, "GitHub".to_string(), "https://github.com".to_string(),
+                                       vec!["Git".to_string(), "Hosting service".to_string()]);
+        github.notes = "First line\nSecond line".to_string();
+
+        vec![
+            github,
+            Bookmark::new(2, "Google".to_string(), "https://google.com".to_string(), vec![]),
+        ]
+    }
+
+    #[test]
+    fn test_render() {
+        let content = render(&test_data());
+
+        assert!(content.starts_with(HEADER));
+        assert!(content.contains("[[bookmark]]\nid = 1\ntitle = \"GitHub\""));
+        assert!(changes(&content, &test_data()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_changes() {
+        let content = render(&test_data())
+            .replace("\"Google\"", "\"Google Search\"")
+            .replace("\"Hosting service\"", "\" \"");
+
+        let changed = changes(&content, &test_data()).unwrap();
+        assert_eq!(changed.len(), 2);
+        assert_eq!(changed[0].tags, vec!["Git".to_string()]);
+        assert_eq!(changed[1].title, "Google Search");
+
+        let content = render(&test_data()).replace("description = \"\"\nnotes = \"\"",
+                                                   "description = \"Search\"");
+        let changed = changes(&content, &test_data()).unwrap();
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].description, "Search");
+    }
+
+    #[test]
+    fn test_changes_error() {
+        let content = render(&test_data());
+
+        assert!(changes(&content.replace("id = 2", "id = 3"), &test_data()).is_err());
+        assert!(changes(&content.replace("id = 2", "id = 1"), &test_data()).is_err());
+        assert!(changes(&content.replace("\"GitHub\"", "\"\""), &test_data()).is_err());
+        assert!(changes(&content.replace("https://google.com", "google"), &test_data()).is_err());
+        assert!(changes(&content.replace("google", "github"), &test_data()).is_err());
+        assert!(changes(&content.replace("title =", "title"), &test_data()).is_err());
+    }
+
+    #[test]
+    fn test_annotate() {
+        let content = annotate("[[bookmark]]\n", "expected a value\nat line 1");
+        assert_eq!(content, "# ERROR: expected a value\n# ERROR: at line 1\n[[bookmark]]\n");
+
+        let content = annotate(&content, "invalid URL");
+        assert_eq!(content, "# ERROR: invalid URL\n[[bookmark]]\n");
+    }
+}
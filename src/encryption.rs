@@ -0,0 +1,565 @@
+// Encrypted library: ~/.bkm/bookmarks.db.enc holds the database encrypted
+// with AES-256-GCM under a key derived from a passphrase or a keyfile.
+// Commands work on a copy decrypted into the runtime directory, encrypted
+// back when they exit, long-running ones after each of their requests.
+
+use libc;
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::rand::rand_bytes;
+use openssl::sha::sha256;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use rusqlite::{Connection, DatabaseName};
+use rusqlite::backup::Progress;
+use std::env;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::Write;
+use std::mem;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::{process, ptr, thread};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use termion::input::TermRead;
+
+use backup;
+use config::Config;
+use utils::{bkm_dir, expand_home};
+
+const MAGIC: &[u8] = b"BKMENC1\0";
+pub const ITERATIONS: u32 = 600_000;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+const HEADER_SIZE: usize = 8 + 4 + SALT_SIZE;
+pub const DEFAULT_TIMEOUT: u64 = 15;
+
+// Parameters of the key derivation, stored unencrypted in front of the data.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Header {
+    pub iterations: u32,
+    pub salt: Vec<u8>
+}
+
+impl Header {
+    pub fn new(iterations: u32) -> Header {
+        let mut salt = vec![0; SALT_SIZE];
+        rand_bytes(&mut salt).unwrap();
+        Header { iterations, salt }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&self.iterations.to_le_bytes());
+        bytes.extend_from_slice(&self.salt);
+        bytes
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Header, String> {
+        if data.len() < HEADER_SIZE + NONCE_SIZE + TAG_SIZE || &data[..8] != MAGIC {
+            return Err("Error: Not an encrypted bkm library".to_string());
+        }
+
+        let mut iterations = [0u8; 4];
+        iterations.copy_from_slice(&data[8..12]);
+        Ok(Header { iterations: u32::from_le_bytes(iterations), salt: data[12..HEADER_SIZE].to_vec() })
+    }
+
+    // Key of a passphrase, or of the content of a keyfile.
+    pub fn derive_key(&self, secret: &[u8]) -> Vec<u8> {
+        let mut key = vec![0; 32];
+        pbkdf2_hmac(secret, &self.salt, self.iterations as usize, MessageDigest::sha256(), &mut key).unwrap();
+        key
+    }
+}
+
+// Header, nonce, tag and ciphertext, the header being authenticated too.
+pub fn seal(header: &Header, key: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut nonce = vec![0; NONCE_SIZE];
+    rand_bytes(&mut nonce).unwrap();
+    let aad = header.to_bytes();
+    let mut tag = vec![0; TAG_SIZE];
+    let ciphertext = encrypt_aead(Cipher::aes_256_gcm(), key, Some(&nonce), &aad, plaintext, &mut tag).unwrap();
+
+    let mut data = aad;
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&tag);
+    data.extend_from_slice(&ciphertext);
+    data
+}
+
+pub fn unseal(key: &[u8], data: &[u8]) -> Result<Vec<u8>, String> {
+    let header = Header::parse(data)?;
+    let nonce = &data[HEADER_SIZE..HEADER_SIZE + NONCE_SIZE];
+    let tag = &data[HEADER_SIZE + NONCE_SIZE..HEADER_SIZE + NONCE_SIZE + TAG_SIZE];
+    let ciphertext = &data[HEADER_SIZE + NONCE_SIZE + TAG_SIZE..];
+
+    decrypt_aead(Cipher::aes_256_gcm(), key, Some(nonce), &header.to_bytes(), ciphertext, tag)
+        .map_err(|_| "Error: Wrong passphrase or keyfile".to_string())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len()).step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+// Key cached by `bkm unlock`: "<salt> <key> <expiry>", the key being used
+// only with the salt it was derived with.
+fn format_cache(header: &Header, key: &[u8], expiry: u64) -> String {
+    format!("{} {} {}\n", hex(&header.salt), hex(key), expiry)
+}
+
+fn parse_cache(content: &str, header: &Header, now: u64) -> Option<Vec<u8>> {
+    let mut parts = content.split_whitespace();
+    let (salt, key, expiry) = (parts.next()?, parts.next()?, parts.next()?);
+
+    if unhex(salt)? != header.salt || expiry.parse::<u64>().ok()? <= now {
+        return None;
+    }
+    unhex(key)
+}
+
+pub fn encrypted_path() -> PathBuf {
+    bkm_dir().join("bookmarks.db.enc")
+}
+
+pub fn plain_path() -> PathBuf {
+    bkm_dir().join("bookmarks.db")
+}
+
+pub fn is_encrypted() -> bool {
+    encrypted_path().exists()
+}
+
+// Directory readable by the user only, cleared on logout on most systems.
+// Commands refuse to run when it is not.
+pub fn runtime_dir() -> PathBuf {
+    let uid = unsafe { libc::getuid() };
+    let dir = match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("bkm"),
+        _ => env::temp_dir().join(format!("bkm-{}", uid)),
+    };
+
+    let created = DirBuilder::new().recursive(true).mode(0o700).create(&dir)
+        .map_err(|e| format!("Error: Failed to create {}: {}", dir.display(), e))
+        .and_then(|_| check_private(&dir, uid));
+    if let Err(e) = created {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    dir
+}
+
+// In a shared temporary directory, another user may have created dir first,
+// or made it a link to a directory of theirs.
+fn check_private(dir: &Path, uid: u32) -> Result<(), String> {
+    let metadata = fs::symlink_metadata(dir)
+        .map_err(|e| format!("Error: Failed to read {}: {}", dir.display(), e))?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o777 != 0o700 {
+        return Err(format!("Error: {} must be a directory of yours with mode 700", dir.display()));
+    }
+    Ok(())
+}
+
+fn cache_path() -> PathBuf {
+    runtime_dir().join("key")
+}
+
+// Write a file readable by the user only, replacing it at once.
+pub fn write_private(path: &PathBuf, data: &[u8]) -> Result<(), String> {
+    let tmp = path.with_extension("tmp");
+    OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&tmp)
+        .and_then(|mut f| f.write_all(data).and_then(|_| f.sync_all()))
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| format!("Error: Failed to write {}: {}", path.display(), e))
+}
+
+// Passphrase typed on the terminal, even when stdin and stdout are redirected.
+pub fn prompt(message: &str) -> Result<String, String> {
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty");
+    let mut tty = tty.map_err(|_| "Error: Library is locked, run bkm unlock first".to_string())?;
+
+    write!(tty, "{}", message).and_then(|_| tty.flush()).map_err(|e| format!("Error: {}", e))?;
+    let mut input = tty.try_clone().map_err(|e| format!("Error: {}", e))?;
+    let passphrase = input.read_passwd(&mut tty).map_err(|e| format!("Error: {}", e))?;
+    let _ = writeln!(tty);
+
+    passphrase.ok_or_else(|| "Error: Cancelled".to_string())
+}
+
+// Passphrase or keyfile content, from the environment, the config or the terminal.
+pub fn read_secret(keyfile: Option<&str>, confirm: bool) -> Result<Vec<u8>, String> {
+    let keyfile = keyfile.map(|k| k.to_string())
+        .or_else(|| env::var("BKM_KEYFILE").ok())
+        .or(Config::load().encryption.keyfile);
+    if let Some(keyfile) = keyfile {
+        let path = expand_home(&keyfile);
+        return fs::read(&path).map_err(|e| format!("Error: Failed to read {}: {}", path.display(), e));
+    }
+    if let Ok(passphrase) = env::var("BKM_PASSPHRASE") {
+        return Ok(passphrase.into_bytes());
+    }
+
+    let passphrase = prompt("Passphrase: ")?;
+    if confirm && prompt("Repeat passphrase: ")? != passphrase {
+        return Err("Error: Passphrases do not match".to_string());
+    }
+    Ok(passphrase.into_bytes())
+}
+
+// Key of the encrypted library, cached or derived from its secret, checked
+// by decrypting it. Answers the header, key and decrypted database.
+pub fn unlock(keyfile: Option<&str>) -> Result<(Header, Vec<u8>, Vec<u8>), String> {
+    let path = encrypted_path();
+    let data = fs::read(&path).map_err(|e| format!("Error: Failed to read {}: {}", path.display(), e))?;
+    let header = Header::parse(&data)?;
+
+    let cached = fs::read_to_string(cache_path()).ok()
+        .and_then(|content| parse_cache(&content, &header, now()));
+    if let Some(key) = cached {
+        if let Ok(plaintext) = unseal(&key, &data) {
+            return Ok((header, key, plaintext));
+        }
+    }
+
+    let key = header.derive_key(&read_secret(keyfile, false)?);
+    let plaintext = unseal(&key, &data)?;
+    Ok((header, key, plaintext))
+}
+
+// Keep the key for timeout minutes, so that commands do not ask for it.
+pub fn cache_key(header: &Header, key: &[u8], timeout: u64) -> Result<(), String> {
+    write_private(&cache_path(), format_cache(header, key, now() + timeout * 60).as_bytes())
+}
+
+pub fn forget_key() {
+    let _ = fs::remove_file(cache_path());
+}
+
+// Overwrite a plain file of length len before removing it, on file systems
+// which allow it.
+fn wipe(path: &Path, len: usize) {
+    let _ = fs::write(path, vec![0; len]);
+    let _ = fs::remove_file(path);
+}
+
+// Encrypt the file at path into sealed, wiping it.
+fn seal_file(path: &Path, sealed: &PathBuf, header: &Header, key: &[u8]) -> Result<(), String> {
+    let plaintext = read(path)?;
+    write_private(sealed, &seal(header, key, &plaintext))?;
+    wipe(path, plaintext.len());
+    Ok(())
+}
+
+// Encrypt the plain library with the key of secret, `bkm encrypt`.
+pub fn encrypt_library(secret: &[u8]) -> Result<(), String> {
+    let _lock = lock()?;
+    if is_encrypted() {
+        return Err("Error: Library is encrypted already".to_string());
+    }
+
+    let header = Header::new(ITERATIONS);
+    let key = header.derive_key(secret);
+
+    // Backups are copies of the library, left in plain they would give it away.
+    for backup in backup::list()? {
+        if backup.path.extension().is_some_and(|e| e == "db") {
+            let sealed = PathBuf::from(format!("{}.enc", backup.path.display()));
+            seal_file(&backup.path, &sealed, &header, &key)?;
+        }
+    }
+
+    // Archived pages are encrypted in place, keeping the paths the library
+    // has of them.
+    let snapshots = fs::read_dir(bkm_dir().join("archive")).into_iter().flatten()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "html"));
+    for path in snapshots {
+        if read(&path).map(|data| Header::parse(&data).is_err())? {
+            let sealed = path.with_extension("sealed");
+            seal_file(&path, &sealed, &header, &key)?;
+            fs::rename(&sealed, &path).map_err(|e| format!("Error: Failed to write {}: {}", path.display(), e))?;
+        }
+    }
+
+    seal_file(&plain_path(), &encrypted_path(), &header, &key)
+}
+
+// Write the library back in plain, `bkm decrypt`.
+pub fn decrypt_library(keyfile: Option<&str>) -> Result<(), String> {
+    let _lock = lock()?;
+    if !is_encrypted() {
+        return Err("Error: Library is not encrypted".to_string());
+    }
+
+    let (_, _, plaintext) = unlock(keyfile)?;
+    write_private(&plain_path(), &plaintext)?;
+    let _ = fs::remove_file(encrypted_path());
+    forget_key();
+    Ok(())
+}
+
+// Encrypt data with the key of the library opened by the running command,
+// None when it is not encrypted.
+pub fn seal_with_session(plaintext: &[u8]) -> Option<Vec<u8>> {
+    let session = SESSION.lock().unwrap_or_else(|e| e.into_inner());
+    session.as_ref().map(|s| seal(&s.header, &s.key, plaintext))
+}
+
+// Decrypt data with the key of the opened library when it was encrypted with
+// it, with the key of a secret asked for otherwise.
+pub fn unseal_with_session(data: &[u8]) -> Result<Vec<u8>, String> {
+    let header = Header::parse(data)?;
+    let key = {
+        let session = SESSION.lock().unwrap_or_else(|e| e.into_inner());
+        session.as_ref().filter(|s| s.header == header).map(|s| s.key.clone())
+    };
+
+    match key {
+        Some(key) => unseal(&key, data),
+        None => unseal(&header.derive_key(&read_secret(None, false)?), data),
+    }
+}
+
+// Decrypted library of the running command.
+struct Session {
+    header: Header,
+    key: Vec<u8>,
+    // Copy of this process only.
+    path: PathBuf,
+    // Digests of the encrypted library and of the copy when they last matched.
+    sealed: [u8; 32],
+    copied: [u8; 32],
+    // Held while the copy may be changed, for the whole of short commands and
+    // for each request of long-running ones.
+    lock: Option<File>
+}
+
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+extern "C" fn close_at_exit() {
+    if let Err(e) = close_session() {
+        eprintln!("{}", e);
+    }
+}
+
+// Close the session when the command is interrupted, from a thread waiting
+// for the signals blocked in the others.
+fn close_on_signals() {
+    unsafe {
+        let mut signals: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut signals);
+        for &signal in &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            libc::sigaddset(&mut signals, signal);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &signals, ptr::null_mut());
+
+        thread::spawn(move || {
+            let mut signal = 0;
+            libc::sigwait(&signals, &mut signal);
+            close_at_exit();
+            process::exit(128 + signal);
+        });
+    }
+}
+
+// Take the library lock, waiting for other commands using it.
+pub fn lock() -> Result<File, String> {
+    let path = bkm_dir().join("bookmarks.lock");
+    let file = OpenOptions::new().write(true).create(true).truncate(false).open(&path)
+        .map_err(|e| format!("Error: Failed to open {}: {}", path.display(), e))?;
+    file.lock().map_err(|e| format!("Error: Failed to lock {}: {}", path.display(), e))?;
+    Ok(file)
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("Error: Failed to read {}: {}", path.display(), e))
+}
+
+// Content of the copy as of its last transaction, even while one is going on.
+fn snapshot(path: &Path) -> Result<Vec<u8>, String> {
+    let tmp = path.with_extension("snapshot");
+    let copied = Connection::open(path)
+        .and_then(|conn| conn.backup(DatabaseName::Main, &tmp, None::<fn(Progress)>))
+        .map_err(|e| format!("Error: Failed to read {}: {}", path.display(), e))
+        .and_then(|_| read(&tmp));
+    let _ = fs::remove_file(&tmp);
+    copied
+}
+
+// Replace the content of the copy by plaintext, through SQLite for the
+// connection opened on it to notice.
+fn replace_copy(path: &Path, plaintext: &[u8]) -> Result<(), String> {
+    let tmp = path.with_extension("snapshot");
+    let replaced = write_private(&tmp, plaintext).and_then(|_| {
+        Connection::open(path)
+            .and_then(|mut conn| conn.restore(DatabaseName::Main, &tmp, None::<fn(Progress)>))
+            .map_err(|e| format!("Error: Failed to write {}: {}", path.display(), e))
+    });
+    let _ = fs::remove_file(&tmp);
+    replaced
+}
+
+// Decrypt the library for the running command, answering the path of the
+// copy. The library stays locked until pause_session or the command exits.
+pub fn open_session() -> PathBuf {
+    let mut session = SESSION.lock().unwrap();
+    if let Some(ref session) = *session {
+        return session.path.clone();
+    }
+
+    let opened = lock().and_then(|lock| {
+        let (header, key, plaintext) = unlock(None)?;
+        let path = runtime_dir().join(format!("bookmarks-{}.db", process::id()));
+        write_private(&path, &plaintext)?;
+        let sealed = sha256(&read(&encrypted_path())?);
+        Ok(Session { header, key, path, sealed, copied: sha256(&plaintext), lock: Some(lock) })
+    });
+
+    match opened {
+        Ok(opened) => {
+            let path = opened.path.clone();
+            *session = Some(opened);
+            unsafe {
+                libc::atexit(close_at_exit);
+            }
+            close_on_signals();
+            path
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// Encrypt the copy back into the library when it changed.
+fn seal_session(session: &mut Session) -> Result<(), String> {
+    let plaintext = snapshot(&session.path)?;
+    let copied = sha256(&plaintext);
+    if copied == session.copied {
+        return Ok(());
+    }
+
+    let data = seal(&session.header, &session.key, &plaintext);
+    write_private(&encrypted_path(), &data)?;
+    session.sealed = sha256(&data);
+    session.copied = copied;
+    Ok(())
+}
+
+// Lock the library again for a request, loading the changes other commands
+// made to it meanwhile.
+pub fn resume_session() -> Result<(), String> {
+    let mut session = SESSION.lock().unwrap_or_else(|e| e.into_inner());
+    let session = match *session {
+        Some(ref mut session) if session.lock.is_none() => session,
+        _ => return Ok(()),
+    };
+
+    let lock = lock()?;
+    let data = read(&encrypted_path())?;
+    if sha256(&data) != session.sealed {
+        let plaintext = unseal(&session.key, &data)?;
+        replace_copy(&session.path, &plaintext)?;
+        session.sealed = sha256(&data);
+        session.copied = sha256(&plaintext);
+    }
+    session.lock = Some(lock);
+    Ok(())
+}
+
+// Encrypt the changes of a request back and let other commands use the
+// library until the next one.
+pub fn pause_session() -> Result<(), String> {
+    let mut session = SESSION.lock().unwrap_or_else(|e| e.into_inner());
+    let session = match *session {
+        Some(ref mut session) if session.lock.is_some() => session,
+        _ => return Ok(()),
+    };
+
+    let sealed = seal_session(session);
+    session.lock = None;
+    sealed
+}
+
+// Encrypt the copy of the running command back and remove it.
+pub fn close_session() -> Result<(), String> {
+    let mut session = match SESSION.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        Some(session) => session,
+        None => return Ok(()),
+    };
+
+    if session.lock.is_none() {
+        session.lock = Some(lock()?);
+    }
+    seal_session(&mut session)?;
+
+    let _ = fs::remove_file(&session.path);
+    let _ = fs::remove_file(session.path.with_extension("db-journal"));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile;
+
+    #[test]
+    fn test_seal() {
+        let header = Header::new(1000);
+        let key = header.derive_key(b"passphrase");
+        let data = seal(&header, &key, b"SQLite format 3");
+
+        assert_eq!(Header::parse(&data).unwrap(), header);
+        assert_eq!(unseal(&key, &data).unwrap(), b"SQLite format 3");
+        assert!(unseal(&header.derive_key(b"wrong"), &data).is_err());
+
+        // The header is authenticated, weakening its parameters is noticed.
+        let mut tampered = data.clone();
+        tampered[8] = 1;
+        assert!(unseal(&key, &tampered).is_err());
+        assert!(Header::parse(b"SQLite format 3").is_err());
+    }
+
+    #[test]
+    fn test_check_private() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let uid = unsafe { libc::getuid() };
+        let dir = tmp.path().join("bkm");
+        DirBuilder::new().mode(0o700).create(&dir).unwrap();
+        assert!(check_private(&dir, uid).is_ok());
+        assert!(check_private(&dir, uid + 1).is_err());
+
+        let link = tmp.path().join("link");
+        symlink(&dir, &link).unwrap();
+        assert!(check_private(&link, uid).is_err());
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(check_private(&dir, uid).is_err());
+    }
+
+    #[test]
+    fn test_cache() {
+        let header = Header::new(1000);
+        let key = header.derive_key(b"passphrase");
+        let cache = format_cache(&header, &key, 100);
+
+        assert_eq!(parse_cache(&cache, &header, 99), Some(key));
+        assert_eq!(parse_cache(&cache, &header, 100), None);
+        assert_eq!(parse_cache(&cache, &Header::new(1000), 99), None);
+        assert_eq!(parse_cache("garbage", &header, 99), None);
+    }
+}
@@ -0,0 +1,885 @@
+use encoding_rs::{Encoding, UTF_8};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, COOKIE, LOCATION, USER_AGENT};
+use reqwest::{Certificate, Client, Identity, Proxy, RedirectPolicy, RequestBuilder, Response,
+              StatusCode, Url};
+use select::document::Document;
+use select::node::Node;
+use select::predicate::Name;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, fmt, fs, process};
+
+use config::{Config, NetworkConfig};
+use cookies::CookieJar;
+use normalize::host_matches;
+use utils::expand_home;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REDIRECTS: usize = 10;
+const MAX_BODY_SIZE: u64 = 5 * 1024 * 1024;
+const MAX_TEXT_SIZE: usize = 200 * 1024;
+const MAX_ICON_SIZE: usize = 256 * 1024;
+
+// Elements around the content of pages rather than part of it.
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "script", "style", "noscript", "template", "svg", "nav", "header", "footer", "aside", "form",
+];
+
+pub struct Metadata {
+    pub title: String,
+    pub description: Option<String>,
+    pub canonical_url: Option<String>,
+    pub icon_url: Option<String>
+}
+
+pub struct Icon {
+    pub mime: String,
+    pub data: Vec<u8>
+}
+
+pub struct Page {
+    // URL after redirects.
+    pub url: Url,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>
+}
+
+impl Page {
+    fn is_html(&self) -> bool {
+        self.content_type.as_ref().is_none_or(|t| t.contains("html"))
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        if self.is_html() {
+            parse_metadata(&self.body, self.content_type.as_deref(), &self.url)
+        } else {
+            Metadata {
+                title: self.url.to_string(),
+                description: None,
+                canonical_url: None,
+                icon_url: None,
+            }
+        }
+    }
+
+    // Readable text of the page, empty for pages other than HTML and plain text.
+    pub fn text(&self) -> String {
+        if self.is_html() {
+            extract_text(&self.body, self.content_type.as_deref())
+        } else if self.content_type.as_ref().is_some_and(|t| t.starts_with("text/plain")) {
+            let text = decode(&self.body, self.content_type.as_deref());
+            normalize(&text).unwrap_or_default()
+        } else {
+            String::new()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FetchError {
+    pub message: String,
+    // Status of the response, None when none came back.
+    pub status: Option<u16>
+}
+
+impl FetchError {
+    // Whether trying again later may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self.status {
+            Some(status) => status == 429 || status >= 500,
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<FetchError> for String {
+    fn from(e: FetchError) -> String {
+        e.message
+    }
+}
+
+pub struct Link {
+    pub status: u16,
+    pub final_url: String,
+    // Where the link moved to through permanent redirects (301 and 308) only.
+    pub permanent_url: Option<String>
+}
+
+impl Link {
+    pub fn is_broken(&self) -> bool {
+        self.status >= 400
+    }
+}
+
+// Proxies of http and https URLs, not used for hosts matching no_proxy.
+#[derive(Default)]
+struct Proxies {
+    http: Option<Url>,
+    https: Option<Url>,
+    no_proxy: Vec<String>
+}
+
+impl Proxies {
+    // Proxies set in the config file, else in the environment read with var.
+    fn new<F>(network: &NetworkConfig, var: F) -> Result<Proxies, String>
+        where F: Fn(&str) -> Option<String>
+    {
+        let var = |names: &[&str]| names.iter().filter_map(|n| var(n)).find(|v| !v.trim().is_empty());
+        let parse = |proxy: String| {
+            Url::parse(proxy.trim()).map_err(|e| format!("Error: Invalid proxy {}: {}", proxy, e))
+        };
+
+        let (http, https) = match network.proxy {
+            Some(ref proxy) => {
+                let proxy = parse(proxy.clone())?;
+                (Some(proxy.clone()), Some(proxy))
+            },
+            None => (var(&["HTTP_PROXY", "http_proxy"]).map(parse).transpose()?,
+                     var(&["HTTPS_PROXY", "https_proxy"]).map(parse).transpose()?),
+        };
+
+        let no_proxy = match network.no_proxy {
+            Some(ref hosts) => hosts.clone(),
+            None => var(&["NO_PROXY", "no_proxy"])
+                .map(|v| v.split(',').map(|h| h.trim().to_string()).filter(|h| !h.is_empty()).collect())
+                .unwrap_or_default(),
+        };
+
+        Ok(Proxies { http, https, no_proxy })
+    }
+
+    fn proxy_for(&self, url: &Url) -> Option<Url> {
+        let host = url.host_str()?;
+        if self.no_proxy.iter().any(|pattern| host_matches(pattern, host)) {
+            return None;
+        }
+
+        match url.scheme() {
+            "http" => self.http.clone(),
+            "https" => self.https.clone(),
+            _ => None,
+        }
+    }
+}
+
+pub struct Fetcher {
+    client: Client,
+    // Client leaving redirects to the caller, for checking links.
+    check_client: Client,
+    // Headers sent to hosts matching a pattern.
+    headers: Vec<(String, HeaderMap)>,
+    cookies: Option<CookieJar>
+}
+
+impl Fetcher {
+    // Fetcher sending requests straight to every host.
+    pub fn new(timeout: Duration) -> Fetcher {
+        Fetcher::configured(timeout, &NetworkConfig::default(), |_| None).unwrap()
+    }
+
+    // Fetcher set up by the [network] table of the config file and the
+    // proxy environment variables, exiting when they are invalid.
+    pub fn load(timeout: Duration) -> Fetcher {
+        match Fetcher::configured(timeout, &Config::load().network, |name| env::var(name).ok()) {
+            Ok(fetcher) => fetcher,
+            Err(e) => {
+                println!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    fn configured<F>(timeout: Duration, network: &NetworkConfig, var: F) -> Result<Fetcher, String>
+        where F: Fn(&str) -> Option<String>
+    {
+        let proxies = Arc::new(Proxies::new(network, var)?);
+
+        let mut headers: Vec<(String, HeaderMap)> = Vec::new();
+        for (pattern, values) in &network.headers {
+            let mut map = HeaderMap::new();
+            for (name, value) in values {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| format!("Error: Invalid header name {}", name))?;
+                let value = HeaderValue::from_str(value)
+                    .map_err(|_| format!("Error: Invalid value of header {}", name))?;
+                map.insert(name, value);
+            }
+            headers.push((pattern.clone(), map));
+        }
+
+        let read = |path: &str| {
+            fs::read(expand_home(path)).map_err(|e| format!("Error: Failed to read {}: {}", path, e))
+        };
+        let cookies = match network.cookies {
+            Some(ref path) => Some(CookieJar::parse(&String::from_utf8_lossy(&read(path)?))),
+            None => None,
+        };
+        let identity = match network.client_cert {
+            Some(ref path) => Some(read(path)?),
+            None => None,
+        };
+        let ca_cert = match network.ca_cert {
+            Some(ref path) => Some(read(path)?),
+            None => None,
+        };
+
+        let build = |redirect: RedirectPolicy| -> Result<Client, String> {
+            let mut defaults = HeaderMap::new();
+            let user_agent = format!("bkm/{}", env!("CARGO_PKG_VERSION"));
+            defaults.insert(USER_AGENT, HeaderValue::from_str(&user_agent).unwrap());
+
+            let proxies = proxies.clone();
+            let mut builder = Client::builder()
+                .timeout(timeout)
+                .redirect(redirect)
+                .default_headers(defaults)
+                .proxy(Proxy::custom(move |url| proxies.proxy_for(url)));
+
+            if let Some(ref der) = identity {
+                let password = network.client_cert_password.as_deref().unwrap_or("");
+                let identity = Identity::from_pkcs12_der(der, password)
+                    .map_err(|e| format!("Error: Invalid client certificate: {}", e))?;
+                builder = builder.identity(identity);
+            }
+            if let Some(ref pem) = ca_cert {
+                let cert = Certificate::from_pem(pem)
+                    .map_err(|e| format!("Error: Invalid CA certificate: {}", e))?;
+                builder = builder.add_root_certificate(cert);
+            }
+
+            builder.build().map_err(|e| format!("Error: Failed to build HTTP client: {}", e))
+        };
+
+        // Redirects to another host are left to get, which sends them with the
+        // headers and cookies of that host instead of those of the first one.
+        let same_host = RedirectPolicy::custom(|attempt| {
+            let host = attempt.previous().last().and_then(|u| u.host_str().map(|h| h.to_string()));
+            if host.as_deref() != attempt.url().host_str() {
+                attempt.stop()
+            } else {
+                RedirectPolicy::limited(MAX_REDIRECTS).redirect(attempt)
+            }
+        });
+
+        Ok(Fetcher {
+            client: build(same_host)?,
+            check_client: build(RedirectPolicy::none())?,
+            headers,
+            cookies,
+        })
+    }
+
+    // Add the configured headers and cookies of the host of url to request.
+    fn prepare(&self, mut request: RequestBuilder, url: &Url) -> RequestBuilder {
+        let host = url.host_str().unwrap_or("");
+        for (pattern, headers) in &self.headers {
+            if host_matches(pattern, host) {
+                request = request.headers(headers.clone());
+            }
+        }
+        if let Some(cookie) = self.cookies.as_ref().and_then(|jar| jar.header(url)) {
+            request = request.header(COOKIE, cookie);
+        }
+        request
+    }
+
+    pub fn get(&self, url: &str) -> Result<Page, FetchError> {
+        let mut next = Url::parse(url).map_err(|e| FetchError {
+            message: format!("Error: Failed to fetch {}: {}", url, e),
+            status: None,
+        })?;
+
+        let mut hops = 0;
+        let mut res = loop {
+            let res = self.prepare(self.client.get(next.clone()), &next).send().map_err(|e| FetchError {
+                message: format!("Error: Failed to fetch {}: {}", url, e),
+                status: None,
+            })?;
+
+            let location = res.headers().get(LOCATION)
+                .and_then(|l| l.to_str().ok())
+                .and_then(|l| res.url().join(l).ok());
+            match location {
+                Some(location) if res.status().is_redirection() && hops < MAX_REDIRECTS => {
+                    hops += 1;
+                    next = location;
+                },
+                _ => break res,
+            }
+        };
+
+        if !res.status().is_success() {
+            return Err(FetchError {
+                message: format!("Error: Failed to fetch {}: {}", url, res.status()),
+                status: Some(res.status().as_u16()),
+            });
+        }
+
+        let final_url = res.url().clone();
+        let content_type = res.headers().get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+
+        let mut body: Vec<u8> = Vec::new();
+        res.by_ref().take(MAX_BODY_SIZE).read_to_end(&mut body).map_err(|e| FetchError {
+            message: format!("Error: Failed to fetch {}: {}", url, e),
+            status: None,
+        })?;
+
+        Ok(Page { url: final_url, content_type, body })
+    }
+
+    pub fn fetch(&self, url: &str) -> Result<Metadata, String> {
+        Ok(self.get(url)?.metadata())
+    }
+
+    // Status and final URL of url after redirects, Err when no response came
+    // back at all (DNS failure, refused connection, timeout...).
+    pub fn check(&self, url: &str) -> Result<Link, String> {
+        let mut url = Url::parse(url).map_err(|e| e.to_string())?;
+        let mut permanent_url: Option<String> = None;
+        let mut permanent = true;
+
+        for _ in 0..=MAX_REDIRECTS {
+            let res = self.head_or_get(&url)?;
+            let status = res.status();
+            let location = res.headers().get(LOCATION)
+                .and_then(|l| l.to_str().ok())
+                .and_then(|l| url.join(l).ok());
+
+            match location {
+                Some(next) if status.is_redirection() => {
+                    permanent = permanent && (status == StatusCode::MOVED_PERMANENTLY
+                                              || status == StatusCode::PERMANENT_REDIRECT);
+                    if permanent {
+                        permanent_url = Some(next.to_string());
+                    }
+                    url = next;
+                },
+                _ => return Ok(Link {
+                    status: status.as_u16(),
+                    final_url: url.to_string(),
+                    permanent_url,
+                }),
+            }
+        }
+
+        Err(format!("{}: too many redirects", url))
+    }
+
+    // Icon of the page at url, from icon_url (its <link rel=icon>) or
+    // /favicon.ico of the site. None when the site has none, Err when it could
+    // not be told.
+    pub fn favicon(&self, url: &Url, icon_url: Option<&str>) -> Result<Option<Icon>, FetchError> {
+        let candidates = icon_url.and_then(|u| url.join(u).ok()).into_iter()
+            .chain(url.join("/favicon.ico").ok());
+        let mut transient: Option<FetchError> = None;
+
+        for candidate in candidates {
+            let page = match self.get(candidate.as_str()) {
+                Ok(page) => page,
+                Err(e) => {
+                    if e.is_transient() {
+                        transient = Some(e);
+                    }
+                    continue;
+                },
+            };
+            let mime = page.content_type.as_deref()
+                .and_then(|t| t.split(';').next())
+                .map(|t| t.trim().to_lowercase())
+                .unwrap_or_default();
+            let is_ico = page.body.starts_with(&[0, 0, 1, 0]);
+
+            if page.body.is_empty() || page.body.len() > MAX_ICON_SIZE {
+                continue;
+            }
+            if mime.starts_with("image/") {
+                return Ok(Some(Icon { mime, data: page.body }));
+            }
+            if is_ico {
+                return Ok(Some(Icon { mime: "image/x-icon".to_string(), data: page.body }));
+            }
+        }
+
+        // No icon is only known when every candidate was answered.
+        match transient {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    // Some servers answer HEAD with an error but GET just fine.
+    fn head_or_get(&self, url: &Url) -> Result<Response, String> {
+        let res = self.prepare(self.check_client.head(url.clone()), url)
+            .send().map_err(|e| e.to_string())?;
+
+        if res.status().is_client_error() || res.status().is_server_error() {
+            self.prepare(self.check_client.get(url.clone()), url)
+                .send().map_err(|e| e.to_string())
+        } else {
+            Ok(res)
+        }
+    }
+}
+
+impl Default for Fetcher {
+    fn default() -> Self {
+        Fetcher::new(DEFAULT_TIMEOUT)
+    }
+}
+
+pub fn parse_metadata(body: &[u8], content_type: Option<&str>, url: &Url) -> Metadata {
+    let doc = Document::from(decode(body, content_type).as_str());
+
+    let title = doc.find(Name("title")).next().map(|t| t.text())
+        .and_then(|t| normalize(&t))
+        .or_else(|| meta_content(&doc, &["og:title", "twitter:title"]))
+        .or_else(|| doc.find(Name("h1")).next().and_then(|h| normalize(&h.text())))
+        .unwrap_or_else(|| url.to_string());
+
+    let description = meta_content(&doc, &["description", "og:description", "twitter:description"]);
+
+    let canonical_url = link_href(&doc, url, "canonical");
+    let icon_url = link_href(&doc, url, "icon")
+        .or_else(|| link_href(&doc, url, "apple-touch-icon"));
+
+    Metadata { title, description, canonical_url, icon_url }
+}
+
+// Absolute href of the first <link> with rel among its rel values.
+fn link_href(doc: &Document, url: &Url, rel: &str) -> Option<String> {
+    doc.find(Name("link"))
+        .find(|l| {
+            l.attr("rel").is_some_and(|rels| {
+                rels.split_whitespace().any(|r| r.eq_ignore_ascii_case(rel))
+            })
+        })
+        .and_then(|l| l.attr("href"))
+        .and_then(|href| url.join(href.trim()).ok())
+        .map(|u| u.to_string())
+}
+
+// Text of the <main> or <article> of an HTML page, of its <body> otherwise,
+// leaving out scripts, navigation, headers and footers.
+pub fn extract_text(body: &[u8], content_type: Option<&str>) -> String {
+    let doc = Document::from(decode(body, content_type).as_str());
+
+    let root = doc.find(Name("main")).next()
+        .or_else(|| doc.find(Name("article")).next())
+        .or_else(|| doc.find(Name("body")).next());
+
+    let mut text = String::new();
+    match root {
+        Some(root) => collect_text(root, &mut text),
+        None => return String::new(),
+    }
+
+    let mut text = normalize(&text).unwrap_or_default();
+    if text.len() > MAX_TEXT_SIZE {
+        let end = (0..=MAX_TEXT_SIZE).rev().find(|&i| text.is_char_boundary(i)).unwrap();
+        text.truncate(end);
+    }
+
+    text
+}
+
+fn collect_text(node: Node, text: &mut String) {
+    if let Some(t) = node.as_text() {
+        text.push_str(t);
+        return;
+    }
+    if node.name().is_some_and(|name| SKIPPED_ELEMENTS.contains(&name)) {
+        return;
+    }
+
+    for child in node.children() {
+        collect_text(child, text);
+    }
+    // Keep words of neighbouring elements apart.
+    text.push(' ');
+}
+
+// Text of body in the encoding detected by detect_encoding.
+pub fn decode(body: &[u8], content_type: Option<&str>) -> String {
+    let (text, _, _) = detect_encoding(body, content_type).decode(body);
+    text.into_owned()
+}
+
+// Content of the first <meta> named by keys, in order of preference.
+fn meta_content(doc: &Document, keys: &[&str]) -> Option<String> {
+    for key in keys {
+        for meta in doc.find(Name("meta")) {
+            let name = meta.attr("property").or_else(|| meta.attr("name")).unwrap_or("");
+            if !name.eq_ignore_ascii_case(key) {
+                continue;
+            }
+            if let Some(content) = meta.attr("content").and_then(normalize) {
+                return Some(content);
+            }
+        }
+    }
+
+    None
+}
+
+// Encoding from BOM, Content-Type charset or <meta> charset, UTF-8 otherwise.
+fn detect_encoding(body: &[u8], content_type: Option<&str>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return encoding;
+    }
+
+    if let Some(encoding) = content_type.and_then(charset).and_then(|c| Encoding::for_label(c.as_bytes())) {
+        return encoding;
+    }
+
+    let head = String::from_utf8_lossy(&body[..body.len().min(1024)]).to_lowercase();
+    head.match_indices("<meta")
+        .filter_map(|(i, _)| {
+            let end = head[i..].find('>').map_or(head.len(), |e| i + e);
+            charset(&head[i..end])
+        })
+        .filter_map(|c| Encoding::for_label(c.as_bytes()))
+        .next()
+        .unwrap_or(UTF_8)
+}
+
+fn charset(s: &str) -> Option<String> {
+    let lower = s.to_lowercase();
+    let i = lower.find("charset=")? + "charset=".len();
+    let label: String = lower[i..].trim_start_matches(['"', '\''])
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_' || *c == ':' || *c == '.')
+        .collect();
+
+    if label.is_empty() {
+        None
+    } else {
+        Some(label)
+    }
+}
+
+// Collapse whitespace, None when nothing is left.
+fn normalize(s: &str) -> Option<String> {
+    let s = s.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+    use testserver::{serve, Response};
+
+    fn parse(html: &str) -> Metadata {
+        parse_metadata(html.as_bytes(), None, &Url::parse("https://example.com/a/b").unwrap())
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let metadata = parse("<html><head><title>\n  Example\n  Domain </title>
+            <meta name=\"Description\" content=\"An example\">
+            <meta property=\"og:description\" content=\"Other\">
+            <link rel=\"canonical\" href=\"/c\">
+            <link rel=\"apple-touch-icon\" href=\"touch.png\">
+            <link rel=\"Shortcut Icon\" href=\"icon.png\"></head></html>");
+
+        assert_eq!(metadata.title, "Example Domain");
+        assert_eq!(metadata.description, Some("An example".to_string()));
+        assert_eq!(metadata.canonical_url, Some("https://example.com/c".to_string()));
+        assert_eq!(metadata.icon_url, Some("https://example.com/a/icon.png".to_string()));
+    }
+
+    #[test]
+    fn test_title_fallback() {
+        let metadata = parse("<title> </title><meta property=\"og:title\" content=\"OG\">
+            <meta name=\"twitter:title\" content=\"Twitter\"><h1>Heading</h1>");
+        assert_eq!(metadata.title, "OG");
+
+        let metadata = parse("<meta name=\"twitter:title\" content=\"Twitter\"><h1>Heading</h1>");
+        assert_eq!(metadata.title, "Twitter");
+
+        let metadata = parse("<h1>Heading <b>1</b></h1>");
+        assert_eq!(metadata.title, "Heading 1");
+
+        let metadata = parse("<p>No title</p>");
+        assert_eq!(metadata.title, "https://example.com/a/b");
+        assert_eq!(metadata.description, None);
+        assert_eq!(metadata.canonical_url, None);
+        assert_eq!(metadata.icon_url, None);
+    }
+
+    #[test]
+    fn test_extract_text() {
+        let html = "<html><head><title>Title</title><style>p { color: red }</style></head>
+            <body><nav><a href=\"/\">Home</a></nav><header>Site</header>
+            <p>First <b>paragraph</b></p><script>var x = 1;</script>
+            <div>Second\n   paragraph</div><footer>Copyright</footer></body></html>";
+        assert_eq!(extract_text(html.as_bytes(), None), "First paragraph Second paragraph");
+
+        let html = "<body><p>Menu</p><article><h1>Post</h1><p>Text</p></article></body>";
+        assert_eq!(extract_text(html.as_bytes(), None), "Post Text");
+        assert_eq!(extract_text(b"", None), "");
+    }
+
+    #[test]
+    fn test_detect_encoding() {
+        let (body, _, _) = encoding_rs::SHIFT_JIS.encode("<title>日本語</title>");
+        let url = Url::parse("https://example.com").unwrap();
+
+        let metadata = parse_metadata(&body, Some("text/html; charset=Shift_JIS"), &url);
+        assert_eq!(metadata.title, "日本語");
+
+        let html = [b"<meta charset=\"shift_jis\">".to_vec(), body.to_vec()].concat();
+        assert_eq!(parse_metadata(&html, Some("text/html"), &url).title, "日本語");
+
+        let html = [b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\">"
+            .to_vec(), body.to_vec()].concat();
+        assert_eq!(parse_metadata(&html, None, &url).title, "日本語");
+    }
+
+    #[test]
+    fn test_fetch() {
+        let server = serve(|req| match req.path.as_str() {
+            "/" => Response::redirect(301, "/page"),
+            "/page" => Response::html("<title>Page</title>")
+                .header("Content-Type", "text/html; charset=utf-8"),
+            "/loop" => Response::redirect(302, "/loop"),
+            "/slow" => Response::html("<title>Slow</title>").delay(Duration::from_secs(3)),
+            "/file" => Response::new(200, b"%PDF").header("Content-Type", "application/pdf"),
+            _ => Response::new(404, b"<title>Not Found</title>"),
+        });
+        let fetcher = Fetcher::new(Duration::from_secs(1));
+
+        assert_eq!(fetcher.fetch(&server.url("/")).unwrap().title, "Page");
+        assert_eq!(fetcher.fetch(&server.url("/file")).unwrap().title, server.url("/file"));
+        assert!(fetcher.fetch(&server.url("/loop")).is_err());
+        assert!(fetcher.get(&server.url("/slow")).err().unwrap().is_transient());
+        assert!(!fetcher.get(&server.url("/missing")).err().unwrap().is_transient());
+        assert!(fetcher.fetch("not a url").is_err());
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "GET");
+        assert!(request.header("User-Agent").unwrap().starts_with("bkm/"));
+    }
+
+    #[test]
+    fn test_favicon() {
+        let server = serve(|req| match req.path.as_str() {
+            "/static/icon.png" => Response::new(200, b"\x89PNG").header("Content-Type", "image/png"),
+            "/favicon.ico" => Response::new(200, b"\x00\x00\x01\x00")
+                .header("Content-Type", "application/octet-stream"),
+            _ => Response::new(404, b""),
+        });
+        let no_icon = serve(|req| match req.path.as_str() {
+            "/favicon.ico" => Response::html("<title>Not Found</title>"),
+            "/error" => Response::new(503, b""),
+            _ => Response::new(404, b""),
+        });
+        let fetcher = Fetcher::new(Duration::from_secs(1));
+        let url = Url::parse(&server.url("/a/page")).unwrap();
+
+        let icon = fetcher.favicon(&url, Some("/static/icon.png")).unwrap().unwrap();
+        assert_eq!((icon.mime.as_str(), icon.data.as_slice()), ("image/png", &b"\x89PNG"[..]));
+
+        let icon = fetcher.favicon(&url, Some("/missing.png")).unwrap().unwrap();
+        assert_eq!(icon.mime, "image/x-icon");
+        assert!(fetcher.favicon(&url, None).unwrap().is_some());
+
+        let url = Url::parse(&no_icon.url("/")).unwrap();
+        assert!(fetcher.favicon(&url, None).unwrap().is_none());
+        let url = Url::parse(&no_icon.url("/error")).unwrap();
+        assert!(fetcher.favicon(&url, Some("/error")).is_err());
+        assert!(fetcher.favicon(&Url::parse("http://127.0.0.1:1/").unwrap(), None).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let server = serve(|req| match (req.method.as_str(), req.path.as_str()) {
+            (_, "/") => Response::redirect(301, "/page"),
+            (_, "/page") => Response::html("<title>Page</title>"),
+            (_, "/old") => Response::redirect(308, "/"),
+            (_, "/temporary") => Response::redirect(302, "/"),
+            (_, "/moved") => Response::redirect(301, "/temporary"),
+            (_, "/loop") => Response::redirect(301, "/loop"),
+            ("HEAD", "/get-only") => Response::new(405, b""),
+            ("GET", "/get-only") => Response::html("<title>GET only</title>"),
+            (_, "/error") => Response::new(500, b""),
+            (_, "/slow") => Response::html("").delay(Duration::from_secs(3)),
+            _ => Response::new(404, b""),
+        });
+        let fetcher = Fetcher::new(Duration::from_secs(1));
+
+        let link = fetcher.check(&server.url("/")).unwrap();
+        assert_eq!((link.status, link.final_url), (200, server.url("/page")));
+        assert_eq!(link.permanent_url, Some(server.url("/page")));
+        assert_eq!(fetcher.check(&server.url("/old")).unwrap().permanent_url,
+                   Some(server.url("/page")));
+        assert_eq!(fetcher.check(&server.url("/moved")).unwrap().permanent_url,
+                   Some(server.url("/temporary")));
+        assert_eq!(fetcher.check(&server.url("/page")).unwrap().permanent_url, None);
+
+        let link = fetcher.check(&server.url("/temporary")).unwrap();
+        assert_eq!((link.final_url, link.permanent_url), (server.url("/page"), None));
+        assert!(fetcher.check(&server.url("/loop")).is_err());
+        assert_eq!(fetcher.check(&server.url("/get-only")).unwrap().status, 200);
+        assert_eq!(fetcher.check(&server.url("/missing")).unwrap().status, 404);
+        assert!(fetcher.check(&server.url("/error")).unwrap().is_broken());
+        assert!(fetcher.check(&server.url("/slow")).is_err());
+        assert!(fetcher.check("http://127.0.0.1:1/").is_err());
+
+        let methods: Vec<String> = server.requests().into_iter()
+            .filter(|r| r.path == "/get-only")
+            .map(|r| r.method)
+            .collect();
+        assert_eq!(methods, vec!["HEAD", "GET"]);
+    }
+
+    #[test]
+    fn test_proxies() {
+        let env = |name: &str| match name {
+            "HTTPS_PROXY" => Some("http://secure-proxy:3128".to_string()),
+            "http_proxy" => Some("http://proxy:3128".to_string()),
+            "NO_PROXY" => Some("localhost, .corp.example.com".to_string()),
+            _ => None,
+        };
+        let proxy_for = |proxies: &Proxies, url: &str| {
+            proxies.proxy_for(&Url::parse(url).unwrap()).map(|u| u.to_string())
+        };
+
+        let proxies = Proxies::new(&NetworkConfig::default(), env).unwrap();
+        assert_eq!(proxy_for(&proxies, "https://github.com/"), Some("http://secure-proxy:3128/".to_string()));
+        assert_eq!(proxy_for(&proxies, "http://github.com/"), Some("http://proxy:3128/".to_string()));
+        assert_eq!(proxy_for(&proxies, "http://localhost:8080/"), None);
+        assert_eq!(proxy_for(&proxies, "https://wiki.corp.example.com/"), None);
+
+        let mut network = NetworkConfig {
+            proxy: Some("http://config-proxy:8080".to_string()),
+            no_proxy: Some(Vec::new()),
+            ..NetworkConfig::default()
+        };
+        let proxies = Proxies::new(&network, env).unwrap();
+        assert_eq!(proxy_for(&proxies, "https://localhost/"), Some("http://config-proxy:8080/".to_string()));
+
+        network.proxy = Some("not a proxy".to_string());
+        assert!(Proxies::new(&network, env).is_err());
+    }
+
+    #[test]
+    fn test_fetch_through_proxy() {
+        // Requests to a proxy carry the whole URL in their request line.
+        let proxy = serve(|req| match req.path.as_str() {
+            "http://intranet.test/page" => Response::html("<title>Intranet</title>"),
+            _ => Response::new(502, b""),
+        });
+
+        let network = NetworkConfig { proxy: Some(proxy.url("/")), ..NetworkConfig::default() };
+        let fetcher = Fetcher::configured(Duration::from_secs(1), &network, |_| None).unwrap();
+
+        assert_eq!(fetcher.fetch("http://intranet.test/page").unwrap().title, "Intranet");
+        assert_eq!(proxy.requests()[0].header("Host"), Some("intranet.test"));
+    }
+
+    #[test]
+    fn test_headers_and_cookies() {
+        let server = serve(|_| Response::html("<title>Page</title>"));
+
+        let mut jar = NamedTempFile::new().unwrap();
+        writeln!(jar, "127.0.0.1\tFALSE\t/private\tFALSE\t0\tsession\tabc").unwrap();
+
+        let mut headers = BTreeMap::new();
+        headers.insert("X-Token".to_string(), "secret".to_string());
+        let mut network = NetworkConfig::default();
+        network.headers.insert("127.0.0.1".to_string(), headers);
+        network.headers.insert("*.example.com".to_string(), BTreeMap::new());
+        network.cookies = Some(jar.path().to_str().unwrap().to_string());
+        let fetcher = Fetcher::configured(Duration::from_secs(1), &network, |_| None).unwrap();
+
+        fetcher.get(&server.url("/private/page")).unwrap();
+        fetcher.get(&server.url("/public")).unwrap();
+        fetcher.check(&server.url("/private/check")).unwrap();
+
+        let requests = server.requests();
+        assert!(requests.iter().all(|r| r.header("X-Token") == Some("secret")));
+        assert_eq!(requests[0].header("Cookie"), Some("session=abc"));
+        assert_eq!(requests[1].header("Cookie"), None);
+        assert_eq!(requests[2].header("Cookie"), Some("session=abc"));
+    }
+
+    #[test]
+    fn test_cross_host_redirects() {
+        // One server reached as 127.0.0.1 and as localhost, two hosts.
+        let server = serve(|req| {
+            let port = req.header("Host").unwrap_or("").rsplit(':').next().unwrap_or("").to_string();
+            match req.path.as_str() {
+                "/away" => Response::redirect(302, &format!("http://localhost:{}/page", port)),
+                "/back" => Response::redirect(302, &format!("http://127.0.0.1:{}/page", port)),
+                _ => Response::html("<title>Page</title>"),
+            }
+        });
+
+        let mut jar = NamedTempFile::new().unwrap();
+        writeln!(jar, "127.0.0.1\tFALSE\t/\tFALSE\t0\tsession\tabc").unwrap();
+        let mut headers = BTreeMap::new();
+        headers.insert("X-Token".to_string(), "secret".to_string());
+        let mut network = NetworkConfig::default();
+        network.headers.insert("127.0.0.1".to_string(), headers);
+        network.cookies = Some(jar.path().to_str().unwrap().to_string());
+        let fetcher = Fetcher::configured(Duration::from_secs(1), &network, |_| None).unwrap();
+
+        fetcher.get(&server.url("/away")).unwrap();
+        fetcher.get(&server.url("/back").replace("127.0.0.1", "localhost")).unwrap();
+
+        let sent: Vec<(String, bool, bool)> = server.requests().into_iter()
+            .map(|r| (format!("{} {}", r.header("Host").unwrap_or("").split(':').next().unwrap_or(""), r.path),
+                      r.header("X-Token") == Some("secret"), r.header("Cookie") == Some("session=abc")))
+            .collect();
+        assert_eq!(sent, vec![
+            ("127.0.0.1 /away".to_string(), true, true),
+            ("localhost /page".to_string(), false, false),
+            ("localhost /back".to_string(), false, false),
+            ("127.0.0.1 /page".to_string(), true, true),
+        ]);
+    }
+
+    #[test]
+    fn test_configured_errors() {
+        let configured = |network: &NetworkConfig| {
+            Fetcher::configured(DEFAULT_TIMEOUT, network, |_| None).err()
+        };
+
+        let network = NetworkConfig {
+            cookies: Some("/nonexistent/cookies.txt".to_string()),
+            ..NetworkConfig::default()
+        };
+        assert!(configured(&network).unwrap().starts_with("Error: Failed to read"));
+
+        let mut cert = NamedTempFile::new().unwrap();
+        cert.write_all(b"not a certificate").unwrap();
+        let network = NetworkConfig {
+            client_cert: Some(cert.path().to_str().unwrap().to_string()),
+            ..NetworkConfig::default()
+        };
+        assert!(configured(&network).unwrap().starts_with("Error: Invalid client certificate"));
+
+        let mut headers = BTreeMap::new();
+        headers.insert("Bad Name".to_string(), "x".to_string());
+        let mut network = NetworkConfig::default();
+        network.headers.insert("*".to_string(), headers);
+        assert!(configured(&network).is_some());
+    }
+}
@@ -0,0 +1,131 @@
+// History of the changes of bookmarks: every change of a field is recorded as
+// a revision of its bookmark with the old and new values, so that refreshes
+// and rewrites lose nothing and any revision can be gone back to.
+
+use serde_json;
+
+use database::DB;
+
+#[derive(Debug, PartialEq)]
+pub struct Revision {
+    pub rev: i64,
+    // Date of the change, as "YYYY-MM-DD HH:MM:SS" in local time.
+    pub time: String,
+    // "title", "url", "description", "notes" or "tags".
+    pub field: String,
+    pub old: String,
+    pub new: String
+}
+
+// Tags as recorded in the history, a sorted JSON array since tags may contain
+// commas.
+pub fn encode_tags(tags: &[String]) -> String {
+    let mut tags = tags.to_vec();
+    tags.sort();
+    serde_json::to_string(&tags).unwrap()
+}
+
+pub fn decode_tags(value: &str) -> Vec<String> {
+    serde_json::from_str(value).unwrap_or_default()
+}
+
+// Set the fields of bookmark id changed after revision rev back to their
+// values at rev, 0 going back to before the first change. Reverting is a
+// change of its own, recorded as the next revisions. Answers the number of
+// fields set.
+pub fn revert(db: &DB, id: i64, rev: i64) -> Result<usize, String> {
+    let bookmark = db.get_bookmark_by_id(id).map_err(|e| format!("{} {}", e, id))?;
+    let history = db.get_history(id);
+    let last = history.last().map_or(0, |r| r.rev);
+    if rev < 0 || rev > last {
+        return Err(format!("Error: No revision {} of bookmark {}", rev, id));
+    }
+
+    // Value of a field at rev is the old value of its first change after it.
+    let value = |field: &str| {
+        history.iter()
+            .find(|r| r.rev > rev && r.field == field)
+            .map(|r| r.old.clone())
+    };
+
+    let mut count = 0;
+    let title = value("title");
+    let url = value("url");
+    if title.is_some() || url.is_some() {
+        let title = title.unwrap_or_else(|| bookmark.title.clone());
+        let url = url.unwrap_or_else(|| bookmark.url.clone());
+        db.update_bookmark(id, &title, &url).map_err(|e| e.to_string())?;
+        count += (title != bookmark.title) as usize + (url != bookmark.url) as usize;
+    }
+    if let Some(description) = value("description") {
+        count += (description != bookmark.description) as usize;
+        db.update_description(id, &description);
+    }
+    if let Some(notes) = value("notes") {
+        count += (notes != bookmark.notes) as usize;
+        db.update_notes(id, &notes);
+    }
+    if let Some(tags) = value("tags") {
+        count += (tags != encode_tags(&bookmark.tags)) as usize;
+        db.set_tags(id, &decode_tags(&tags));
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let db = DB::open_in_memory();
+        let id = db.add_bookmark(&"GitHub".to_string(), &"https://github.com/".to_string()).unwrap();
+        db.add_tag(id, "git");
+        db.add_tag(id, "git");
+        db.update_bookmark(id, "GitHub!", "https://github.com/").unwrap();
+        db.set_tags(id, &["hosting".to_string(), "git".to_string()]);
+        db.update_notes(id, "Code");
+        db.remove_tag(id, "git");
+
+        let history = db.get_history(id);
+        let changes: Vec<(i64, &str, &str, &str)> = history.iter()
+            .map(|r| (r.rev, r.field.as_str(), r.old.as_str(), r.new.as_str()))
+            .collect();
+        assert_eq!(changes, vec![
+            (1, "tags", "[]", r#"["git"]"#),
+            (2, "title", "GitHub", "GitHub!"),
+            (3, "tags", r#"["git"]"#, r#"["git","hosting"]"#),
+            (4, "notes", "", "Code"),
+            (5, "tags", r#"["git","hosting"]"#, r#"["hosting"]"#),
+        ]);
+
+        assert_eq!(revert(&db, id, 2), Ok(2));
+        let bookmark = db.get_bookmark_by_id(id).unwrap();
+        assert_eq!(bookmark.title, "GitHub!");
+        assert_eq!(bookmark.tags, vec!["git"]);
+        assert_eq!(bookmark.notes, "");
+        assert_eq!(db.get_history(id).len(), 7);
+
+        assert_eq!(revert(&db, id, 0), Ok(2));
+        let bookmark = db.get_bookmark_by_id(id).unwrap();
+        assert_eq!(bookmark.title, "GitHub");
+        assert!(bookmark.tags.is_empty());
+        assert!(revert(&db, id, 10).is_err());
+    }
+
+    #[test]
+    fn test_tags() {
+        let tags = vec!["rust".to_string(), "async, await".to_string()];
+        assert_eq!(encode_tags(&tags), r#"["async, await","rust"]"#);
+        assert_eq!(decode_tags(r#"["async, await","rust"]"#), vec!["async, await", "rust"]);
+        assert!(decode_tags("[]").is_empty());
+
+        let db = DB::open_in_memory();
+        let id = db.add_bookmark(&"Tokio".to_string(), &"https://tokio.rs/".to_string()).unwrap();
+        db.set_tags(id, &tags);
+        db.set_tags(id, &["rust".to_string()]);
+        assert_eq!(revert(&db, id, 1), Ok(1));
+        assert_eq!(db.get_bookmark_by_id(id).unwrap().tags, vec!["async, await", "rust"]);
+    }
+}
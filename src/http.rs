@@ -0,0 +1,267 @@
+// Minimal HTTP/1.1 server for the local API, one request per connection.
+
+use reqwest::Url;
+use serde_json::{self, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const MAX_HEADERS: usize = 100;
+// Longest request line or header line.
+const MAX_LINE: usize = 8 * 1024;
+const MAX_BODY_SIZE: usize = 20 * 1024 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Request {
+    pub method: String,
+    // Path without the query string, percent-decoded.
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|&(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    // Fields of a form posted as application/x-www-form-urlencoded.
+    pub fn form(&self) -> Vec<(String, String)> {
+        let mut url = Url::parse("http://localhost").unwrap();
+        url.set_query(Some(&String::from_utf8_lossy(&self.body)));
+        url.query_pairs().into_owned().collect()
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.header("Cookie")?.split(';')
+            .filter_map(|c| {
+                let mut parts = c.trim().splitn(2, '=');
+                Some((parts.next()?, parts.next()?))
+            })
+            .find(|&(k, _)| k == name)
+            .map(|(_, v)| v)
+    }
+
+    // Path split into its segments, "/bookmarks/1" into ["bookmarks", "1"].
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.split('/').filter(|s| !s.is_empty()).collect()
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Response {
+        Response { status, headers: vec![("Content-Type".to_string(), content_type.to_string())], body }
+    }
+
+    pub fn json(status: u16, value: &Value) -> Response {
+        let body = serde_json::to_vec_pretty(value).unwrap();
+        Response::new(status, "application/json", body)
+    }
+
+    // JSON error response, {"error": message}.
+    pub fn error(status: u16, message: &str) -> Response {
+        Response::json(status, &json!({ "error": message.trim_start_matches("Error: ") }))
+    }
+
+    pub fn html(status: u16, html: String) -> Response {
+        Response::new(status, "text/html; charset=utf-8", html.into_bytes())
+    }
+
+    // See other, sending browsers to location after a form is posted.
+    pub fn redirect(location: &str) -> Response {
+        Response::empty(303).header("Location", location)
+    }
+
+    pub fn empty(status: u16) -> Response {
+        Response { status, headers: Vec::new(), body: Vec::new() }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+// Answer every connection to listener with the response of handler.
+pub fn serve<F>(listener: TcpListener, handler: F)
+    where F: Fn(&Request) -> Response + Send + Sync + 'static
+{
+    let handler = Arc::new(handler);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let handler = handler.clone();
+
+        thread::spawn(move || {
+            let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+            let response = match read_request(&stream) {
+                Ok(request) => handler(&request),
+                Err(status) => Response::error(status, reason(status)),
+            };
+            write_response(stream, &response);
+        });
+    }
+}
+
+// Line of reader up to MAX_LINE bytes, Err(status) when it is longer.
+fn read_line<R: BufRead>(reader: &mut R, status: u16) -> Result<String, u16> {
+    let mut line = String::new();
+    reader.take(MAX_LINE as u64).read_line(&mut line).map_err(|_| 400u16)?;
+    if line.len() == MAX_LINE && !line.ends_with('\n') {
+        return Err(status);
+    }
+    Ok(line)
+}
+
+fn read_request(stream: &TcpStream) -> Result<Request, u16> {
+    let mut reader = BufReader::new(stream);
+
+    let line = read_line(&mut reader, 400)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(400),
+    };
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    loop {
+        let line = read_line(&mut reader, 431)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(431);
+        }
+        if let Some(i) = line.find(':') {
+            headers.push((line[..i].trim().to_string(), line[i + 1..].trim().to_string()));
+        }
+    }
+
+    let url = Url::parse("http://localhost").unwrap().join(&target).map_err(|_| 400u16)?;
+    let path = percent_decode(url.path());
+    let query = url.query_pairs().into_owned().collect();
+
+    let mut request = Request { method, path, query, headers, body: Vec::new() };
+    if let Some(length) = request.header("Content-Length") {
+        let length: usize = length.parse().map_err(|_| 400u16)?;
+        if length > MAX_BODY_SIZE {
+            return Err(413);
+        }
+        request.body = vec![0; length];
+        reader.read_exact(&mut request.body).map_err(|_| 400u16)?;
+    }
+
+    Ok(request)
+}
+
+fn write_response(mut stream: TcpStream, response: &Response) {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status, reason(response.status), response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    // Clients may hang up before reading the response.
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(&response.body);
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded: Vec<u8> = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = s.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        303 => "See Other",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_form_and_cookie() {
+        let request = Request {
+            method: "POST".to_string(),
+            path: "/add".to_string(),
+            query: Vec::new(),
+            headers: vec![("Cookie".to_string(), "a=1; bkm_token=abc=".to_string())],
+            body: b"url=https%3A%2F%2Fgithub.com&tags=git+hosting".to_vec(),
+        };
+
+        assert_eq!(request.form(), vec![("url".to_string(), "https://github.com".to_string()),
+                                        ("tags".to_string(), "git hosting".to_string())]);
+        assert_eq!(request.cookie("bkm_token"), Some("abc="));
+        assert_eq!(request.cookie("b"), None);
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut reader = BufReader::new("GET / HTTP/1.1\r\nHost: a\r\n".as_bytes());
+        assert_eq!(read_line(&mut reader, 431), Ok("GET / HTTP/1.1\r\n".to_string()));
+        assert_eq!(read_line(&mut reader, 431), Ok("Host: a\r\n".to_string()));
+        assert_eq!(read_line(&mut reader, 431), Ok(String::new()));
+
+        let long = format!("X-Long: {}\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(read_line(&mut BufReader::new(long.as_bytes()), 431), Err(431));
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("/tags/hosting%20service"), "/tags/hosting service");
+        assert_eq!(percent_decode("/a%2"), "/a%2");
+        assert_eq!(percent_decode("/%E3%81%82"), "/あ");
+    }
+}
@@ -0,0 +1,139 @@
+// Journal of the changes made by the last commands: every change records the
+// action reverting it, grouped by command, so that `bkm undo` can revert the
+// last command.
+
+use database::DB;
+
+// Number of commands kept in the journal.
+pub const JOURNAL_STEPS: i64 = 50;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    // Delete a bookmark added, without keeping it in the trash.
+    Purge { id: i64 },
+    // Move a restored bookmark back to the trash.
+    Trash { id: i64 },
+    Restore { trash_id: i64 },
+    Update { id: i64, title: String, url: String },
+    Description { id: i64, value: String },
+    Notes { id: i64, value: String },
+    AddTag { id: i64, tag: String },
+    RemoveTag { id: i64, tag: String },
+    Tags { id: i64, tags: Vec<String> }
+}
+
+impl Action {
+    fn apply(&self, db: &DB) -> Result<(), String> {
+        let exists = |id: i64| -> Result<(), String> {
+            match db.check_existence_bookmark(id) {
+                1 => Ok(()),
+                _ => Err(format!("Error: Bookmark {} no longer exists", id)),
+            }
+        };
+
+        match *self {
+            Action::Purge { id } => {
+                exists(id)?;
+                db.purge_bookmark(id);
+            },
+            Action::Trash { id } => {
+                exists(id)?;
+                db.delete_bookmark(id);
+            },
+            Action::Restore { trash_id } => {
+                db.restore_bookmark(trash_id)?;
+            },
+            Action::Update { id, ref title, ref url } => {
+                exists(id)?;
+                db.update_bookmark(id, title, url)?;
+            },
+            Action::Description { id, ref value } => {
+                exists(id)?;
+                db.update_description(id, value);
+            },
+            Action::Notes { id, ref value } => {
+                exists(id)?;
+                db.update_notes(id, value);
+            },
+            Action::AddTag { id, ref tag } => {
+                exists(id)?;
+                db.add_tag(id, tag);
+            },
+            Action::RemoveTag { id, ref tag } => {
+                exists(id)?;
+                db.remove_tag(id, tag);
+            },
+            Action::Tags { id, ref tags } => {
+                exists(id)?;
+                db.set_tags(id, tags);
+            },
+        }
+
+        Ok(())
+    }
+}
+
+// Revert the last command of the journal, answering its name and the actions
+// which could not be done, none when the journal is empty.
+pub fn undo(db: &DB) -> Option<(String, Vec<String>)> {
+    let (step, command, actions) = db.get_last_step()?;
+
+    let errors = db.without_journal(|| {
+        actions.iter().rev()
+            .filter_map(|action| action.apply(db).err())
+            .collect()
+    });
+    db.delete_step(step);
+
+    Some((command, errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use sync::records;
+
+    #[test]
+    fn test_undo() {
+        let db = DB::open_in_memory();
+        assert!(undo(&db).is_none());
+
+        db.begin_step("add");
+        let id = db.add_bookmark(&"GitHub".to_string(), &"https://github.com/".to_string()).unwrap();
+        db.add_tag(id, "git");
+        db.update_description(id, "Hosting");
+        let added = records(&db);
+
+        db.begin_step("update");
+        db.update_bookmark(id, "GitHub!", "https://github.com/").unwrap();
+        db.set_tags(id, &["hosting".to_string()]);
+        db.update_notes(id, "Code");
+
+        db.begin_step("delete");
+        db.delete_bookmark(id);
+        assert_eq!(db.get_record_count("bookmarks"), 0);
+
+        assert_eq!(undo(&db), Some(("delete".to_string(), Vec::new())));
+        assert_eq!(db.get_bookmark_by_id(id).unwrap().tags, vec!["hosting"]);
+        assert!(db.get_trash().is_empty());
+
+        assert_eq!(undo(&db).unwrap().0, "update");
+        assert_eq!(records(&db), added);
+
+        assert_eq!(undo(&db).unwrap().0, "add");
+        assert_eq!(db.get_record_count("bookmarks"), 0);
+        // Undoing leaves no trace in the trash nor in the journal.
+        assert!(db.get_trash().is_empty());
+        assert!(undo(&db).is_none());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let action = Action::AddTag { id: 1, tag: "git".to_string() };
+        let json = serde_json::to_string(&action).unwrap();
+        assert_eq!(json, r#"{"action":"add_tag","id":1,"tag":"git"}"#);
+        assert_eq!(serde_json::from_str::<Action>(&json).unwrap(), action);
+    }
+}
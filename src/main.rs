@@ -1,18 +1,55 @@
 #[macro_use]
 extern crate clap;
+extern crate atty;
+extern crate base64;
 extern crate dirs;
+extern crate encoding_rs;
+extern crate libc;
+extern crate openssl;
 extern crate reqwest;
 extern crate rusqlite;
 extern crate select;
+extern crate tempfile;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate termion;
+extern crate toml;
+extern crate tui;
 extern crate webbrowser;
 
 use clap::{App, AppSettings};
 use std::process;
 
 mod cmd;
+mod api;
+mod archive;
+mod backup;
 mod bookmark;
+mod config;
+mod cookies;
 mod database;
+mod editor;
+mod encryption;
+mod fetch;
+mod history;
+mod http;
+mod journal;
+mod native;
+mod normalize;
+mod oplog;
+mod pager;
+mod pool;
+mod progress;
+mod query;
+mod sync;
+mod ui;
 mod utils;
+mod web;
+
+#[cfg(test)]
+mod testserver;
 
 fn main() {
     let args = App::new("bkm")
@@ -26,9 +63,30 @@ fn main() {
         .subcommand(cmd::add::make_subcommand())
         .subcommand(cmd::delete::make_subcommand())
         .subcommand(cmd::update::make_subcommand())
+        .subcommand(cmd::edit::make_subcommand())
         .subcommand(cmd::open::make_subcommand())
         .subcommand(cmd::search::make_subcommand())
         .subcommand(cmd::import::make_subcommand())
+        .subcommand(cmd::export::make_subcommand())
+        .subcommand(cmd::saved::make_subcommand())
+        .subcommand(cmd::tui::make_subcommand())
+        .subcommand(cmd::check::make_subcommand())
+        .subcommand(cmd::dedupe::make_subcommand())
+        .subcommand(cmd::archive::make_subcommand())
+        .subcommand(cmd::index::make_subcommand())
+        .subcommand(cmd::refresh::make_subcommand())
+        .subcommand(cmd::serve::make_subcommand())
+        .subcommand(cmd::native_host::make_subcommand())
+        .subcommand(cmd::sync::make_subcommand())
+        .subcommand(cmd::encrypt::make_subcommand())
+        .subcommand(cmd::decrypt::make_subcommand())
+        .subcommand(cmd::unlock::make_subcommand())
+        .subcommand(cmd::lock::make_subcommand())
+        .subcommand(cmd::trash::make_subcommand())
+        .subcommand(cmd::undo::make_subcommand())
+        .subcommand(cmd::history::make_subcommand())
+        .subcommand(cmd::revert::make_subcommand())
+        .subcommand(cmd::backup::make_subcommand())
         .get_matches();
 
     match args.subcommand() {
@@ -36,9 +94,30 @@ fn main() {
         ("add" , Some(args)) => cmd::add::execute(args),
         ("delete", Some(args)) => cmd::delete::execute(args),
         ("update", Some(args)) => cmd::update::execute(args),
+        ("edit", Some(args)) => cmd::edit::execute(args),
         ("open", Some(args)) => cmd::open::execute(args),
         ("search", Some(args)) => cmd::search::execute(args),
         ("import", Some(args)) => cmd::import::execute(args),
+        ("export", Some(args)) => cmd::export::execute(args),
+        ("saved", Some(args)) => cmd::saved::execute(args),
+        ("tui", Some(args)) => cmd::tui::execute(args),
+        ("check", Some(args)) => cmd::check::execute(args),
+        ("dedupe", Some(args)) => cmd::dedupe::execute(args),
+        ("archive", Some(args)) => cmd::archive::execute(args),
+        ("index", Some(args)) => cmd::index::execute(args),
+        ("refresh", Some(args)) => cmd::refresh::execute(args),
+        ("serve", Some(args)) => cmd::serve::execute(args),
+        ("native-host", Some(args)) => cmd::native_host::execute(args),
+        ("sync", Some(args)) => cmd::sync::execute(args),
+        ("encrypt", Some(args)) => cmd::encrypt::execute(args),
+        ("decrypt", Some(args)) => cmd::decrypt::execute(args),
+        ("unlock", Some(args)) => cmd::unlock::execute(args),
+        ("lock", Some(args)) => cmd::lock::execute(args),
+        ("trash", Some(args)) => cmd::trash::execute(args),
+        ("undo", Some(args)) => cmd::undo::execute(args),
+        ("history", Some(args)) => cmd::history::execute(args),
+        ("revert", Some(args)) => cmd::revert::execute(args),
+        ("backup", Some(args)) => cmd::backup::execute(args),
         _ => process::exit(1),
     }
 }
@@ -0,0 +1,222 @@
+// WebExtensions native messaging: every message is UTF-8 JSON preceded by
+// its length as a 32-bit integer in native byte order.
+
+use serde_json::{self, Value};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use api::{create_bookmark, BookmarkInput};
+use bookmark::Bookmark;
+use database::DB;
+use normalize::{host, normalize_url};
+use query::{ListOptions, Query, SortKey};
+
+// Browsers refuse messages from the host larger than 1 MB.
+const MAX_OUTGOING_SIZE: usize = 1024 * 1024;
+const MAX_INCOMING_SIZE: usize = 64 * 1024 * 1024;
+const DEFAULT_LIMIT: i64 = 20;
+
+// Next message of input, None when the browser closed it.
+pub fn read_message<R: Read>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = [0u8; 4];
+    match input.read_exact(&mut length) {
+        Ok(()) => (),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let length = u32::from_ne_bytes(length) as usize;
+    if length > MAX_INCOMING_SIZE {
+        return Err(io::Error::other("message too large"));
+    }
+
+    let mut message = vec![0; length];
+    input.read_exact(&mut message)?;
+    serde_json::from_slice(&message).map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let mut bytes = serde_json::to_vec(message).unwrap();
+    if bytes.len() > MAX_OUTGOING_SIZE {
+        bytes = serde_json::to_vec(&error("Response too large")).unwrap();
+    }
+
+    output.write_all(&(bytes.len() as u32).to_ne_bytes())?;
+    output.write_all(&bytes)?;
+    output.flush()
+}
+
+// Answer messages of input on output until the browser closes input.
+pub fn run<R: Read, W: Write>(db: &DB, input: &mut R, output: &mut W) -> io::Result<()> {
+    loop {
+        match read_message(input) {
+            Ok(Some(message)) => {
+                let answer = db.request(|| handle(db, &message)).unwrap_or_else(|e| error(&e));
+                write_message(output, &answer)?
+            },
+            Ok(None) => return Ok(()),
+            // Invalid JSON leaves the stream in sync, the length was read.
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+                write_message(output, &error(&format!("Invalid message: {}", e)))?
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn error(message: &str) -> Value {
+    json!({ "ok": false, "error": message.trim_start_matches("Error: ") })
+}
+
+fn to_json(bookmark: &Bookmark) -> Value {
+    serde_json::to_value(bookmark).unwrap()
+}
+
+// Answer of a message {"type": "add" | "search" | "lookup" | "suggest", ...}.
+pub fn handle(db: &DB, message: &Value) -> Value {
+    let text = |name: &str| message[name].as_str().map(|s| s.to_string());
+
+    match message["type"].as_str() {
+        // {"type": "add", "url", "title", "tags", "description", "notes"}
+        Some("add") => {
+            db.begin_step("native-host add");
+            let input: BookmarkInput = match serde_json::from_value(message.clone()) {
+                Ok(input) => input,
+                Err(e) => return error(&format!("Invalid bookmark: {}", e)),
+            };
+            let url = input.url.clone().unwrap_or_default();
+
+            match create_bookmark(db, input) {
+                Ok(id) => json!({ "ok": true, "bookmark": to_json(&db.get_bookmark_by_id(id).unwrap()) }),
+                Err(e) => {
+                    let mut answer = error(e);
+                    // Let the extension offer the bookmark saved already.
+                    if let Some(id) = db.get_id_by_url(&normalize_url(&url)) {
+                        answer["bookmark"] = to_json(&db.get_bookmark_by_id(id).unwrap());
+                    }
+                    answer
+                },
+            }
+        },
+        // {"type": "search", "query", "limit"}
+        Some("search") => {
+            let query = Query::parse(&text("query").unwrap_or_default());
+            let limit = message["limit"].as_i64().unwrap_or(DEFAULT_LIMIT);
+            let options = ListOptions::new(SortKey::Id, false, Some(limit), None);
+            let bookmarks: Vec<Value> = db.search_query(&query, &options).iter().map(to_json).collect();
+
+            json!({ "ok": true, "bookmarks": bookmarks })
+        },
+        // {"type": "lookup", "url"}, the bookmark of the page or null.
+        Some("lookup") => {
+            let url = match text("url") {
+                Some(url) => normalize_url(&url),
+                None => return error("Missing url"),
+            };
+            let bookmark = db.get_id_by_url(&url)
+                .and_then(|id| db.get_bookmark_by_id(id).ok())
+                .map(|b| to_json(&b));
+
+            json!({ "ok": true, "bookmark": bookmark })
+        },
+        // {"type": "suggest", "url", "title"}
+        Some("suggest") => {
+            let tags = suggest_tags(db, &text("url").unwrap_or_default(), &text("title").unwrap_or_default());
+            json!({ "ok": true, "tags": tags })
+        },
+        Some(other) => error(&format!("Unknown message type \"{}\"", other)),
+        None => error("Missing message type"),
+    }
+}
+
+// Tags for a page, most likely first: tags of bookmarks of the same site,
+// then saved tags appearing in its title.
+pub fn suggest_tags(db: &DB, url: &str, title: &str) -> Vec<String> {
+    let site = host(url);
+    let words: Vec<String> = title.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_string())
+        .collect();
+    let mut scores: HashMap<String, usize> = HashMap::new();
+
+    for bookmark in db.get_all_bookmark(&ListOptions::default()) {
+        if site.is_some() && host(&bookmark.url) == site {
+            for tag in bookmark.tags {
+                *scores.entry(tag).or_insert(0) += 2;
+            }
+        }
+    }
+    for (tag, _) in db.get_tag_counts() {
+        if words.contains(&tag.to_lowercase()) {
+            *scores.entry(tag).or_insert(0) += 1;
+        }
+    }
+
+    let mut tags: Vec<(String, usize)> = scores.into_iter().collect();
+    tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn frame(message: &str) -> Vec<u8> {
+        let mut bytes = (message.len() as u32).to_ne_bytes().to_vec();
+        bytes.extend_from_slice(message.as_bytes());
+        bytes
+    }
+
+    // Answers of the host to messages, sent as the browser would.
+    fn exchange(db: &DB, messages: &[&str]) -> Vec<Value> {
+        let input: Vec<u8> = messages.iter().flat_map(|m| frame(m)).collect();
+        let mut output: Vec<u8> = Vec::new();
+        run(db, &mut Cursor::new(input), &mut output).unwrap();
+
+        let mut output = Cursor::new(output);
+        let mut answers = Vec::new();
+        while let Some(answer) = read_message(&mut output).unwrap() {
+            answers.push(answer);
+        }
+        answers
+    }
+
+    #[test]
+    fn test_messages() {
+        let db = DB::open_in_memory();
+
+        let answers = exchange(&db, &[
+            r#"{"type": "add", "url": "https://github.com/rust-lang/rust", "title": "Rust", "tags": ["rust", "git"]}"#,
+            r#"{"type": "add", "url": "http://github.com/rust-lang/rust/"}"#,
+            r#"{"type": "lookup", "url": "https://github.com/rust-lang/rust?utm_source=x"}"#,
+            r#"{"type": "lookup", "url": "https://example.com"}"#,
+            r#"{"type": "search", "query": "tag:git"}"#,
+            r#"{"type": "suggest", "url": "https://github.com/serde-rs/serde", "title": "Serde for Rust"}"#,
+            r#"{"type": "nothing"}"#,
+            "not json",
+        ]);
+
+        assert_eq!(answers.len(), 8);
+        assert_eq!(answers[0]["ok"], true);
+        assert_eq!(answers[0]["bookmark"]["id"], 1);
+        assert_eq!(answers[1]["error"], "URL already exists");
+        assert_eq!(answers[1]["bookmark"]["id"], 1);
+        assert_eq!(answers[2]["bookmark"]["title"], "Rust");
+        assert_eq!(answers[3]["bookmark"], Value::Null);
+        assert_eq!(answers[4]["bookmarks"][0]["id"], 1);
+        assert_eq!(answers[5]["tags"], json!(["rust", "git"]));
+        assert_eq!(answers[6]["ok"], false);
+        assert!(answers[7]["error"].as_str().unwrap().starts_with("Invalid message"));
+    }
+
+    #[test]
+    fn test_read_message() {
+        assert!(read_message(&mut Cursor::new(Vec::new())).unwrap().is_none());
+        assert!(read_message(&mut Cursor::new(vec![5, 0])).unwrap().is_none());
+        assert!(read_message(&mut Cursor::new(frame("{\"a\"")[..6].to_vec())).is_err());
+        assert_eq!(read_message(&mut Cursor::new(frame("[1]"))).unwrap(), Some(json!([1])));
+    }
+}
@@ -0,0 +1,190 @@
+use reqwest::Url;
+
+// Query parameters only telling where a visitor came from.
+const TRACKING_PARAMS: &[&str] = &[
+    "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "yclid", "igshid",
+    "mc_cid", "mc_eid", "_hsenc", "_hsmi", "mkt_tok",
+];
+
+fn is_tracking_param(name: &str) -> bool {
+    name.starts_with("utm_") || TRACKING_PARAMS.contains(&name)
+}
+
+// URL as it is saved: lowercase scheme and host, no default port, no
+// tracking parameters and no empty fragment. Unparsable URLs are kept as is.
+pub fn normalize_url(url: &str) -> String {
+    let mut url = match Url::parse(url.trim()) {
+        Ok(url) => url,
+        Err(_) => return url.trim().to_string(),
+    };
+
+    let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    let kept: Vec<&(String, String)> = pairs.iter().filter(|(k, _)| !is_tracking_param(k)).collect();
+    // Leave the query untouched when nothing is stripped, re-encoding may change it.
+    if kept.is_empty() {
+        url.set_query(None);
+    } else if kept.len() < pairs.len() {
+        url.query_pairs_mut().clear().extend_pairs(kept);
+    }
+
+    if url.fragment() == Some("") {
+        url.set_fragment(None);
+    }
+
+    url.to_string()
+}
+
+// URL saved for a page at url declaring canonical as its canonical URL. A
+// canonical URL on another host is ignored, pages may claim any URL.
+pub fn canonical_url(url: &str, canonical: Option<&str>) -> String {
+    match canonical {
+        Some(canonical) if host(canonical).is_some() && host(canonical) == host(url) => {
+            normalize_url(canonical)
+        },
+        _ => normalize_url(url),
+    }
+}
+
+// Lowercase host of url, None for URLs without any (e.g. mailto:).
+pub fn host(url: &str) -> Option<String> {
+    Url::parse(url.trim()).ok()
+        .and_then(|u| u.host_str().map(|h| h.to_lowercase()))
+}
+
+// Whether host matches pattern: "*" matches every host, "*.example.com" and
+// ".example.com" match example.com and its subdomains, other patterns only
+// the host itself.
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+    let host = host.to_lowercase();
+
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.strip_prefix("*.").or_else(|| pattern.strip_prefix('.')) {
+        Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+        None => host == pattern,
+    }
+}
+
+// Title of a bookmark whose page could not be fetched, its host and path
+// (e.g. github.com/rust-lang/rust).
+pub fn placeholder_title(url: &str) -> String {
+    match Url::parse(url.trim()) {
+        Ok(ref u) if u.host_str().is_some() => {
+            let path = u.path().trim_matches('/');
+            if path.is_empty() {
+                u.host_str().unwrap().to_string()
+            } else {
+                format!("{}/{}", u.host_str().unwrap(), path)
+            }
+        },
+        _ => url.trim().to_string(),
+    }
+}
+
+// Key shared by URLs leading to the same page: http and https, trailing
+// slashes, order of query parameters and in-page anchors do not matter.
+// Fragments used as routes by single page apps (#/ and #!) are kept.
+pub fn url_key(url: &str) -> String {
+    let url = match Url::parse(&normalize_url(url)) {
+        Ok(ref url) if url.host_str().is_some() => url.clone(),
+        _ => return url.trim().to_lowercase(),
+    };
+
+    let mut key = match url.scheme() {
+        "http" | "https" => String::new(),
+        scheme => format!("{}://", scheme),
+    };
+    key.push_str(url.host_str().unwrap());
+    if let Some(port) = url.port() {
+        key.push_str(&format!(":{}", port));
+    }
+    key.push_str(url.path().trim_end_matches('/'));
+
+    let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    if !pairs.is_empty() {
+        pairs.sort();
+        let query: Vec<String> = pairs.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        key.push('?');
+        key.push_str(&query.join("&"));
+    }
+
+    if let Some(fragment) = url.fragment() {
+        if fragment.starts_with('/') || fragment.starts_with('!') {
+            key.push('#');
+            key.push_str(fragment);
+        }
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(normalize_url("HTTPS://GitHub.com:443"), "https://github.com/");
+        assert_eq!(normalize_url("http://example.com:8080/A?b=1"), "http://example.com:8080/A?b=1");
+        assert_eq!(normalize_url("https://example.com/?utm_source=x&utm_medium=y"),
+                   "https://example.com/");
+        assert_eq!(normalize_url("https://example.com/?q=a%20b&fbclid=1"),
+                   "https://example.com/?q=a+b");
+        assert_eq!(normalize_url("https://example.com/?q=a%20b"), "https://example.com/?q=a%20b");
+        assert_eq!(normalize_url("https://example.com/page#"), "https://example.com/page");
+        assert_eq!(normalize_url("https://example.com/page#top"), "https://example.com/page#top");
+        assert_eq!(normalize_url(" not a url "), "not a url");
+    }
+
+    #[test]
+    fn test_canonical_url() {
+        let url = "https://example.com/a?utm_source=x";
+        assert_eq!(canonical_url(url, None), "https://example.com/a");
+        assert_eq!(canonical_url(url, Some("https://Example.com/b?fbclid=1")), "https://example.com/b");
+        assert_eq!(canonical_url(url, Some("https://other.com/b")), "https://example.com/a");
+        assert_eq!(canonical_url(url, Some("not a url")), "https://example.com/a");
+    }
+
+    #[test]
+    fn test_host() {
+        assert_eq!(host("https://GitHub.com:443/a"), Some("github.com".to_string()));
+        assert_eq!(host("mailto:someone@example.com"), None);
+        assert_eq!(host("not a url"), None);
+    }
+
+    #[test]
+    fn test_host_matches() {
+        assert!(host_matches("*", "github.com"));
+        assert!(host_matches("*.example.com", "example.com"));
+        assert!(host_matches("*.example.com", "wiki.Example.com"));
+        assert!(host_matches(".example.com", "a.b.example.com"));
+        assert!(!host_matches("*.example.com", "notexample.com"));
+        assert!(host_matches("Example.com", "example.com"));
+        assert!(!host_matches("example.com", "wiki.example.com"));
+    }
+
+    #[test]
+    fn test_placeholder_title() {
+        assert_eq!(placeholder_title("https://github.com/"), "github.com");
+        assert_eq!(placeholder_title("https://github.com/rust-lang/rust/?tab=1"), "github.com/rust-lang/rust");
+        assert_eq!(placeholder_title("mailto:someone@example.com"), "mailto:someone@example.com");
+    }
+
+    #[test]
+    fn test_url_key() {
+        let key = url_key("https://github.com");
+        assert_eq!(key, "github.com");
+        assert_eq!(url_key("https://github.com/"), key);
+        assert_eq!(url_key("http://GitHub.com/?utm_source=x"), key);
+        assert_eq!(url_key("https://github.com/#readme"), key);
+
+        assert_eq!(url_key("https://example.com/a/?b=2&a=1"), url_key("https://example.com/a?a=1&b=2"));
+        assert_ne!(url_key("https://example.com/a"), url_key("https://example.com/b"));
+        assert_ne!(url_key("https://example.com/#/a"), url_key("https://example.com/#/b"));
+        assert_ne!(url_key("https://example.com:8080"), url_key("https://example.com"));
+        assert_eq!(url_key("ftp://example.com/file"), "ftp://example.com/file");
+        assert_eq!(url_key("mailto:Someone@example.com"), "mailto:someone@example.com");
+    }
+}
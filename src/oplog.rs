@@ -0,0 +1,322 @@
+// Replication through the operation log: every change of a bookmark field is
+// recorded with the device making it and a hybrid logical clock timestamp.
+// The last operation on a field wins, ties going to the greater device id,
+// so devices holding the same operations hold the same bookmarks whatever
+// the order they received them in.
+
+use reqwest::{Client, Url};
+use serde_json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use api::remove_bookmark;
+use database::DB;
+use sync::{update_bookmark, Record, Report};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Op {
+    pub device: String,
+    pub ts: i64,
+    // Key of the bookmark, the same on every device.
+    pub key: String,
+    // "deleted", "url", "title", "description", "notes" or "tag:<name>".
+    pub field: String,
+    pub value: String
+}
+
+impl Op {
+    fn wins_over(&self, other: &Op) -> bool {
+        (self.ts, &self.device) > (other.ts, &other.device)
+    }
+}
+
+// Bookmark resulting from the operations on its key, None when deleted.
+pub fn materialize(ops: &[Op]) -> Option<Record> {
+    let mut last: BTreeMap<&str, &Op> = BTreeMap::new();
+    for op in ops {
+        match last.get(op.field.as_str()) {
+            Some(other) if !op.wins_over(other) => (),
+            _ => {
+                last.insert(&op.field, op);
+            },
+        }
+    }
+
+    let value = |field: &str| last.get(field).map(|op| op.value.clone());
+    if value("deleted").as_deref() != Some("0") {
+        return None;
+    }
+
+    Some(Record {
+        url: value("url")?,
+        title: value("title").unwrap_or_default(),
+        tags: last.iter()
+            .filter(|&(_, op)| op.value == "1")
+            .filter_map(|(field, _)| field.strip_prefix("tag:"))
+            .map(|tag| tag.to_string())
+            .collect(),
+        description: value("description").unwrap_or_default(),
+        notes: value("notes").unwrap_or_default(),
+    })
+}
+
+// Add the operations of other devices to the log and apply those not seen
+// yet, returning how many were new. Bookmarks skipped by earlier merges for
+// conflicting with others here are tried again.
+pub fn merge_ops(db: &DB, ops: &[Op]) -> (usize, Report) {
+    let mut report = Report::default();
+
+    // Operations stay unseen unless applied.
+    let merged = db.transaction(|| {
+        let mut keys: BTreeSet<String> = db.get_sync_conflicts().into_iter().collect();
+        let mut new = 0;
+        for op in ops {
+            if db.insert_op(op) {
+                keys.insert(op.key.clone());
+                db.observe(op.ts);
+                new += 1;
+            }
+        }
+
+        let conflicts: Vec<String> = db.without_log(|| {
+            keys.into_iter().filter(|key| !apply_key(db, key, &mut report)).collect()
+        });
+        db.set_sync_conflicts(&conflicts);
+        Ok::<_, ()>(new)
+    });
+
+    (merged.unwrap_or(0), report)
+}
+
+// Bring the bookmark of key to the state its operations lead to, false when
+// it conflicts with another bookmark here.
+fn apply_key(db: &DB, key: &str, report: &mut Report) -> bool {
+    let record = materialize(&db.get_ops_by_key(key));
+
+    match (record, db.get_id_by_sync_key(key)) {
+        (None, Some(id)) => {
+            let _ = remove_bookmark(db, id);
+            report.deleted += 1;
+        },
+        (Some(record), None) => match db.insert_bookmark(&record.to_bookmark()) {
+            Ok(id) => {
+                db.set_sync_key(id, key);
+                report.added += 1;
+            },
+            Err(_) => {
+                report.conflicts.push(format!("{}: saved as another bookmark here, skipped", record.url));
+                return false;
+            },
+        },
+        (Some(record), Some(id)) => {
+            let current = Record::from_bookmark(&db.get_bookmark_by_id(id).unwrap());
+            if current != record {
+                match update_bookmark(db, id, &record) {
+                    Ok(()) => report.updated += 1,
+                    Err(_) => {
+                        report.conflicts.push(format!("{}: saved as another bookmark here, skipped", record.url));
+                        return false;
+                    },
+                }
+            }
+        },
+        (None, None) => (),
+    }
+    true
+}
+
+fn is_url(target: &str) -> bool {
+    target.starts_with("http://") || target.starts_with("https://")
+}
+
+// Endpoint of the operations of a `bkm serve` at target.
+fn ops_url(target: &str) -> Result<Url, String> {
+    Url::parse(&format!("{}/", target.trim_end_matches('/')))
+        .and_then(|url| url.join("sync/ops"))
+        .map_err(|e| format!("Error: Invalid URL \"{}\": {}", target, e))
+}
+
+// Send the log to target: a directory shared by the devices, each writing
+// its log to <device>.jsonl, or the URL of a `bkm serve` merging it.
+pub fn push(db: &DB, target: &str, token: Option<&str>) -> Result<usize, String> {
+    db.log_unlogged();
+    let ops = db.get_ops();
+
+    if is_url(target) {
+        let token = token.ok_or("Error: Missing token of the server, pass --token")?;
+        let res = Client::new().post(ops_url(target)?).bearer_auth(token).json(&ops).send()
+            .map_err(|e| format!("Error: Failed to push to {}: {}", target, e))?;
+        if !res.status().is_success() {
+            return Err(format!("Error: Failed to push to {}: {}", target, res.status()));
+        }
+    } else {
+        let dir = Path::new(target);
+        let lines: Vec<String> = ops.iter().map(|op| serde_json::to_string(op).unwrap()).collect();
+        let path = dir.join(format!("{}.jsonl", db.device_id()));
+        let tmp = dir.join(format!(".{}.jsonl", db.device_id()));
+
+        // Written aside and renamed, other devices never read half a log.
+        fs::create_dir_all(dir)
+            .and_then(|_| fs::write(&tmp, lines.join("\n") + "\n"))
+            .and_then(|_| fs::rename(&tmp, &path))
+            .map_err(|e| format!("Error: Failed to write {}: {}", path.display(), e))?;
+    }
+
+    Ok(ops.len())
+}
+
+// Merge the operations found at target into the library.
+pub fn pull(db: &DB, target: &str, token: Option<&str>) -> Result<(usize, Report), String> {
+    let mut ops: Vec<Op> = Vec::new();
+
+    if is_url(target) {
+        let token = token.ok_or("Error: Missing token of the server, pass --token")?;
+        let mut res = Client::new().get(ops_url(target)?).bearer_auth(token).send()
+            .map_err(|e| format!("Error: Failed to pull from {}: {}", target, e))?;
+        if !res.status().is_success() {
+            return Err(format!("Error: Failed to pull from {}: {}", target, res.status()));
+        }
+        ops = res.json().map_err(|e| format!("Error: Invalid operations from {}: {}", target, e))?;
+    } else {
+        let entries = fs::read_dir(target)
+            .map_err(|e| format!("Error: Failed to read {}: {}", target, e))?;
+        for entry in entries {
+            let path = entry.map_err(|e| format!("Error: Failed to read {}: {}", target, e))?.path();
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            if !name.ends_with(".jsonl") || name.starts_with('.') {
+                continue;
+            }
+
+            let content = fs::read_to_string(&path)
+                .map_err(|e| format!("Error: Failed to read {}: {}", path.display(), e))?;
+            for line in content.lines().filter(|l| !l.trim().is_empty()) {
+                let op = serde_json::from_str(line)
+                    .map_err(|e| format!("Error: Invalid operation in {}: {}", path.display(), e))?;
+                ops.push(op);
+            }
+        }
+    }
+
+    let result = merge_ops(db, &ops);
+    // Bookmarks of this device saved before the log now have operations too.
+    db.log_unlogged();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sync::records;
+    use tempfile;
+
+    fn op(device: &str, ts: i64, field: &str, value: &str) -> Op {
+        Op {
+            device: device.to_string(),
+            ts,
+            key: "github.com".to_string(),
+            field: field.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_materialize() {
+        let mut ops = vec![
+            op("a", 1, "deleted", "0"),
+            op("a", 2, "url", "https://github.com/"),
+            op("a", 3, "title", "GitHub"),
+            op("b", 3, "title", "GitHub b"),
+            op("a", 4, "tag:git", "1"),
+            op("b", 5, "tag:git", "0"),
+            op("b", 6, "tag:hosting", "1"),
+        ];
+        let record = materialize(&ops).unwrap();
+        assert_eq!(record.title, "GitHub b");
+        assert_eq!(record.tags, vec!["hosting"]);
+
+        // Order of arrival does not matter.
+        ops.reverse();
+        assert_eq!(materialize(&ops), Some(record));
+
+        ops.push(op("a", 7, "deleted", "1"));
+        assert_eq!(materialize(&ops), None);
+    }
+
+    #[test]
+    fn test_log() {
+        let db = DB::open_in_memory();
+        let id = db.add_bookmark(&"GitHub".to_string(), &"https://github.com/".to_string()).unwrap();
+        db.add_tag(id, "git");
+        db.update_bookmark(id, "GitHub", "https://github.com/").unwrap();
+        db.update_notes(id, "Code");
+        db.set_tags(id, &["git".to_string(), "hosting".to_string()]);
+
+        let fields: Vec<String> = db.get_ops().into_iter().map(|op| op.field).collect();
+        assert_eq!(fields, vec!["deleted", "url", "title", "description", "notes",
+                                "tag:git", "notes", "tag:hosting"]);
+        let record = materialize(&db.get_ops()).unwrap();
+        assert_eq!(record, Record::from_bookmark(&db.get_bookmark_by_id(id).unwrap()));
+
+        db.delete_bookmark(id);
+        assert_eq!(materialize(&db.get_ops()), None);
+    }
+
+    #[test]
+    fn test_push_pull() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().to_str().unwrap();
+        let (a, b) = (DB::open_in_memory(), DB::open_in_memory());
+
+        let id = a.add_bookmark(&"GitHub".to_string(), &"https://github.com/".to_string()).unwrap();
+        a.add_tag(id, "git");
+        push(&a, target, None).unwrap();
+        let (new, report) = pull(&b, target, None).unwrap();
+        assert!(new > 0);
+        assert_eq!(report.added, 1);
+
+        // Concurrent changes on both devices, then exchanges in both orders.
+        a.update_bookmark(id, "GitHub a", "https://github.com/").unwrap();
+        a.add_tag(id, "hosting");
+        let id_b = b.get_id_by_url("https://github.com/").unwrap();
+        b.update_notes(id_b, "Code");
+        b.remove_tag(id_b, "git");
+        b.add_bookmark(&"Rust".to_string(), &"https://www.rust-lang.org/".to_string()).unwrap();
+
+        push(&a, target, None).unwrap();
+        push(&b, target, None).unwrap();
+        pull(&a, target, None).unwrap();
+        pull(&b, target, None).unwrap();
+
+        assert_eq!(records(&a), records(&b));
+        let github = a.get_bookmark_by_id(id).unwrap();
+        assert_eq!(github.title, "GitHub a");
+        assert_eq!(github.notes, "Code");
+        assert_eq!(github.tags, vec!["hosting"]);
+
+        // Pulling again changes nothing, and deletions replicate.
+        assert_eq!(pull(&a, target, None).unwrap().0, 0);
+        b.delete_bookmark(id_b);
+        push(&b, target, None).unwrap();
+        assert_eq!(pull(&a, target, None).unwrap().1.deleted, 1);
+        assert_eq!(records(&a), records(&b));
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let (a, b) = (DB::open_in_memory(), DB::open_in_memory());
+        let id = a.add_bookmark(&"GitHub".to_string(), &"https://github.com/".to_string()).unwrap();
+        a.update_bookmark(id, "GitLab", "https://gitlab.com/").unwrap();
+        let id_b = b.add_bookmark(&"GitLab".to_string(), &"https://gitlab.com/".to_string()).unwrap();
+
+        let (new, report) = merge_ops(&b, &a.get_ops());
+        assert!(new > 0);
+        assert_eq!((report.added, report.conflicts.len()), (0, 1));
+
+        // Tried again once the other bookmark is gone, with no new operations.
+        b.delete_bookmark(id_b);
+        let (new, report) = merge_ops(&b, &a.get_ops());
+        assert_eq!((new, report.added, report.conflicts.len()), (0, 1, 0));
+        assert_eq!(merge_ops(&b, &a.get_ops()).1.added, 0);
+    }
+}
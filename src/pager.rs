@@ -0,0 +1,37 @@
+use atty;
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const DEFAULT_PAGER: &str = "less -FRX";
+
+// Print output through $PAGER when stdout is a terminal.
+pub fn page(output: &str, enabled: bool) {
+    if !enabled || !atty::is(atty::Stream::Stdout) {
+        print!("{}", output);
+        return;
+    }
+
+    let pager = env::var("PAGER").unwrap_or_else(|_| DEFAULT_PAGER.to_string());
+    if pager.trim().is_empty() {
+        print!("{}", output);
+        return;
+    }
+
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(&pager)
+        .stdin(Stdio::piped())
+        .spawn();
+
+    match child {
+        Ok(mut child) => {
+            if let Some(mut stdin) = child.stdin.take() {
+                // The pager closes stdin when quit early, so ignore broken pipes.
+                let _ = stdin.write_all(output.as_bytes());
+            }
+            child.wait().expect("Failed to wait for pager");
+        },
+        Err(_) => print!("{}", output),
+    }
+}
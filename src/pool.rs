@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Apply f to every item on at most workers threads, results in input order.
+pub fn map<T, R, F>(items: Vec<T>, workers: usize, f: F) -> Vec<R>
+    where T: Send, R: Send, F: Fn(T) -> R + Sync
+{
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results: Mutex<Vec<(usize, R)>> = Mutex::new(Vec::new());
+
+    thread::scope(|s| {
+        for _ in 0..workers.max(1) {
+            s.spawn(|| loop {
+                // Take the next item without holding the lock while working on it.
+                let next = queue.lock().unwrap().next();
+                let (i, item) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+
+                let result = f(item);
+                results.lock().unwrap().push((i, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+// Spaces requests to the same host at least delay apart, so workers of
+// map do not hammer a site holding many of the bookmarks.
+pub struct HostLimiter {
+    delay: Duration,
+    next: Mutex<HashMap<String, Instant>>
+}
+
+impl HostLimiter {
+    pub fn new(delay: Duration) -> HostLimiter {
+        HostLimiter { delay, next: Mutex::new(HashMap::new()) }
+    }
+
+    // Block until a request to host may be sent.
+    pub fn wait(&self, host: &str) {
+        let at = {
+            // Reserve the slot under the lock, sleep without it.
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            let at = match next.get(host) {
+                Some(&at) if at > now => at,
+                _ => now,
+            };
+            next.insert(host.to_string(), at + self.delay);
+            at
+        };
+
+        let now = Instant::now();
+        if at > now {
+            thread::sleep(at - now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_map() {
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+
+        let results = map((0..20).collect(), 4, |i: u64| {
+            let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(n, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20 - i));
+            running.fetch_sub(1, Ordering::SeqCst);
+            i * 2
+        });
+
+        assert_eq!(results, (0..20).map(|i| i * 2).collect::<Vec<u64>>());
+        assert!(max_running.load(Ordering::SeqCst) <= 4);
+        assert!(map(Vec::new(), 0, |i: u64| i).is_empty());
+    }
+
+    #[test]
+    fn test_host_limiter() {
+        let limiter = HostLimiter::new(Duration::from_millis(50));
+        let start = Instant::now();
+
+        let hosts = vec!["a", "a", "a", "b"];
+        let times = map(hosts, 4, |host| {
+            limiter.wait(host);
+            start.elapsed()
+        });
+
+        let mut a: Vec<Duration> = times[..3].to_vec();
+        a.sort();
+        assert!(a[1] - a[0] >= Duration::from_millis(45));
+        assert!(a[2] - a[1] >= Duration::from_millis(45));
+        assert!(times[3] < Duration::from_millis(45));
+    }
+}
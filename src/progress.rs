@@ -0,0 +1,63 @@
+use atty;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+const WIDTH: usize = 30;
+
+// Progress bar drawn on stderr, only when it is a terminal so it never
+// ends up in redirected output. Safe to advance from worker threads.
+pub struct Progress {
+    total: usize,
+    done: Mutex<usize>,
+    enabled: bool
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Progress {
+        let progress = Progress {
+            total,
+            done: Mutex::new(0),
+            enabled: atty::is(atty::Stream::Stderr),
+        };
+        progress.draw(0);
+        progress
+    }
+
+    pub fn inc(&self) {
+        let mut done = self.done.lock().unwrap();
+        *done += 1;
+        self.draw(*done);
+    }
+
+    // Clear the bar so following output starts on an empty line.
+    pub fn finish(&self) {
+        if self.enabled {
+            eprint!("\r{}\r", " ".repeat(WIDTH + 24));
+        }
+    }
+
+    fn draw(&self, done: usize) {
+        if !self.enabled {
+            return;
+        }
+        eprint!("\r{}", bar(done, self.total));
+        let _ = io::stderr().flush();
+    }
+}
+
+fn bar(done: usize, total: usize) -> String {
+    let filled = (done * WIDTH).checked_div(total).unwrap_or(WIDTH);
+    format!("[{}{}] {}/{}", "#".repeat(filled), " ".repeat(WIDTH - filled), done, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bar() {
+        assert_eq!(bar(0, 3), format!("[{}] 0/3", " ".repeat(WIDTH)));
+        assert_eq!(bar(3, 3), format!("[{}] 3/3", "#".repeat(WIDTH)));
+        assert_eq!(bar(1, 2), format!("[{}{}] 1/2", "#".repeat(WIDTH / 2), " ".repeat(WIDTH / 2)));
+    }
+}
@@ -0,0 +1,162 @@
+use std::str::FromStr;
+
+pub struct Query {
+    pub keywords: Vec<String>,
+    pub tags: Vec<String>,
+    // Keywords also match the indexed text of pages.
+    pub content: bool
+}
+
+impl Query {
+    pub fn new(keywords: Vec<String>, tags: Vec<String>) -> Self {
+        Query { keywords, tags, content: false }
+    }
+
+    // Words prefixed with "tag:" filter by tag, the others match title or URL.
+    // Double quotes keep spaces inside a word, e.g. tag:"hosting service".
+    pub fn parse(query: &str) -> Self {
+        let mut keywords: Vec<String> = Vec::new();
+        let mut tags: Vec<String> = Vec::new();
+
+        for word in split_words(query) {
+            if let Some(tag) = word.strip_prefix("tag:") {
+                tags.push(tag.to_string());
+            } else {
+                keywords.push(word);
+            }
+        }
+
+        Query::new(keywords, tags)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keywords.is_empty() && self.tags.is_empty()
+    }
+}
+
+pub enum SortKey {
+    Id,
+    Title,
+    Url,
+    Created,
+    Visited,
+    Visits
+}
+
+impl SortKey {
+    pub const NAMES: &'static [&'static str] = &[
+        "id", "title", "url", "created", "visited", "visits"
+    ];
+
+    pub fn column(&self) -> &'static str {
+        match *self {
+            SortKey::Id => "id",
+            SortKey::Title => "title COLLATE NOCASE",
+            SortKey::Url => "url",
+            SortKey::Created => "created",
+            SortKey::Visited => "visited",
+            SortKey::Visits => "visits",
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(SortKey::Id),
+            "title" => Ok(SortKey::Title),
+            "url" => Ok(SortKey::Url),
+            "created" => Ok(SortKey::Created),
+            "visited" => Ok(SortKey::Visited),
+            "visits" => Ok(SortKey::Visits),
+            _ => Err(format!("Error: Unknown sort key \"{}\"", s)),
+        }
+    }
+}
+
+pub struct ListOptions {
+    pub sort: SortKey,
+    pub reverse: bool,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>
+}
+
+impl ListOptions {
+    pub fn new(sort: SortKey, reverse: bool, limit: Option<i64>, offset: Option<i64>) -> Self {
+        ListOptions { sort, reverse, limit, offset }
+    }
+
+    // ORDER BY and LIMIT clauses appended to a bookmarks query.
+    pub fn to_sql(&self) -> String {
+        let order = if self.reverse { "DESC" } else { "ASC" };
+        let mut sql = format!(" ORDER BY {} {}, id {}", self.sort.column(), order, order);
+
+        if self.limit.is_some() || self.offset.is_some() {
+            sql.push_str(&format!(
+                " LIMIT {} OFFSET {}", self.limit.unwrap_or(-1), self.offset.unwrap_or(0)
+            ));
+        }
+
+        sql
+    }
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        ListOptions::new(SortKey::Id, false, None, None)
+    }
+}
+
+fn split_words(query: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(word.clone());
+                    word.clear();
+                }
+            },
+            c => word.push(c),
+        }
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let query = Query::parse("rust  articles tag:async tag:\"hosting service\"");
+
+        assert_eq!(query.keywords, vec!["rust".to_string(), "articles".to_string()]);
+        assert_eq!(query.tags, vec!["async".to_string(), "hosting service".to_string()]);
+    }
+
+    #[test]
+    fn test_list_options_to_sql() {
+        assert_eq!(ListOptions::default().to_sql(), " ORDER BY id ASC, id ASC");
+
+        let options = ListOptions::new("visits".parse().unwrap(), true, Some(10), None);
+        assert_eq!(options.to_sql(), " ORDER BY visits DESC, id DESC LIMIT 10 OFFSET 0");
+
+        let options = ListOptions::new(SortKey::Title, false, None, Some(5));
+        assert_eq!(options.to_sql(),
+                   " ORDER BY title COLLATE NOCASE ASC, id ASC LIMIT -1 OFFSET 5");
+
+        assert!("size".parse::<SortKey>().is_err());
+    }
+}
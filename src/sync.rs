@@ -0,0 +1,498 @@
+// Sync of the library through a git repository: bookmarks are written one
+// per line to a text file, and the files of two machines are merged against
+// the one of their last common sync.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use api::remove_bookmark;
+use bookmark::Bookmark;
+use database::DB;
+use normalize::url_key;
+use query::ListOptions;
+
+pub const FILE_NAME: &str = "bookmarks.txt";
+const HEADER: &str = "# bkm bookmarks: url, title, tags, description and notes separated by tabs";
+
+// The synced fields of a bookmark, visits and checks stay on each machine.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Record {
+    pub url: String,
+    pub title: String,
+    // Sorted, so that records compare equal whatever the order of tagging.
+    pub tags: Vec<String>,
+    pub description: String,
+    pub notes: String
+}
+
+impl Record {
+    pub fn from_bookmark(bookmark: &Bookmark) -> Record {
+        let mut tags = bookmark.tags.clone();
+        tags.sort();
+        tags.dedup();
+
+        Record {
+            url: bookmark.url.clone(),
+            title: bookmark.title.clone(),
+            tags,
+            description: bookmark.description.clone(),
+            notes: bookmark.notes.clone(),
+        }
+    }
+
+    pub fn to_bookmark(&self) -> Bookmark {
+        Bookmark {
+            title: self.title.clone(),
+            url: self.url.clone(),
+            tags: self.tags.clone(),
+            description: self.description.clone(),
+            notes: self.notes.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+// Records by the normalized URL of the bookmarks.
+pub type Records = BTreeMap<String, Record>;
+
+pub fn records(db: &DB) -> Records {
+    db.get_all_bookmark(&ListOptions::default()).iter()
+        .map(|b| (url_key(&b.url), Record::from_bookmark(b)))
+        .collect()
+}
+
+fn escape(s: &str, separator: char) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c if c == separator => {
+                escaped.push('\\');
+                escaped.push(c);
+            },
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Unescaped parts of s split at the separators not escaped.
+fn unescape_split(s: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => part.push('\t'),
+                Some('n') => part.push('\n'),
+                Some('r') => part.push('\r'),
+                Some(c) => part.push(c),
+                None => part.push('\\'),
+            },
+            c if c == separator => parts.push(String::new()),
+            c => part.push(c),
+        }
+    }
+
+    parts
+}
+
+// One line per record, sorted by URL so that the same library always gives
+// the same file and changes touch the lines of the changed bookmarks only.
+pub fn to_text(records: &Records) -> String {
+    let mut text = format!("{}\n", HEADER);
+
+    for record in records.values() {
+        let tags: Vec<String> = record.tags.iter().map(|t| escape(t, ',')).collect();
+        let fields = [
+            escape(&record.url, '\t'),
+            escape(&record.title, '\t'),
+            tags.join(","),
+            escape(&record.description, '\t'),
+            escape(&record.notes, '\t'),
+        ];
+        text.push_str(&fields.join("\t"));
+        text.push('\n');
+    }
+
+    text
+}
+
+pub fn parse_text(text: &str) -> Result<Records, String> {
+    let mut records = Records::new();
+
+    for (i, line) in text.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 5 {
+            return Err(format!("Error: Invalid line {} of {}", i + 1, FILE_NAME));
+        }
+        let text = |i: usize| unescape_split(fields[i], '\t').concat();
+        let tags = if fields[2].is_empty() { Vec::new() } else { unescape_split(fields[2], ',') };
+
+        let record = Record {
+            url: text(0),
+            title: text(1),
+            tags,
+            description: text(3),
+            notes: text(4),
+        };
+        records.insert(url_key(&record.url), record);
+    }
+
+    Ok(records)
+}
+
+// Value of a field changed on one side only, ours when both changed it.
+fn merge_field(base: Option<&str>, ours: &str, theirs: &str, conflict: &mut bool) -> String {
+    if ours == theirs || Some(theirs) == base {
+        ours.to_string()
+    } else if Some(ours) == base {
+        theirs.to_string()
+    } else {
+        *conflict = true;
+        ours.to_string()
+    }
+}
+
+// Tags kept on both sides or added on either one.
+fn merge_tags(base: &[String], ours: &[String], theirs: &[String]) -> Vec<String> {
+    let tags: BTreeSet<&String> = ours.iter().chain(theirs)
+        .filter(|t| (ours.contains(t) && theirs.contains(t)) || !base.contains(t))
+        .collect();
+    tags.into_iter().cloned().collect()
+}
+
+fn merge_record(base: Option<&Record>, ours: &Record, theirs: &Record) -> (Record, bool) {
+    let mut conflict = false;
+    let no_tags = Vec::new();
+
+    let record = Record {
+        url: merge_field(base.map(|b| b.url.as_str()), &ours.url, &theirs.url, &mut conflict),
+        title: merge_field(base.map(|b| b.title.as_str()), &ours.title, &theirs.title, &mut conflict),
+        tags: merge_tags(base.map_or(&no_tags, |b| &b.tags), &ours.tags, &theirs.tags),
+        description: merge_field(base.map(|b| b.description.as_str()),
+                                 &ours.description, &theirs.description, &mut conflict),
+        notes: merge_field(base.map(|b| b.notes.as_str()), &ours.notes, &theirs.notes, &mut conflict),
+    };
+
+    (record, conflict)
+}
+
+// Three-way merge of the records of both sides with those of their last
+// common sync, with a message for every bookmark changed on both sides.
+pub fn merge(base: &Records, ours: &Records, theirs: &Records) -> (Records, Vec<String>) {
+    let keys: BTreeSet<&String> = base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+    let mut merged = Records::new();
+    let mut conflicts = Vec::new();
+
+    for key in keys {
+        let (b, o, t) = (base.get(key), ours.get(key), theirs.get(key));
+
+        let record = if o == t || t == b {
+            o.cloned()
+        } else if o == b {
+            t.cloned()
+        } else {
+            match (o, t) {
+                (Some(o), Some(t)) => {
+                    let (record, conflict) = merge_record(b, o, t);
+                    if conflict {
+                        conflicts.push(format!("{}: changed on both sides, kept local changes", o.url));
+                    }
+                    Some(record)
+                },
+                (Some(o), None) => {
+                    conflicts.push(format!("{}: deleted remotely but changed locally, kept", o.url));
+                    Some(o.clone())
+                },
+                (None, Some(t)) => {
+                    conflicts.push(format!("{}: deleted locally but changed remotely, kept", t.url));
+                    Some(t.clone())
+                },
+                (None, None) => None,
+            }
+        };
+
+        if let Some(record) = record {
+            merged.insert(key.clone(), record);
+        }
+    }
+
+    (merged, conflicts)
+}
+
+#[derive(Default, Debug)]
+pub struct Report {
+    pub added: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub conflicts: Vec<String>
+}
+
+// Give bookmark id the fields of record.
+pub fn update_bookmark<'a>(db: &'a DB, id: i64, record: &Record) -> Result<(), &'a str> {
+    db.update_bookmark(id, &record.title, &record.url)?;
+    db.update_description(id, &record.description);
+    db.update_notes(id, &record.notes);
+    db.set_tags(id, &record.tags);
+    Ok(())
+}
+
+// Bring the library from ours to merged.
+fn apply(db: &DB, ours: &Records, merged: &Records, report: &mut Report) -> Result<(), String> {
+    for (key, record) in ours {
+        if !merged.contains_key(key) {
+            if let Some(id) = db.get_id_by_url(&record.url) {
+                remove_bookmark(db, id)?;
+                report.deleted += 1;
+            }
+        }
+    }
+
+    for (key, record) in merged {
+        match ours.get(key) {
+            None => {
+                db.insert_bookmark(&record.to_bookmark())?;
+                report.added += 1;
+            },
+            Some(old) if old != record => {
+                let id = db.get_id_by_url(&old.url).ok_or("Error: Did not match index")?;
+                update_bookmark(db, id, record)?;
+                report.updated += 1;
+            },
+            Some(_) => (),
+        }
+    }
+
+    Ok(())
+}
+
+// Git working tree the library is synced through.
+pub struct Repo {
+    dir: PathBuf
+}
+
+impl Repo {
+    pub fn new(dir: &Path) -> Repo {
+        Repo { dir: dir.to_path_buf() }
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String, String> {
+        let output = Command::new("git").arg("-C").arg(&self.dir).args(args).output()
+            .map_err(|e| format!("Error: Failed to run git: {}", e))?;
+
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).trim_end_matches('\n').to_string())
+        } else {
+            Err(format!("Error: git {} failed: {}",
+                        args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+        }
+    }
+
+    // Whether the git command succeeds, for commands answering by their status.
+    fn test(&self, args: &[&str]) -> bool {
+        self.git(args).is_ok()
+    }
+
+    // Create the working tree on first use, pointing origin at remote.
+    fn init(&self, remote: Option<&str>) -> Result<(), String> {
+        if !self.dir.join(".git").exists() {
+            fs::create_dir_all(&self.dir)
+                .map_err(|e| format!("Error: Failed to create {}: {}", self.dir.display(), e))?;
+            self.git(&["init", "-q"])?;
+        }
+
+        match (remote, self.test(&["remote", "get-url", "origin"])) {
+            (Some(remote), false) => self.git(&["remote", "add", "origin", remote]).map(|_| ()),
+            (Some(remote), true) => self.git(&["remote", "set-url", "origin", remote]).map(|_| ()),
+            (None, true) => Ok(()),
+            (None, false) => Err("Error: No sync remote, set remote in the [sync] table \
+                                  of ~/.bkm/config.toml or pass --remote".to_string()),
+        }
+    }
+
+    // Records of the file at revision, none when it did not exist yet.
+    fn records_at(&self, revision: Option<&str>) -> Result<Records, String> {
+        match revision {
+            Some(revision) => match self.git(&["show", &format!("{}:{}", revision, FILE_NAME)]) {
+                Ok(text) => parse_text(&text),
+                Err(_) => Ok(Records::new()),
+            },
+            None => Ok(Records::new()),
+        }
+    }
+
+    // Run a git command recording commits, as bkm on machines where git
+    // was never set up.
+    fn git_committing(&self, args: &[&str]) -> Result<(), String> {
+        let mut all = Vec::new();
+        if !self.test(&["config", "user.email"]) {
+            all.extend_from_slice(&["-c", "user.name=bkm", "-c", "user.email=bkm@localhost"]);
+        }
+        all.extend_from_slice(args);
+        self.git(&all).map(|_| ())
+    }
+}
+
+// Merge the library with the branch of the remote and push the result.
+pub fn sync(db: &DB, repo: &Repo, remote: Option<&str>, branch: &str) -> Result<Report, String> {
+    repo.init(remote)?;
+    repo.git(&["fetch", "-q", "origin"])?;
+
+    let remote_ref = format!("refs/remotes/origin/{}", branch);
+    let theirs_head = if repo.test(&["rev-parse", "-q", "--verify", &remote_ref]) {
+        Some(remote_ref.as_str())
+    } else {
+        None
+    };
+    let head = if repo.test(&["rev-parse", "-q", "--verify", "HEAD"]) { Some("HEAD") } else { None };
+
+    // The last sync both sides went through.
+    let base_revision = match (head, theirs_head) {
+        (Some(head), Some(theirs)) => repo.git(&["merge-base", head, theirs]).ok(),
+        (Some(head), None) => Some(head.to_string()),
+        _ => None,
+    };
+    let base = repo.records_at(base_revision.as_deref())?;
+    let theirs = match theirs_head {
+        Some(_) => repo.records_at(theirs_head)?,
+        None => base.clone(),
+    };
+    let ours = records(db);
+
+    let (merged, conflicts) = merge(&base, &ours, &theirs);
+    let mut report = Report { conflicts, ..Default::default() };
+    apply(db, &ours, &merged, &mut report)?;
+
+    let path = repo.dir.join(FILE_NAME);
+    fs::write(&path, to_text(&merged))
+        .map_err(|e| format!("Error: Failed to write {}: {}", path.display(), e))?;
+    repo.git(&["add", FILE_NAME])?;
+    if !repo.git(&["status", "--porcelain"])?.is_empty() {
+        repo.git_committing(&["commit", "-q", "-m", "Sync bookmarks"])?;
+    }
+
+    // Record the remote commits as merged, the tree already holds the result.
+    if let Some(theirs) = theirs_head {
+        if !repo.test(&["merge-base", "--is-ancestor", theirs, "HEAD"]) {
+            repo.git_committing(&["merge", "-q", "-s", "ours", "--allow-unrelated-histories",
+                                  "-m", "Merge remote bookmarks", theirs])?;
+        }
+    }
+
+    repo.git(&["push", "-q", "origin", &format!("HEAD:refs/heads/{}", branch)])
+        .map_err(|_| "Error: Failed to push, the remote may have changed during sync: run sync again".to_string())?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile;
+
+    fn record(url: &str, title: &str, tags: &[&str]) -> Record {
+        Record {
+            url: url.to_string(),
+            title: title.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            description: String::new(),
+            notes: String::new(),
+        }
+    }
+
+    fn to_records(list: &[Record]) -> Records {
+        list.iter().map(|r| (url_key(&r.url), r.clone())).collect()
+    }
+
+    #[test]
+    fn test_text() {
+        let mut odd = record("https://example.com/", "Tab\there", &["a,b", "back\\slash"]);
+        odd.notes = "Two\nlines".to_string();
+        let records = to_records(&[odd, record("https://github.com/", "GitHub", &[])]);
+
+        let text = to_text(&records);
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.contains("https://github.com/\tGitHub\t\t\t\n"));
+        assert_eq!(parse_text(&text).unwrap(), records);
+        assert!(parse_text("https://github.com/\tGitHub\n").is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = to_records(&[
+            record("https://a.com/", "A", &["x"]),
+            record("https://b.com/", "B", &[]),
+            record("https://c.com/", "C", &[]),
+            record("https://d.com/", "D", &[]),
+        ]);
+        let ours = to_records(&[
+            record("https://a.com/", "A local", &["x", "y"]),
+            record("https://b.com/", "B", &[]),
+            record("https://d.com/", "D local", &[]),
+            record("https://e.com/", "E", &[]),
+        ]);
+        let theirs = to_records(&[
+            record("https://a.com/", "A", &["z"]),
+            record("https://c.com/", "C", &[]),
+            record("https://d.com/", "D remote", &[]),
+            record("https://f.com/", "F", &[]),
+        ]);
+
+        let (merged, conflicts) = merge(&base, &ours, &theirs);
+        let titles: Vec<&str> = merged.values().map(|r| r.title.as_str()).collect();
+        assert_eq!(titles, vec!["A local", "D local", "E", "F"]);
+        assert_eq!(merged[&url_key("https://a.com/")].tags, vec!["y", "z"]);
+        assert_eq!(conflicts, vec!["https://d.com/: changed on both sides, kept local changes"]);
+    }
+
+    #[test]
+    fn test_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = dir.path().join("remote.git");
+        Command::new("git").args(["init", "-q", "--bare"]).arg(&remote).status().unwrap();
+        let remote = remote.to_str().unwrap();
+
+        let (db_a, repo_a) = (DB::open_in_memory(), Repo::new(&dir.path().join("a")));
+        let (db_b, repo_b) = (DB::open_in_memory(), Repo::new(&dir.path().join("b")));
+
+        db_a.insert_bookmark(&record("https://github.com/", "GitHub", &["git"]).to_bookmark()).unwrap();
+        sync(&db_a, &repo_a, Some(remote), "main").unwrap();
+        let report = sync(&db_b, &repo_b, Some(remote), "main").unwrap();
+        assert_eq!(report.added, 1);
+
+        // Concurrent edits on both machines.
+        db_a.add_bookmark(&"Rust".to_string(), &"https://www.rust-lang.org/".to_string()).unwrap();
+        let id = db_b.get_id_by_url("https://github.com/").unwrap();
+        db_b.update_bookmark(id, "GitHub remote", "https://github.com/").unwrap();
+        db_b.add_tag(id, "hosting");
+
+        sync(&db_b, &repo_b, None, "main").unwrap();
+        let report = sync(&db_a, &repo_a, None, "main").unwrap();
+        assert_eq!((report.added, report.updated, report.deleted), (0, 1, 0));
+        sync(&db_b, &repo_b, None, "main").unwrap();
+
+        assert_eq!(records(&db_a), records(&db_b));
+        assert_eq!(records(&db_a).len(), 2);
+        assert_eq!(db_a.get_bookmark_by_id(1).unwrap().title, "GitHub remote");
+
+        // Deleting on one machine deletes on the other.
+        db_b.delete_bookmark(id);
+        sync(&db_b, &repo_b, None, "main").unwrap();
+        let report = sync(&db_a, &repo_a, None, "main").unwrap();
+        assert_eq!(report.deleted, 1);
+        assert!(report.conflicts.is_empty());
+        assert_eq!(records(&db_a), records(&db_b));
+    }
+}
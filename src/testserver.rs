@@ -0,0 +1,149 @@
+// Local HTTP stand-in server for tests of code fetching pages.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub delay: Option<Duration>
+}
+
+impl Response {
+    pub fn new(status: u16, body: &[u8]) -> Self {
+        Response { status, headers: Vec::new(), body: body.to_vec(), delay: None }
+    }
+
+    pub fn html(body: &str) -> Self {
+        Response::new(200, body.as_bytes()).header("Content-Type", "text/html")
+    }
+
+    pub fn redirect(status: u16, location: &str) -> Self {
+        Response::new(status, b"").header("Location", location)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+}
+
+pub struct TestServer {
+    pub addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>
+}
+
+impl TestServer {
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+// Serve every connection on its own thread with the response of handler.
+pub fn serve<F>(handler: F) -> TestServer
+    where F: Fn(&Request) -> Response + Send + Sync + 'static
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let handler = Arc::new(handler);
+
+    let log = requests.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let handler = handler.clone();
+            let log = log.clone();
+
+            thread::spawn(move || {
+                if let Some(request) = read_request(&stream) {
+                    log.lock().unwrap().push(request.clone());
+                    write_response(stream, &handler(&request));
+                }
+            });
+        }
+    });
+
+    TestServer { addr, requests }
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(i) = line.find(':') {
+            headers.push((line[..i].trim().to_string(), line[i + 1..].trim().to_string()));
+        }
+    }
+
+    let request = Request { method, path, headers };
+    if let Some(length) = request.header("Content-Length").and_then(|l| l.parse().ok()) {
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).ok()?;
+    }
+
+    Some(request)
+}
+
+fn write_response(mut stream: TcpStream, response: &Response) {
+    if let Some(delay) = response.delay {
+        thread::sleep(delay);
+    }
+
+    let mut head = format!(
+        "HTTP/1.1 {} Test\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status, response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    // Clients giving up early (e.g. on timeout) close the connection.
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(&response.body);
+}
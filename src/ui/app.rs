@@ -0,0 +1,361 @@
+use termion::event::Key;
+
+use bookmark::Bookmark;
+use database::DB;
+use query::{ListOptions, Query};
+
+#[derive(PartialEq, Debug)]
+pub enum Focus {
+    Bookmarks,
+    Tags
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Field {
+    Title,
+    Url,
+    Tags
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Mode {
+    Normal,
+    Search,
+    Prompt(Field),
+    Confirm
+}
+
+// Side effects outside the database, performed by the caller.
+#[derive(PartialEq, Debug)]
+pub enum Effect {
+    Open(String),
+    Copy(String),
+    Quit
+}
+
+pub struct App<'a> {
+    db: &'a DB,
+    pub search: String,
+    pub bookmarks: Vec<Bookmark>,
+    pub tags: Vec<String>,
+    pub tag: Option<String>,
+    pub selected: usize,
+    pub tag_selected: usize,
+    pub focus: Focus,
+    pub mode: Mode,
+    pub input: String,
+    pub message: String
+}
+
+impl<'a> App<'a> {
+    pub fn new(db: &'a DB) -> Self {
+        let mut app = App {
+            db,
+            search: String::new(),
+            bookmarks: Vec::new(),
+            tags: Vec::new(),
+            tag: None,
+            selected: 0,
+            tag_selected: 0,
+            focus: Focus::Bookmarks,
+            mode: Mode::Normal,
+            input: String::new(),
+            message: String::new(),
+        };
+
+        app.refresh();
+        app
+    }
+
+    // Reload bookmarks matching the search and the tag filter.
+    pub fn refresh(&mut self) {
+        let mut query = Query::parse(&self.search);
+        if let Some(ref tag) = self.tag {
+            query.tags.push(tag.clone());
+        }
+
+        self.bookmarks = self.db.search_query(&query, &ListOptions::default());
+        self.tags = self.db.get_all_tag();
+
+        if self.selected >= self.bookmarks.len() {
+            self.selected = self.bookmarks.len().saturating_sub(1);
+        }
+        if self.tag_selected > self.tags.len() {
+            self.tag_selected = self.tags.len();
+        }
+    }
+
+    pub fn selected_bookmark(&self) -> Option<&Bookmark> {
+        self.bookmarks.get(self.selected)
+    }
+
+    pub fn handle_key(&mut self, key: Key) -> Option<Effect> {
+        self.message.clear();
+
+        match self.mode {
+            Mode::Normal => match self.focus {
+                Focus::Bookmarks => self.handle_bookmarks_key(key),
+                Focus::Tags => self.handle_tags_key(key),
+            },
+            Mode::Search => {
+                self.handle_search_key(key);
+                None
+            },
+            Mode::Prompt(_) => {
+                self.handle_prompt_key(key);
+                None
+            },
+            Mode::Confirm => {
+                self.handle_confirm_key(key);
+                None
+            },
+        }
+    }
+
+    fn handle_bookmarks_key(&mut self, key: Key) -> Option<Effect> {
+        match key {
+            Key::Char('q') | Key::Esc | Key::Ctrl('c') => return Some(Effect::Quit),
+            Key::Char('/') => self.mode = Mode::Search,
+            Key::Char('\t') => self.focus = Focus::Tags,
+            Key::Char('j') | Key::Down if self.selected + 1 < self.bookmarks.len() => {
+                self.selected += 1;
+            },
+            Key::Char('k') | Key::Up => self.selected = self.selected.saturating_sub(1),
+            Key::Char('g') | Key::Home => self.selected = 0,
+            Key::Char('G') | Key::End => {
+                self.selected = self.bookmarks.len().saturating_sub(1);
+            },
+            _ => return self.handle_bookmark_action(key),
+        }
+
+        None
+    }
+
+    fn handle_bookmark_action(&mut self, key: Key) -> Option<Effect> {
+        let (id, title, url, tags) = match self.selected_bookmark() {
+            Some(b) => (b.id, b.title.clone(), b.url.clone(), b.tags.join(", ")),
+            None => return None,
+        };
+
+        match key {
+            Key::Char('\n') | Key::Char('o') => {
+                self.db.visit_bookmark(id);
+                return Some(Effect::Open(url));
+            },
+            Key::Char('y') => return Some(Effect::Copy(url)),
+            Key::Char('e') => self.prompt(Field::Title, title),
+            Key::Char('u') => self.prompt(Field::Url, url),
+            Key::Char('t') => self.prompt(Field::Tags, tags),
+            Key::Char('d') => self.mode = Mode::Confirm,
+            _ => {},
+        }
+
+        None
+    }
+
+    fn handle_tags_key(&mut self, key: Key) -> Option<Effect> {
+        match key {
+            Key::Char('q') | Key::Esc | Key::Ctrl('c') => return Some(Effect::Quit),
+            Key::Char('\t') => self.focus = Focus::Bookmarks,
+            Key::Char('j') | Key::Down if self.tag_selected < self.tags.len() => {
+                self.tag_selected += 1;
+            },
+            Key::Char('k') | Key::Up => self.tag_selected = self.tag_selected.saturating_sub(1),
+            Key::Char('\n') => {
+                // The first entry of the sidebar shows all bookmarks.
+                self.tag = if self.tag_selected == 0 {
+                    None
+                } else {
+                    Some(self.tags[self.tag_selected - 1].clone())
+                };
+                self.selected = 0;
+                self.focus = Focus::Bookmarks;
+                self.refresh();
+            },
+            _ => {},
+        }
+
+        None
+    }
+
+    fn handle_search_key(&mut self, key: Key) {
+        match key {
+            Key::Char('\n') => self.mode = Mode::Normal,
+            Key::Esc => {
+                self.search.clear();
+                self.mode = Mode::Normal;
+            },
+            Key::Ctrl('u') => self.search.clear(),
+            Key::Backspace => {
+                self.search.pop();
+            },
+            Key::Char(c) => self.search.push(c),
+            _ => return,
+        }
+
+        self.selected = 0;
+        self.refresh();
+    }
+
+    fn handle_prompt_key(&mut self, key: Key) {
+        match key {
+            Key::Char('\n') => {
+                self.apply_prompt();
+                self.mode = Mode::Normal;
+                self.refresh();
+            },
+            Key::Esc => self.mode = Mode::Normal,
+            Key::Ctrl('u') => self.input.clear(),
+            Key::Backspace => {
+                self.input.pop();
+            },
+            Key::Char(c) => self.input.push(c),
+            _ => {},
+        }
+    }
+
+    fn handle_confirm_key(&mut self, key: Key) {
+        if key == Key::Char('y') || key == Key::Char('Y') {
+            if let Some(id) = self.selected_bookmark().map(|b| b.id) {
+                self.db.delete_bookmark(id);
+                self.message = format!("Index {} deleted", id);
+            }
+            self.refresh();
+        }
+
+        self.mode = Mode::Normal;
+    }
+
+    fn prompt(&mut self, field: Field, input: String) {
+        self.input = input;
+        self.mode = Mode::Prompt(field);
+    }
+
+    fn apply_prompt(&mut self) {
+        let (id, title, url) = match self.selected_bookmark() {
+            Some(b) => (b.id, b.title.clone(), b.url.clone()),
+            None => return,
+        };
+        let input = self.input.trim().to_string();
+
+        let result = match self.mode {
+            Mode::Prompt(Field::Title) => self.db.update_bookmark(id, &input, &url),
+            Mode::Prompt(Field::Url) => self.db.update_bookmark(id, &title, &input),
+            Mode::Prompt(Field::Tags) => {
+                let tags: Vec<String> = input.split(',')
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty())
+                    .collect();
+                self.db.set_tags(id, &tags);
+                Ok(())
+            },
+            _ => Ok(()),
+        };
+
+        self.message = match result {
+            Ok(_) => format!("Index {} updated", id),
+            Err(e) => e.to_string(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_db() -> DB {
+        let db = DB::open_in_memory();
+        db.add_bookmark(&"GitHub".to_string(), &"https://github.com".to_string()).unwrap();
+        db.add_tag(1, "git");
+        db.add_bookmark(&"Google".to_string(), &"https://google.com".to_string()).unwrap();
+        db.add_tag(2, "search");
+
+        db
+    }
+
+    fn type_keys(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            app.handle_key(Key::Char(c));
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let db = test_db();
+        let mut app = App::new(&db);
+        assert_eq!(app.bookmarks.len(), 2);
+
+        type_keys(&mut app, "/goo");
+        assert_eq!(app.mode, Mode::Search);
+        assert_eq!(app.bookmarks.len(), 1);
+        assert_eq!(app.bookmarks[0].title, "Google");
+
+        app.handle_key(Key::Esc);
+        assert_eq!(app.mode, Mode::Normal);
+        assert_eq!(app.bookmarks.len(), 2);
+    }
+
+    #[test]
+    fn test_tag_filter() {
+        let db = test_db();
+        let mut app = App::new(&db);
+
+        type_keys(&mut app, "\tjj\n");
+        assert_eq!(app.focus, Focus::Bookmarks);
+        assert_eq!(app.tag, Some("search".to_string()));
+        assert_eq!(app.bookmarks.len(), 1);
+
+        type_keys(&mut app, "\tkk\n");
+        assert_eq!(app.tag, None);
+        assert_eq!(app.bookmarks.len(), 2);
+    }
+
+    #[test]
+    fn test_open_and_copy() {
+        let db = test_db();
+        let mut app = App::new(&db);
+
+        app.handle_key(Key::Down);
+        assert_eq!(app.handle_key(Key::Char('o')),
+                   Some(Effect::Open("https://google.com".to_string())));
+        assert_eq!(app.handle_key(Key::Char('y')),
+                   Some(Effect::Copy("https://google.com".to_string())));
+        assert_eq!(app.handle_key(Key::Char('q')), Some(Effect::Quit));
+    }
+
+    #[test]
+    fn test_edit_and_retag() {
+        let db = test_db();
+        let mut app = App::new(&db);
+
+        app.handle_key(Key::Char('e'));
+        assert_eq!(app.input, "GitHub");
+        app.handle_key(Key::Ctrl('u'));
+        type_keys(&mut app, "GitHub Home\n");
+        assert_eq!(db.get_bookmark_by_id(1).unwrap().title, "GitHub Home");
+
+        app.handle_key(Key::Char('u'));
+        app.handle_key(Key::Ctrl('u'));
+        type_keys(&mut app, "https://google.com\n");
+        assert_eq!(app.message, "Error: URL already exists");
+        assert_eq!(db.get_url_by_id(1), "https://github.com");
+
+        app.handle_key(Key::Char('t'));
+        type_keys(&mut app, ", hosting\n");
+        assert_eq!(db.get_tags(1).unwrap(), vec!["git", "hosting"]);
+    }
+
+    #[test]
+    fn test_delete() {
+        let db = test_db();
+        let mut app = App::new(&db);
+
+        type_keys(&mut app, "dn");
+        assert_eq!(app.bookmarks.len(), 2);
+
+        type_keys(&mut app, "Gdy");
+        assert_eq!(app.bookmarks.len(), 1);
+        assert_eq!(app.selected, 0);
+        assert!(db.get_bookmark_by_id(2).is_err());
+    }
+}
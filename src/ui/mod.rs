@@ -0,0 +1,164 @@
+use base64;
+use std::io::{self, Write};
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+use tui::backend::{Backend, TermionBackend};
+use tui::layout::{Constraint, Direction, Layout};
+use tui::style::{Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use tui::{Frame, Terminal};
+use webbrowser;
+
+use database::DB;
+
+mod app;
+mod picker;
+
+use self::app::{App, Effect, Field, Focus, Mode};
+pub use self::picker::{pick, pick_external};
+
+const HELP: &str = "q quit  / search  Tab tags  o open  y copy  e title  u url  t tags  d delete";
+
+pub fn run(db: &DB) -> io::Result<()> {
+    let stdout = io::stdout().into_raw_mode()?;
+    let stdout = AlternateScreen::from(stdout);
+    let mut terminal = Terminal::new(TermionBackend::new(stdout))?;
+    let mut app = App::new(db);
+    let mut keys = io::stdin().keys();
+
+    loop {
+        terminal.draw(|f| draw(f, &app))?;
+
+        let key = match keys.next() {
+            Some(key) => key?,
+            None => break,
+        };
+
+        let effect = match db.request(|| app.handle_key(key)) {
+            Ok(effect) => effect,
+            Err(e) => {
+                app.message = e;
+                None
+            },
+        };
+        match effect {
+            Some(Effect::Quit) => break,
+            Some(Effect::Open(ref url)) if webbrowser::open(url).is_err() => {
+                app.message = format!("Error: Failed to open {}", url);
+            },
+            Some(Effect::Open(_)) => {},
+            Some(Effect::Copy(url)) => {
+                // OSC 52 asks the terminal emulator to set the clipboard.
+                let backend = terminal.backend_mut();
+                write!(backend, "\x1b]52;c;{}\x07", base64::encode(&url))?;
+                Write::flush(backend)?;
+                app.message = format!("Copied {}", url);
+            },
+            None => {},
+        }
+    }
+
+    Ok(())
+}
+
+fn draw<B: Backend>(f: &mut Frame<B>, app: &App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(1), Constraint::Length(1)].as_ref())
+        .split(f.size());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(25), Constraint::Percentage(75)].as_ref())
+        .split(rows[1]);
+    let highlight = Style::default().add_modifier(Modifier::REVERSED);
+
+    let search = Paragraph::new(app.search.as_str())
+        .block(Block::default().borders(Borders::ALL).title("Search"));
+    f.render_widget(search, rows[0]);
+
+    let mut tag_items = vec![ListItem::new("All")];
+    tag_items.extend(app.tags.iter().map(|t| ListItem::new(t.as_str())));
+    let mut tags = List::new(tag_items)
+        .block(Block::default().borders(Borders::ALL).title("Tags"));
+    if app.focus == Focus::Tags {
+        tags = tags.highlight_style(highlight);
+    }
+    let mut tag_state = ListState::default();
+    tag_state.select(Some(app.tag_selected));
+    f.render_stateful_widget(tags, columns[0], &mut tag_state);
+
+    let bookmark_items: Vec<ListItem> = app.bookmarks.iter().map(|b| {
+        let mut lines = vec![
+            Spans::from(format!("{} {}", b.id, b.title)),
+            Spans::from(format!("    {}", b.url)),
+        ];
+        if !b.tags.is_empty() {
+            lines.push(Spans::from(format!("    {}", b.tags.join(", "))));
+        }
+        ListItem::new(lines)
+    }).collect();
+    let title = format!("Bookmarks ({})", app.bookmarks.len());
+    let mut bookmarks = List::new(bookmark_items)
+        .block(Block::default().borders(Borders::ALL).title(title));
+    if app.focus == Focus::Bookmarks {
+        bookmarks = bookmarks.highlight_style(highlight);
+    }
+    let mut bookmark_state = ListState::default();
+    if !app.bookmarks.is_empty() {
+        bookmark_state.select(Some(app.selected));
+    }
+    f.render_stateful_widget(bookmarks, columns[1], &mut bookmark_state);
+
+    let status = match app.mode {
+        Mode::Search => format!("/{}", app.search),
+        Mode::Prompt(Field::Title) => format!("Title: {}", app.input),
+        Mode::Prompt(Field::Url) => format!("URL: {}", app.input),
+        Mode::Prompt(Field::Tags) => format!("Tags: {}", app.input),
+        Mode::Confirm => match app.selected_bookmark() {
+            Some(b) => format!("Delete \"{}\"? (y/n)", b.title),
+            None => String::new(),
+        },
+        Mode::Normal if !app.message.is_empty() => app.message.clone(),
+        Mode::Normal => HELP.to_string(),
+    };
+    f.render_widget(Paragraph::new(Span::raw(status)), rows[2]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use termion::event::Key;
+    use tui::backend::TestBackend;
+
+    fn render(app: &App) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(80, 16)).unwrap();
+        terminal.draw(|f| draw(f, app)).unwrap();
+
+        let buffer = terminal.backend().buffer();
+        let width = buffer.area.width as usize;
+        buffer.content.chunks(width)
+            .map(|row| row.iter().map(|c| c.symbol.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_draw() {
+        let db = DB::open_in_memory();
+        db.add_bookmark(&"GitHub".to_string(), &"https://github.com".to_string()).unwrap();
+        db.add_tag(1, "git");
+        let mut app = App::new(&db);
+
+        let screen = render(&app).join("\n");
+        assert!(screen.contains("Bookmarks (1)"));
+        assert!(screen.contains("1 GitHub"));
+        assert!(screen.contains("https://github.com"));
+        assert!(screen.contains("git"));
+        assert!(screen.contains(HELP));
+
+        app.handle_key(Key::Char('d'));
+        let screen = render(&app);
+        assert!(screen[15].starts_with("Delete \"GitHub\"? (y/n)"));
+    }
+}
@@ -0,0 +1,292 @@
+use std::cmp;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use termion;
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+use tui::backend::{Backend, TermionBackend};
+use tui::layout::{Constraint, Direction, Layout};
+use tui::style::{Modifier, Style};
+use tui::widgets::{List, ListItem, ListState, Paragraph};
+use tui::{Frame, Terminal};
+
+use bookmark::Bookmark;
+
+#[derive(PartialEq, Debug)]
+pub enum Outcome {
+    Continue,
+    Accept,
+    Cancel
+}
+
+pub struct Picker {
+    pub query: String,
+    candidates: Vec<(i64, String)>,
+    pub matches: Vec<usize>,
+    pub cursor: usize,
+    pub chosen: Vec<i64>
+}
+
+impl Picker {
+    pub fn new(bookmarks: &[Bookmark]) -> Self {
+        let candidates = bookmarks.iter().map(|b| {
+            (b.id, format!("{} {}  {}  {}", b.id, b.title, b.url, b.tags.join(", ")))
+        }).collect();
+
+        let mut picker = Picker {
+            query: String::new(),
+            candidates,
+            matches: Vec::new(),
+            cursor: 0,
+            chosen: Vec::new(),
+        };
+
+        picker.update_matches();
+        picker
+    }
+
+    fn update_matches(&mut self) {
+        let mut scored: Vec<(i64, usize)> = self.candidates.iter().enumerate()
+            .filter_map(|(i, (_, text))| fuzzy_score(&self.query, text).map(|s| (s, i)))
+            .collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+        self.cursor = 0;
+    }
+
+    pub fn handle_key(&mut self, key: Key) -> Outcome {
+        match key {
+            Key::Char('\n') => return Outcome::Accept,
+            Key::Esc | Key::Ctrl('c') => return Outcome::Cancel,
+            Key::Down | Key::Ctrl('n') if self.cursor + 1 < self.matches.len() => {
+                self.cursor += 1;
+            },
+            Key::Up | Key::Ctrl('p') => self.cursor = self.cursor.saturating_sub(1),
+            Key::Char('\t') => {
+                if let Some(&i) = self.matches.get(self.cursor) {
+                    let id = self.candidates[i].0;
+                    match self.chosen.iter().position(|&c| c == id) {
+                        Some(p) => {
+                            self.chosen.remove(p);
+                        },
+                        None => self.chosen.push(id),
+                    }
+                    if self.cursor + 1 < self.matches.len() {
+                        self.cursor += 1;
+                    }
+                }
+            },
+            Key::Backspace => {
+                self.query.pop();
+                self.update_matches();
+            },
+            Key::Ctrl('u') => {
+                self.query.clear();
+                self.update_matches();
+            },
+            Key::Char(c) => {
+                self.query.push(c);
+                self.update_matches();
+            },
+            _ => {},
+        }
+
+        Outcome::Continue
+    }
+
+    // Chosen ids, or the id under the cursor when nothing is chosen.
+    pub fn result(&self) -> Vec<i64> {
+        if !self.chosen.is_empty() {
+            return self.chosen.clone();
+        }
+
+        match self.matches.get(self.cursor) {
+            Some(&i) => vec![self.candidates[i].0],
+            None => Vec::new(),
+        }
+    }
+}
+
+// Score of text containing every space separated term of pattern as a
+// case-insensitive subsequence, higher is better.
+pub fn fuzzy_score(pattern: &str, text: &str) -> Option<i64> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let mut score = 0;
+    for term in pattern.to_lowercase().split_whitespace() {
+        score += term_score(term, &text)?;
+    }
+
+    Some(score)
+}
+
+fn term_score(term: &str, text: &[char]) -> Option<i64> {
+    let mut score = 0;
+    let mut pos = 0;
+    let mut prev: Option<usize> = None;
+
+    for c in term.chars() {
+        let i = pos + text[pos..].iter().position(|&t| t == c)?;
+
+        score += 1;
+        match prev {
+            Some(p) if p + 1 == i => score += 5,
+            Some(p) => score -= cmp::min((i - p) as i64, 5),
+            None => {},
+        }
+        if i == 0 || !text[i - 1].is_alphanumeric() {
+            score += 3;
+        }
+
+        prev = Some(i);
+        pos = i + 1;
+    }
+
+    Some(score)
+}
+
+pub fn pick(bookmarks: &[Bookmark]) -> io::Result<Vec<i64>> {
+    // Use the terminal directly so the picker works when stdout is piped.
+    let tty = termion::get_tty()?;
+    let keys = tty.try_clone()?.keys();
+    let screen = AlternateScreen::from(tty.into_raw_mode()?);
+    let mut terminal = Terminal::new(TermionBackend::new(screen))?;
+    let mut picker = Picker::new(bookmarks);
+
+    terminal.draw(|f| draw(f, &picker))?;
+    for key in keys {
+        match picker.handle_key(key?) {
+            Outcome::Accept => return Ok(picker.result()),
+            Outcome::Cancel => break,
+            Outcome::Continue => {},
+        }
+        terminal.draw(|f| draw(f, &picker))?;
+    }
+
+    Ok(Vec::new())
+}
+
+// Pick with an external command such as fzf, reading back the ids at the
+// start of the selected lines.
+pub fn pick_external(command: &str, bookmarks: &[Bookmark]) -> io::Result<Vec<i64>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let input: String = bookmarks.iter()
+        .map(|b| format!("{}\t{}\t{}\t{}\n", b.id, b.title, b.url, b.tags.join(", ")))
+        .collect();
+
+    // Write from another thread so a command printing while reading cannot block.
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || {
+        // The command may exit before reading every candidate.
+        let _ = stdin.write_all(input.as_bytes());
+    });
+
+    let mut ids: Vec<i64> = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            if let Some(Ok(id)) = line.split('\t').next().map(|id| id.trim().parse()) {
+                ids.push(id);
+            }
+        }
+    }
+    writer.join().unwrap();
+    child.wait()?;
+
+    Ok(ids)
+}
+
+fn draw<B: Backend>(f: &mut Frame<B>, picker: &Picker) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Min(1), Constraint::Length(1)].as_ref())
+        .split(f.size());
+
+    f.render_widget(Paragraph::new(format!("> {}", picker.query)), rows[0]);
+
+    let items: Vec<ListItem> = picker.matches.iter().map(|&i| {
+        let (id, ref text) = picker.candidates[i];
+        let marker = if picker.chosen.contains(&id) { "* " } else { "  " };
+        ListItem::new(format!("{}{}", marker, text))
+    }).collect();
+    let list = List::new(items)
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default();
+    if !picker.matches.is_empty() {
+        state.select(Some(picker.cursor));
+    }
+    f.render_stateful_widget(list, rows[1], &mut state);
+
+    let status = format!(
+        "{}/{} ({} chosen)  Tab choose  Enter accept  Esc cancel",
+        picker.matches.len(), picker.candidates.len(), picker.chosen.len()
+    );
+    f.render_widget(Paragraph::new(status), rows[2]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_bookmarks() -> Vec<Bookmark> {
+        vec![
+            Bookmark::new(1, "GitHub".to_string(), "https://github.com".to_string(),
+                          vec!["git".to_string()]),
+            Bookmark::new(2, "Google".to_string(), "https://google.com".to_string(), vec![]),
+            Bookmark::new(3, "Rust".to_string(), "https://rust-lang.org".to_string(), vec![]),
+        ]
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(fuzzy_score("", "anything").is_some());
+        assert!(fuzzy_score("ghb", "GitHub").is_some());
+        assert!(fuzzy_score("hg", "GitHub").is_none());
+        assert!(fuzzy_score("git rust", "GitHub").is_none());
+        assert!(fuzzy_score("git", "GitHub").unwrap() > fuzzy_score("gih", "GitHub").unwrap());
+    }
+
+    #[test]
+    fn test_picker() {
+        let mut picker = Picker::new(&test_bookmarks());
+        assert_eq!(picker.matches.len(), 3);
+
+        for c in "goo".chars() {
+            picker.handle_key(Key::Char(c));
+        }
+        assert_eq!(picker.matches.len(), 1);
+        assert_eq!(picker.handle_key(Key::Char('\n')), Outcome::Accept);
+        assert_eq!(picker.result(), vec![2]);
+
+        picker.handle_key(Key::Ctrl('u'));
+        picker.handle_key(Key::Char('\t'));
+        picker.handle_key(Key::Down);
+        picker.handle_key(Key::Char('\t'));
+        assert_eq!(picker.result(), vec![1, 3]);
+
+        picker.handle_key(Key::Up);
+        picker.handle_key(Key::Up);
+        picker.handle_key(Key::Char('\t'));
+        assert_eq!(picker.result(), vec![3]);
+        assert_eq!(picker.handle_key(Key::Esc), Outcome::Cancel);
+    }
+
+    #[test]
+    fn test_pick_external() {
+        let ids = pick_external("grep -i rust", &test_bookmarks()).unwrap();
+        assert_eq!(ids, vec![3]);
+
+        let ids = pick_external("head -n 2", &test_bookmarks()).unwrap();
+        assert_eq!(ids, vec![1, 2]);
+    }
+}
@@ -1,23 +1,56 @@
-use reqwest;
+use base64;
+use dirs;
 use select::document::Document;
+use select::node::Node;
 use select::predicate::Name;
-use std::fs::File;
-use std::io::BufReader;
+use serde_json;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
 use std::path::PathBuf;
 
 use bookmark::Bookmark;
+use normalize::host;
+
+// Directory holding the database and the other files of bkm.
+pub fn bkm_dir() -> PathBuf {
+    let mut path = dirs::home_dir().unwrap();
+    path.push(".bkm");
+    fs::create_dir_all(&path).unwrap();
+
+    path
+}
+
+// Random hexadecimal string of n bytes, for tokens and identifiers.
+pub fn random_hex(n: usize) -> String {
+    let mut bytes = vec![0u8; n];
+    File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut bytes))
+        .expect("Failed to read /dev/urandom");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Path set in the config file, relative to the home directory when starting with ~/.
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().unwrap().join(rest),
+        None => PathBuf::from(path),
+    }
+}
 
 pub fn get_bookmarks_from_html(path: PathBuf) -> Vec<Bookmark> {
-    let file = File::open(path)
-        .expect("File not found or cannot be opened");
-    let doc = Document::from_read(BufReader::new(&file)).unwrap();
+    parse_bookmarks_html(&read_file(path))
+}
+
+// Bookmarks of a Netscape bookmark file, tagged with the folder holding them.
+pub fn parse_bookmarks_html(html: &str) -> Vec<Bookmark> {
+    let doc = Document::from(html);
     let mut bookmarks: Vec<Bookmark> = Vec::new();
 
-    for (i, a) in doc.find(Name("a")).enumerate() {
+    for (i, a) in doc.find(Name("a")).filter(|a| a.attr("href").is_some()).enumerate() {
         let mut tags: Vec<String> = Vec::new();
 
-        if let Some(f) = a.parent().unwrap().parent().unwrap()
-            .parent().unwrap().find(Name("h3")).next() {
+        if let Some(f) = a.parent().and_then(|p| p.parent()).and_then(|p| p.parent())
+            .and_then(|p| p.find(Name("h3")).next()) {
             tags.push(f.text());
         }
 
@@ -30,23 +63,126 @@ pub fn get_bookmarks_from_html(path: PathBuf) -> Vec<Bookmark> {
         tags.sort();
         tags.dedup();
 
-        let bookmark = Bookmark::new(
+        let mut bookmark = Bookmark::new(
             (i + 1) as i64,
             a.text(),
             a.attr("href").unwrap().to_string(),
             tags,
         );
+        bookmark.description = get_description(a).unwrap_or_default();
+        bookmark.notes = a.attr("notes").unwrap_or("").to_string();
         bookmarks.push(bookmark);
     }
 
     bookmarks
 }
 
-pub fn get_title_from_url(url: &str) -> String {
-    let res = reqwest::get(url).expect("invalid URL");
-    let doc = Document::from_read(res).unwrap();
+// Text of the <DD> following the <DT> of a bookmark.
+fn get_description(a: Node) -> Option<String> {
+    let mut sibling = a.parent()?.next();
+
+    while let Some(node) = sibling {
+        match node.name() {
+            Some("dd") => return Some(node.text().trim().to_string()),
+            Some(_) => return None,
+            None => sibling = node.next(),
+        }
+    }
+
+    None
+}
+
+// ICON data URIs of the bookmarks of a Netscape bookmark file by host.
+pub fn get_icons_from_html(path: PathBuf) -> HashMap<String, String> {
+    parse_icons_html(&read_file(path))
+}
+
+pub fn parse_icons_html(html: &str) -> HashMap<String, String> {
+    let doc = Document::from(html);
+    let mut icons: HashMap<String, String> = HashMap::new();
 
-    doc.find(Name("title")).next().unwrap().text()
+    for a in doc.find(Name("a")) {
+        if let (Some(url), Some(icon)) = (a.attr("href"), a.attr("icon")) {
+            if let Some(host) = host(url) {
+                icons.entry(host).or_insert_with(|| icon.to_string());
+            }
+        }
+    }
+
+    icons
+}
+
+pub fn get_bookmarks_from_json(path: PathBuf) -> Vec<Bookmark> {
+    parse_bookmarks_json(&read_file(path)).expect("Invalid JSON bookmarks file")
+}
+
+pub fn parse_bookmarks_json(json: &str) -> Result<Vec<Bookmark>, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+fn read_file(path: PathBuf) -> String {
+    let mut file = File::open(path)
+        .expect("File not found or cannot be opened");
+    let mut content = String::new();
+    file.read_to_string(&mut content).expect("Failed to read file");
+
+    content
+}
+
+// Netscape bookmark file, as exported by browsers, with icons as data URIs by host.
+pub fn bookmarks_to_html(bookmarks: &[Bookmark], icons: &HashMap<String, String>) -> String {
+    let mut html = String::from("<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">
+<TITLE>Bookmarks</TITLE>
+<H1>Bookmarks</H1>
+<DL><p>
+");
+
+    for b in bookmarks {
+        html.push_str(&format!("    <DT><A HREF=\"{}\"", escape_html(&b.url)));
+        if !b.tags.is_empty() {
+            html.push_str(&format!(" TAGS=\"{}\"", escape_html(&b.tags.join(","))));
+        }
+        if !b.notes.is_empty() {
+            html.push_str(&format!(" NOTES=\"{}\"", escape_html(&b.notes)));
+        }
+        if let Some(icon) = host(&b.url).and_then(|h| icons.get(&h)) {
+            html.push_str(&format!(" ICON=\"{}\"", escape_html(icon)));
+        }
+        html.push_str(&format!(">{}</A>\n", escape_html(&b.title)));
+
+        if !b.description.is_empty() {
+            html.push_str(&format!("    <DD>{}\n", escape_html(&b.description)));
+        }
+    }
+
+    html.push_str("</DL><p>\n");
+    html
+}
+
+pub fn bookmarks_to_json(bookmarks: &[Bookmark]) -> String {
+    serde_json::to_string_pretty(bookmarks).unwrap()
+}
+
+pub fn data_uri(mime: &str, data: &[u8]) -> String {
+    format!("data:{};base64,{}", mime, base64::encode(data))
+}
+
+// Type and contents of a base64 data URI.
+pub fn parse_data_uri(uri: &str) -> Option<(String, Vec<u8>)> {
+    let rest = uri.strip_prefix("data:")?;
+    let (mime, data) = rest.split_at(rest.find(";base64,")?);
+    let data = base64::decode(&data[";base64,".len()..]).ok()?;
+
+    Some((mime.to_string(), data))
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\n', "&#10;")
 }
 
 #[cfg(test)]
@@ -54,6 +190,7 @@ mod tests {
     use super::*;
     use std::env;
     use std::path::Path;
+    use tempfile;
 
     #[test]
     fn test_get_bookmarks_from_html() {
@@ -63,18 +200,21 @@ mod tests {
                 title: "GitHub".to_string(),
                 url: "https://github.com".to_string(),
                 tags: vec!["Git".to_string(), "Hosting Service".to_string(), "test".to_string()],
+                ..Default::default()
             }),
             (Bookmark {
                 id: 2,
                 title: "Google".to_string(),
                 url: "https://google.com".to_string(),
                 tags: vec!["test".to_string()],
+                ..Default::default()
             }),
             (Bookmark {
                 id: 3,
                 title: "Example Domain".to_string(),
                 url: "https://example.com".to_string(),
                 tags: vec!["Test".to_string()],
+                ..Default::default()
             }),
         ];
 
@@ -90,4 +230,55 @@ mod tests {
                        (&bookmark.title, &bookmark.url, &bookmark.tags));
         }
     }
+
+    fn export_data() -> Vec<Bookmark> {
+        let mut github = Bookmark::new(
+            1, "GitHub <Home>".to_string(), "https://github.com/?a=1&b=\"2\"".to_string(),
+            vec!["git".to_string(), "hosting service".to_string()]
+        );
+        github.description = "Development platform".to_string();
+        github.notes = "Where bkm\nlives".to_string();
+
+        let google = Bookmark::new(
+            2, "Google".to_string(), "https://google.com".to_string(), vec![]
+        );
+
+        vec![github, google]
+    }
+
+    #[test]
+    fn test_bookmarks_to_html() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bookmarks.html");
+        let mut icons: HashMap<String, String> = HashMap::new();
+        icons.insert("github.com".to_string(), data_uri("image/png", b"\x89PNG"));
+        fs::write(&path, bookmarks_to_html(&export_data(), &icons)).unwrap();
+
+        let icons = get_icons_from_html(path.clone());
+        assert_eq!(icons.len(), 1);
+        assert_eq!(parse_data_uri(&icons["github.com"]),
+                   Some(("image/png".to_string(), b"\x89PNG".to_vec())));
+        assert_eq!(parse_data_uri("data:text/plain,abc"), None);
+
+        let bookmarks = get_bookmarks_from_html(path);
+        assert_eq!(bookmarks.len(), 2);
+        for (expected, bookmark) in export_data().iter().zip(bookmarks.iter()) {
+            assert_eq!((&expected.title, &expected.url, &expected.tags),
+                       (&bookmark.title, &bookmark.url, &bookmark.tags));
+            assert_eq!((&expected.description, &expected.notes),
+                       (&bookmark.description, &bookmark.notes));
+        }
+    }
+
+    #[test]
+    fn test_bookmarks_to_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bookmarks.json");
+        fs::write(&path, bookmarks_to_json(&export_data())).unwrap();
+
+        let bookmarks = get_bookmarks_from_json(path);
+        assert_eq!(bookmarks.len(), 2);
+        assert_eq!(bookmarks[0].notes, "Where bkm\nlives");
+        assert_eq!(bookmarks[1].description, "");
+    }
 }
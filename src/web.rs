@@ -0,0 +1,481 @@
+// Web interface of `bkm serve` for browsing, searching, adding and editing
+// bookmarks, with a bookmarklet saving the page open in the browser.
+
+use reqwest::Url;
+
+use api::{change_bookmark, create_bookmark, error_status, remove_bookmark, BookmarkInput};
+use bookmark::Bookmark;
+use database::DB;
+use http::{Request, Response};
+use query::{ListOptions, Query, SortKey};
+use utils::escape_html;
+
+const COOKIE: &str = "bkm_token";
+const PAGE_SIZE: i64 = 100;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 0; color: #222; }
+header { background: #333; padding: 0.5em 1em; display: flex; gap: 1em; align-items: center; }
+header a { color: #fff; text-decoration: none; font-weight: bold; }
+header form { flex: 1; }
+header input[type=search] { width: 100%; max-width: 30em; padding: 0.3em; }
+main { display: flex; gap: 2em; padding: 1em; }
+nav { min-width: 12em; }
+nav a, .tags a { color: #555; }
+section { flex: 1; }
+article { margin-bottom: 1em; }
+article .url { color: #080; font-size: 0.9em; word-break: break-all; }
+article form { display: inline; }
+label { display: block; margin-top: 0.5em; }
+input[type=text], input[type=url], input[type=password], textarea { width: 100%; max-width: 40em; }
+.error { color: #b00; }
+";
+
+// Whether req asks for a page of the web interface rather than the API.
+pub fn is_page(req: &Request) -> bool {
+    match req.segments().first() {
+        None => true,
+        Some(&first) => ["add", "edit", "delete", "login", "logout"].contains(&first),
+    }
+}
+
+pub fn handle(db: &DB, req: &Request, token: &str) -> Response {
+    let segments = req.segments();
+
+    if segments.first() == Some(&"login") {
+        return match req.method.as_str() {
+            "POST" => login(req, token),
+            _ => login_page(req.param("next").unwrap_or("/"), None),
+        };
+    }
+
+    if req.cookie(COOKIE) != Some(token) {
+        if req.method != "GET" {
+            return Response::html(401, page("Unauthorized", "<p class=\"error\">Log in again.</p>"));
+        }
+        let next = if req.query.is_empty() {
+            req.path.clone()
+        } else {
+            format!("{}?{}", req.path, query_string(&req.query))
+        };
+        return Response::redirect(&format!("/login?{}", query_string(&[("next", &next)])));
+    }
+
+    match (req.method.as_str(), segments.as_slice()) {
+        ("GET", []) => index(db, req),
+        ("GET", ["add"]) => add_page(req),
+        ("POST", ["add"]) => add(db, req),
+        ("GET", ["edit", id]) => with_id(id, |id| edit_page(db, id)),
+        ("POST", ["edit", id]) => with_id(id, |id| edit(db, id, req)),
+        ("POST", ["delete", id]) => with_id(id, |id| delete(db, id)),
+        ("POST", ["logout"]) => {
+            Response::redirect("/login").header("Set-Cookie", &cookie(""))
+        },
+        _ => Response::html(404, page("Not found", "<p class=\"error\">Not found.</p>")),
+    }
+}
+
+fn with_id<F>(id: &str, f: F) -> Response
+    where F: FnOnce(i64) -> Response
+{
+    match id.parse() {
+        Ok(id) => f(id),
+        Err(_) => Response::html(404, page("Not found", "<p class=\"error\">Not found.</p>")),
+    }
+}
+
+// Lax cookies are sent when following the bookmarklet from another site,
+// but not with forms posted from one.
+fn cookie(token: &str) -> String {
+    format!("{}={}; Path=/; HttpOnly; SameSite=Lax", COOKIE, token)
+}
+
+fn query_string<K: AsRef<str>, V: AsRef<str>>(pairs: &[(K, V)]) -> String {
+    let mut url = Url::parse("http://localhost").unwrap();
+    url.query_pairs_mut().extend_pairs(pairs.iter().map(|(k, v)| (k.as_ref(), v.as_ref())));
+    url.query().unwrap_or("").to_string()
+}
+
+fn form_value(form: &[(String, String)], name: &str) -> Option<String> {
+    form.iter().find(|&(k, _)| k == name).map(|(_, v)| v.clone())
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!("<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+<title>{} - bkm</title>
+<style>{}</style>
+</head>
+<body>
+<header>
+<a href=\"/\">bkm</a>
+<form action=\"/\"><input type=\"search\" name=\"q\" placeholder=\"Search\"></form>
+<a href=\"/add\">Add</a>
+</header>
+{}
+</body>
+</html>
+", escape_html(title), STYLE, body)
+}
+
+fn login_page(next: &str, error: Option<&str>) -> Response {
+    let error = error.map(|e| format!("<p class=\"error\">{}</p>", e)).unwrap_or_default();
+    let body = format!("<main><section>
+<h1>Log in</h1>
+{}
+<form method=\"post\" action=\"/login\">
+<input type=\"hidden\" name=\"next\" value=\"{}\">
+<label>Token (from ~/.bkm/token) <input type=\"password\" name=\"token\" autofocus></label>
+<p><button>Log in</button></p>
+</form>
+</section></main>", error, escape_html(next));
+
+    Response::html(if error.is_empty() { 200 } else { 401 }, page("Log in", &body))
+}
+
+fn login(req: &Request, token: &str) -> Response {
+    let form = req.form();
+    // Only go back to pages of this site.
+    let next = form_value(&form, "next")
+        .filter(|n| n.starts_with('/') && !n.starts_with("//"))
+        .unwrap_or_else(|| "/".to_string());
+
+    if form_value(&form, "token").as_deref().map(str::trim) != Some(token) {
+        return login_page(&next, Some("Invalid token"));
+    }
+
+    Response::redirect(&next).header("Set-Cookie", &cookie(token))
+}
+
+// Bookmarks matching the search or tag, with every tag on the side.
+fn index(db: &DB, req: &Request) -> Response {
+    let mut query = Query::parse(req.param("q").unwrap_or(""));
+    if let Some(tag) = req.param("tag") {
+        query.tags.push(tag.to_string());
+    }
+    let page_number: i64 = req.param("page").and_then(|p| p.parse().ok()).unwrap_or(0).max(0);
+    let options = ListOptions::new(SortKey::Id, true, Some(PAGE_SIZE + 1), Some(page_number * PAGE_SIZE));
+    let mut bookmarks = db.search_query(&query, &options);
+    let more = bookmarks.len() as i64 > PAGE_SIZE;
+    bookmarks.truncate(PAGE_SIZE as usize);
+
+    let tags: String = db.get_tag_counts().iter()
+        .filter(|&(_, count)| *count > 0)
+        .map(|(name, count)| format!("<li><a href=\"/?{}\">{}</a> ({})</li>\n",
+                                     escape_html(&query_string(&[("tag", name)])), escape_html(name), count))
+        .collect();
+
+    let list: String = if bookmarks.is_empty() {
+        "<p>No matching any bookmark.</p>".to_string()
+    } else {
+        bookmarks.iter().map(bookmark_html).collect()
+    };
+
+    let mut pages = String::new();
+    if page_number > 0 || more {
+        let link = |n: i64, label: &str| {
+            let mut params: Vec<(String, String)> = req.query.iter().filter(|&(k, _)| k != "page").cloned().collect();
+            params.push(("page".to_string(), n.to_string()));
+            format!("<a href=\"/?{}\">{}</a> ", escape_html(&query_string(&params)), label)
+        };
+        if page_number > 0 {
+            pages.push_str(&link(page_number - 1, "Previous"));
+        }
+        if more {
+            pages.push_str(&link(page_number + 1, "Next"));
+        }
+    }
+
+    let host = req.header("Host").unwrap_or("127.0.0.1:8990");
+    let bookmarklet = format!(
+        "javascript:location.href='http://{}/add?url='+encodeURIComponent(location.href)\
+         +'&title='+encodeURIComponent(document.title)", host
+    );
+
+    let body = format!("<main>
+<nav>
+<h3>Tags</h3>
+<ul>
+{}</ul>
+<p>Drag <a href=\"{}\">Save to bkm</a> to the bookmarks bar to save pages in one click.</p>
+<form method=\"post\" action=\"/logout\"><button>Log out</button></form>
+</nav>
+<section>
+{}
+<p>{}</p>
+</section>
+</main>", tags, escape_html(&bookmarklet), list, pages);
+
+    Response::html(200, page("Bookmarks", &body))
+}
+
+fn bookmark_html(b: &Bookmark) -> String {
+    let tags: String = b.tags.iter()
+        .map(|t| format!("<a href=\"/?{}\">{}</a> ", escape_html(&query_string(&[("tag", t)])), escape_html(t)))
+        .collect();
+    let description = if b.description.is_empty() {
+        String::new()
+    } else {
+        format!("<div>{}</div>", escape_html(&b.description))
+    };
+    // Other schemes, like javascript:, would run in the page when clicked.
+    let link = match Url::parse(&b.url) {
+        Ok(ref url) if url.scheme() == "http" || url.scheme() == "https" => format!(
+            "<a href=\"{}\" target=\"_blank\" rel=\"noopener noreferrer\">{}</a>",
+            escape_html(&b.url), escape_html(&b.title)),
+        _ => format!("<span>{}</span>", escape_html(&b.title)),
+    };
+
+    format!("<article>
+{link}
+<div class=\"url\">{url}</div>
+{description}
+<div class=\"tags\">{tags}</div>
+<a href=\"/edit/{id}\">Edit</a>
+<form method=\"post\" action=\"/delete/{id}\" onsubmit=\"return confirm('Delete this bookmark?')\"><button>Delete</button></form>
+</article>
+", link = link, url = escape_html(&b.url), description = description, tags = tags, id = b.id)
+}
+
+// Form of a bookmark, posted to action.
+fn bookmark_form(action: &str, b: &Bookmark, back: Option<&str>, error: Option<&str>) -> String {
+    let error = error.map(|e| format!("<p class=\"error\">{}</p>", escape_html(e.trim_start_matches("Error: "))))
+        .unwrap_or_default();
+    let back = back.map(|b| format!("<input type=\"hidden\" name=\"back\" value=\"{}\">", escape_html(b)))
+        .unwrap_or_default();
+
+    format!("<main><section>
+{error}
+<form method=\"post\" action=\"{action}\">
+{back}
+<label>URL <input type=\"url\" name=\"url\" value=\"{url}\" required></label>
+<label>Title <input type=\"text\" name=\"title\" value=\"{title}\"></label>
+<label>Tags (comma separated) <input type=\"text\" name=\"tags\" value=\"{tags}\" autofocus></label>
+<label>Description <textarea name=\"description\" rows=\"2\">{description}</textarea></label>
+<label>Notes <textarea name=\"notes\" rows=\"4\">{notes}</textarea></label>
+<p><button>Save</button></p>
+</form>
+</section></main>", error = error, action = action, back = back, url = escape_html(&b.url),
+            title = escape_html(&b.title), tags = escape_html(&b.tags.join(", ")),
+            description = escape_html(&b.description), notes = escape_html(&b.notes))
+}
+
+fn form_input(req: &Request) -> BookmarkInput {
+    let form = req.form();
+    let tags = form_value(&form, "tags").unwrap_or_default();
+
+    BookmarkInput {
+        // An emptied title keeps the saved one.
+        title: form_value(&form, "title").filter(|t| !t.trim().is_empty()),
+        url: form_value(&form, "url"),
+        tags: Some(tags.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect()),
+        description: form_value(&form, "description"),
+        notes: form_value(&form, "notes"),
+    }
+}
+
+fn input_bookmark(input: &BookmarkInput) -> Bookmark {
+    let mut bookmark = Bookmark::new(
+        0,
+        input.title.clone().unwrap_or_default(),
+        input.url.clone().unwrap_or_default(),
+        input.tags.clone().unwrap_or_default(),
+    );
+    bookmark.description = input.description.clone().unwrap_or_default();
+    bookmark.notes = input.notes.clone().unwrap_or_default();
+    bookmark
+}
+
+// GET /add?url=&title=, the bookmarklet opens it with the page to save.
+fn add_page(req: &Request) -> Response {
+    let input = BookmarkInput {
+        title: req.param("title").map(|t| t.to_string()),
+        url: req.param("url").map(|u| u.to_string()),
+        ..BookmarkInput::default()
+    };
+    // Go back to the saved page after saving it from the bookmarklet.
+    let back = input.url.as_deref();
+
+    Response::html(200, page("Add", &bookmark_form("/add", &input_bookmark(&input), back, None)))
+}
+
+fn add(db: &DB, req: &Request) -> Response {
+    let back = form_value(&req.form(), "back").filter(|b| b.starts_with("http://") || b.starts_with("https://"));
+    let input = form_input(req);
+    let bookmark = input_bookmark(&input);
+
+    match create_bookmark(db, input) {
+        Ok(_) => Response::redirect(back.as_deref().unwrap_or("/")),
+        Err(e) => {
+            let form = bookmark_form("/add", &bookmark, back.as_deref(), Some(e));
+            Response::html(error_status(e), page("Add", &form))
+        },
+    }
+}
+
+fn edit_page(db: &DB, id: i64) -> Response {
+    match db.get_bookmark_by_id(id) {
+        Ok(bookmark) => {
+            let form = bookmark_form(&format!("/edit/{}", id), &bookmark, None, None);
+            Response::html(200, page("Edit", &form))
+        },
+        Err(_) => Response::html(404, page("Not found", "<p class=\"error\">Not found.</p>")),
+    }
+}
+
+fn edit(db: &DB, id: i64, req: &Request) -> Response {
+    let input = form_input(req);
+    let bookmark = input_bookmark(&input);
+
+    match change_bookmark(db, id, input) {
+        Ok(()) => Response::redirect("/"),
+        Err(e) if error_status(e) == 404 => {
+            Response::html(404, page("Not found", "<p class=\"error\">Not found.</p>"))
+        },
+        Err(e) => {
+            let form = bookmark_form(&format!("/edit/{}", id), &bookmark, None, Some(e));
+            Response::html(error_status(e), page("Edit", &form))
+        },
+    }
+}
+
+fn delete(db: &DB, id: i64) -> Response {
+    match remove_bookmark(db, id) {
+        Ok(()) => Response::redirect("/"),
+        Err(_) => Response::html(404, page("Not found", "<p class=\"error\">Not found.</p>")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::serve;
+    use reqwest::{Client, RedirectPolicy, StatusCode};
+    use reqwest::header::{COOKIE as COOKIE_HEADER, LOCATION, SET_COOKIE};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    use api::Api;
+
+    const TOKEN: &str = "secret";
+
+    fn start() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let api = Arc::new(Api::new(DB::open_in_memory(), TOKEN));
+        thread::spawn(move || serve(listener, move |req| api.handle(req)));
+
+        format!("http://{}", addr)
+    }
+
+    fn client() -> Client {
+        Client::builder().redirect(RedirectPolicy::none()).build().unwrap()
+    }
+
+    fn session() -> String {
+        format!("{}={}", COOKIE, TOKEN)
+    }
+
+    #[test]
+    fn test_login() {
+        let base = start();
+
+        let res = client().get(&format!("{}/add?url=https%3A%2F%2Fgithub.com", base)).send().unwrap();
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let location = res.headers()[LOCATION].to_str().unwrap().to_string();
+        assert!(location.starts_with("/login?next="));
+
+        let mut res = client().post(&format!("{}/login", base))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("token=wrong&next=%2F").send().unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert!(res.text().unwrap().contains("Invalid token"));
+
+        let res = client().post(&format!("{}/login", base))
+            .body("token=secret&next=%2Fadd%3Furl%3Dx").send().unwrap();
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert_eq!(res.headers()[LOCATION], "/add?url=x");
+        assert!(res.headers()[SET_COOKIE].to_str().unwrap().starts_with(&session()));
+
+        let res = client().post(&format!("{}/login", base))
+            .body("token=secret&next=%2F%2Fevil.example.com").send().unwrap();
+        assert_eq!(res.headers()[LOCATION], "/");
+
+        // The API still wants its bearer token.
+        let res = client().get(&format!("{}/bookmarks", base))
+            .header(COOKIE_HEADER, session()).send().unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_bookmark_html() {
+        let html = bookmark_html(&Bookmark::new(1, "GitHub".to_string(), "https://github.com/".to_string(), vec![]));
+        assert!(html.contains("<a href=\"https://github.com/\" target=\"_blank\""));
+
+        let html = bookmark_html(&Bookmark::new(2, "Run".to_string(), "javascript:alert(1)".to_string(), vec![]));
+        assert!(html.contains("<span>Run</span>"));
+        assert!(!html.contains("href=\"javascript:"));
+        assert!(html.contains("<div class=\"url\">javascript:alert(1)</div>"));
+    }
+
+    #[test]
+    fn test_pages() {
+        let base = start();
+        let get = |path: &str| {
+            let mut res = client().get(&format!("{}{}", base, path))
+                .header(COOKIE_HEADER, session()).send().unwrap();
+            (res.status(), res.text().unwrap())
+        };
+        let post = |path: &str, body: &str| {
+            client().post(&format!("{}{}", base, path))
+                .header(COOKIE_HEADER, session()).body(body.to_string()).send().unwrap()
+        };
+
+        let (status, html) = get("/add?url=https%3A%2F%2Fgithub.com%2F&title=GitHub+%3Cb%3E");
+        assert_eq!(status, StatusCode::OK);
+        assert!(html.contains("value=\"GitHub &lt;b&gt;\""));
+        assert!(html.contains("name=\"back\" value=\"https://github.com/\""));
+
+        let res = post("/add", "url=https%3A%2F%2Fgithub.com%2F&title=GitHub&tags=git%2C+hosting+service\
+                                &back=https%3A%2F%2Fgithub.com%2F");
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert_eq!(res.headers()[LOCATION], "https://github.com/");
+
+        let res = post("/add", "url=http%3A%2F%2Fgithub.com&title=Again");
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let res = post("/add", "url=https%3A%2F%2Fwww.rust-lang.org&tags=rust");
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert_eq!(res.headers()[LOCATION], "/");
+
+        let (_, html) = get("/");
+        assert!(html.contains(">GitHub</a>"));
+        assert!(html.contains(">www.rust-lang.org</a>"));
+        assert!(html.contains("<a href=\"/?tag=hosting+service\">hosting service</a> (1)"));
+        assert!(html.contains("javascript:location.href="));
+
+        let (_, html) = get("/?tag=rust");
+        assert!(!html.contains(">GitHub</a>"));
+        let (_, html) = get("/?q=git");
+        assert!(html.contains(">GitHub</a>") && !html.contains(">www.rust-lang.org</a>"));
+
+        let (status, html) = get("/edit/1");
+        assert_eq!(status, StatusCode::OK);
+        assert!(html.contains("value=\"git, hosting service\""));
+        let res = post("/edit/1", "url=https%3A%2F%2Fgithub.com%2F&title=GitHub&tags=git&notes=Read+me");
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let (_, html) = get("/edit/1");
+        assert!(html.contains(">Read me</textarea>"));
+        assert_eq!(post("/edit/1", "url=+&title=GitHub").status(), StatusCode::BAD_REQUEST);
+        assert_eq!(post("/edit/2", "url=https%3A%2F%2Fgithub.com%2F").status(), StatusCode::CONFLICT);
+        assert_eq!(get("/edit/9").0, StatusCode::NOT_FOUND);
+
+        assert_eq!(post("/delete/1", "").status(), StatusCode::SEE_OTHER);
+        assert_eq!(post("/delete/1", "").status(), StatusCode::NOT_FOUND);
+        let (_, html) = get("/");
+        assert!(!html.contains(">GitHub</a>"));
+    }
+}